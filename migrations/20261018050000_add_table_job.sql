@@ -0,0 +1,15 @@
+-- Add up migration script here
+CREATE TABLE job (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    gallery_id INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    force BOOLEAN NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    run_after DATETIME NOT NULL,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+CREATE INDEX job_gallery_id_idx ON job (gallery_id);
+CREATE INDEX job_status_idx ON job (status, run_after);
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE image ADD COLUMN host TEXT NOT NULL DEFAULT 'catbox';
+UPDATE image SET host = 'telegraph' WHERE url LIKE '/file/%';
@@ -0,0 +1,10 @@
+-- Add up migration script here
+ALTER TABLE image ADD COLUMN phash INTEGER;
+ALTER TABLE image ADD COLUMN ad BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- 将 64 位的感知哈希拆成 4 段分别建立索引
+-- 两个哈希的汉明距离不超过 3 时，至少有一段是完全相同的，因此可以用这些索引快速找出候选图片
+CREATE INDEX image_phash_0_idx ON image ((phash >> 48) & 65535);
+CREATE INDEX image_phash_1_idx ON image ((phash >> 32) & 65535);
+CREATE INDEX image_phash_2_idx ON image ((phash >> 16) & 65535);
+CREATE INDEX image_phash_3_idx ON image (phash & 65535);
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- 过长的画廊会被拆分为多篇文章，parts 按顺序记录所有文章的 URL，url 仍然是第一篇文章
+ALTER TABLE telegraph ADD COLUMN parts TEXT NOT NULL DEFAULT '[]';
+UPDATE telegraph SET parts = json_array(url);
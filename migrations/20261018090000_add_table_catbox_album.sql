@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- 单个 catbox 专辑最多 500 个文件，超出时一个画廊会对应多个专辑，part 为专辑的序号
+CREATE TABLE catbox_album (
+    short TEXT PRIMARY KEY NOT NULL,
+    gallery_id INTEGER NOT NULL,
+    part INTEGER NOT NULL,
+    -- 按顺序排列的专辑中的文件名，JSON 数组
+    file_list TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+CREATE UNIQUE INDEX catbox_album_gallery_id_idx ON catbox_album (gallery_id, part);
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE job ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- 旧任务的频道为空，启动时会被分配给第一个频道
+ALTER TABLE job ADD COLUMN channel_id TEXT NOT NULL DEFAULT '';
+CREATE INDEX job_channel_idx ON job (channel_id, status);
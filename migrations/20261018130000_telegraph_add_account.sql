@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- 发布文章的 telegraph 账号名称，只有发布文章的账号才能编辑文章，为空时表示账号池之前的默认账号
+ALTER TABLE telegraph ADD COLUMN account TEXT NOT NULL DEFAULT '';
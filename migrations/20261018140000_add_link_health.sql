@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- 后台检查链接的结果，status 为 unchecked、alive、dead 或 error，last_checked 为空时表示还没有检查过
+ALTER TABLE telegraph ADD COLUMN status TEXT NOT NULL DEFAULT 'unchecked';
+ALTER TABLE telegraph ADD COLUMN last_checked DATETIME;
+ALTER TABLE image ADD COLUMN status TEXT NOT NULL DEFAULT 'unchecked';
+ALTER TABLE image ADD COLUMN last_checked DATETIME;
+CREATE INDEX telegraph_last_checked ON telegraph (last_checked);
+CREATE INDEX image_last_checked ON image (last_checked);
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- 同一个画廊在同一个频道最多只有一个未完成的任务，之前并发添加的重复任务只保留最早的一个
+UPDATE job SET status = 'failed', last_error = '重复的任务'
+WHERE status NOT IN ('done', 'failed', 'skipped') AND id NOT IN (
+    SELECT MIN(id) FROM job WHERE status NOT IN ('done', 'failed', 'skipped')
+    GROUP BY gallery_id, channel_id
+);
+CREATE UNIQUE INDEX job_active_idx ON job (gallery_id, channel_id)
+WHERE status NOT IN ('done', 'failed', 'skipped');
@@ -6,6 +6,7 @@ use chrono::Timelike;
 use clap::Parser;
 use exloli_cat::config::Config;
 use exloli_cat::ehentai::EhClient;
+use exloli_cat::utils::ratelimit::{Backoff, RateLimiter};
 use futures::StreamExt;
 use glob::glob;
 use tracing::{info, warn};
@@ -36,7 +37,10 @@ async fn main() -> Result<()> {
         .try_init()
         .unwrap();
 
-    let ehentai = EhClient::new(&config.exhentai.cookie).await?;
+    let ehentai = EhClient::new(&config.exhentai.cookie)
+        .await?
+        .with_rate_limit(RateLimiter::new(&config.limit.exhentai))
+        .with_backoff(Backoff::new(&config.limit));
     let params = [("favcat", args.favcat)];
     let stream = ehentai.page_iter("https://exhentai.org/favorites.php", &params);
     tokio::pin!(stream);
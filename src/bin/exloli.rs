@@ -1,21 +1,62 @@
 use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
 use exloli_cat::bot::start_dispatcher;
-use exloli_cat::config::{Config, CHANNEL_ID};
+use exloli_cat::config::Config;
+use exloli_cat::database::{self, Database, OnConflict};
 use exloli_cat::ehentai::EhClient;
+use exloli_cat::site;
+use exloli_cat::utils::ratelimit::{Backoff, RateLimiter};
 use exloli_cat::tags::EhTagTransDB;
 use exloli_cat::uploader::ExloliUploader;
 use teloxide::prelude::*;
 use teloxide::types::ParseMode;
 
+#[derive(Parser)]
+struct Args {
+    /// 配置文件路径
+    #[clap(short, long, default_value = "./config.toml")]
+    config: String,
+    /// 只模拟扫描并输出报告，不写入数据库，也不调用 Telegram、Telegraph 和 Catbox
+    #[clap(long)]
+    dry_run: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 将数据库导出为 JSON Lines 格式的归档
+    Export {
+        /// 归档文件路径
+        path: PathBuf,
+    },
+    /// 将归档导入数据库，数据库不存在时会自动创建
+    Import {
+        /// 归档文件路径
+        path: PathBuf,
+        /// 数据库中已经存在相同记录时的处理方式
+        #[clap(long, value_enum, default_value = "abort")]
+        on_conflict: OnConflict,
+    },
+    /// 根据数据库生成静态的画廊目录网站
+    Site {
+        /// 输出目录
+        output: PathBuf,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = Config::new("./config.toml")?;
-    CHANNEL_ID.set(config.telegram.channel_id.to_string()).unwrap();
+    let args = Args::parse();
+
+    let mut config = Config::new(&args.config)?;
+    config.dry_run |= args.dry_run;
 
-    // NOTE: 全局数据库连接需要用这个变量初始化
-    env::set_var("DATABASE_URL", &config.database_url);
     env::set_var("RUST_LOG", &config.log_level);
 
     tracing_subscriber::FmtSubscriber::builder()
@@ -23,9 +64,41 @@ async fn main() -> Result<()> {
         .try_init()
         .unwrap();
 
+    // 模拟运行时数据库以只读方式打开，无法写入导入的记录
+    if config.dry_run && matches!(args.command, Some(Command::Import { .. })) {
+        bail!("模拟运行时不能导入归档，请去掉 --dry-run 或者配置文件中的 dry_run");
+    }
+
     // 初始化需要的客户端
+    let db = if config.dry_run {
+        Database::connect_read_only(&config.database_url).await?
+    } else {
+        Database::connect(&config.database_url).await?
+    };
+    match args.command {
+        Some(Command::Export { path }) => {
+            let stats = database::export(&db, BufWriter::new(File::create(path)?)).await?;
+            print!("{}", stats);
+            return Ok(());
+        }
+        Some(Command::Import { path, on_conflict }) => {
+            let reader = BufReader::new(File::open(path)?);
+            let stats = database::import(&db, reader, on_conflict).await?;
+            print!("{}", stats);
+            return Ok(());
+        }
+        _ => {}
+    }
     let trans = EhTagTransDB::new(&config.exhentai.trans_file);
-    let ehentai = EhClient::new(&config.exhentai.cookie).await?;
+    if let Some(Command::Site { output }) = args.command {
+        let pages = site::generate(&db, &trans, &output).await?;
+        println!("生成了 {} 个页面", pages);
+        return Ok(());
+    }
+    let ehentai = EhClient::new(&config.exhentai.cookie)
+        .await?
+        .with_rate_limit(RateLimiter::new(&config.limit.exhentai))
+        .with_backoff(Backoff::new(&config.limit));
     let bot = Bot::new(&config.telegram.token)
         .throttle(Default::default())
         .parse_mode(ParseMode::Html)
@@ -35,7 +108,14 @@ async fn main() -> Result<()> {
     let userhash = config.catbox.userhash.clone(); // 从配置文件中获取 userhash
 
     // 创建 ExloliUploader，并传递 userhash
-    let uploader = ExloliUploader::new(config.clone(), ehentai.clone(), bot.clone(), trans.clone(), userhash.clone()).await?;
+    let uploader = ExloliUploader::new(config.clone(), db.clone(), ehentai.clone(), bot.clone(), trans.clone(), userhash.clone()).await?;
+
+    if config.dry_run {
+        for report in uploader.dry_run().await? {
+            println!("{}", report);
+        }
+        return Ok(());
+    }
 
     // 启动任务
     let t1 = {
@@ -45,7 +125,7 @@ async fn main() -> Result<()> {
 
     let t2 = {
         let trans = trans.clone();
-        tokio::spawn(async move { start_dispatcher(config, uploader, bot, trans).await })
+        tokio::spawn(async move { start_dispatcher(config, db, uploader, bot, trans).await })
     };
 
     let t3 = tokio::spawn(async move { trans.start().await });
@@ -8,6 +8,14 @@ use crate::ehentai::EhGalleryUrl;
 pub enum AdminCommand {
     #[command(description = "根据 E 站 URL 上传一个指定画廊，如果已存在，则重新上传")]
     Upload(EhGalleryUrl),
+    #[command(description = "取消一个画廊的上传")]
+    Cancel(EhGalleryUrl),
+    #[command(description = "查看上传队列中的任务")]
+    Jobs,
+    #[command(description = "查看发布队列")]
+    Queue,
+    #[command(description = "将画廊移动到发布队列的第 $2 位", parse_with = "split")]
+    Move(EhGalleryUrl, usize),
     #[command(description = "删除所回复的画廊")]
     Delete,
     #[command(description = "完全删除所回复的画廊，会导致重新上传")]
@@ -15,8 +23,12 @@ pub enum AdminCommand {
     // TODO: 该功能需要移除
     #[command(description = "将 80 分以上的本子中，没有被补档的重新上传")]
     ReUpload,
-    #[command(description = "检测并补档 80 分以上或最近两个月的本子的预览")]
+    #[command(description = "让后台立即重新检查所有预览和图片的链接")]
     ReCheck,
+    #[command(description = "查看预览和图片链接的检查结果")]
+    Health,
+    #[command(description = "重新读取消息模板，并重新渲染 80 分以上或最近两个月的本子的消息")]
+    ReRender,
 }
 
 #[derive(BotCommands, Clone, PartialEq, Debug)]
@@ -8,11 +8,13 @@ use super::utils::{ChallengeLocker, ChallengeProvider, RateLimiter};
 use super::Bot;
 use crate::bot::scheduler::Scheduler;
 use crate::config::Config;
+use crate::database::Database;
 use crate::tags::EhTagTransDB;
 use crate::uploader::ExloliUploader;
 
 pub async fn start_dispatcher(
     config: Config,
+    db: Database,
     ehentai: ExloliUploader,
     bot: Bot,
     trans: EhTagTransDB,
@@ -36,7 +38,7 @@ pub async fn start_dispatcher(
 
     let challenge_locker = ChallengeLocker::new();
 
-    let challenge_provider = ChallengeProvider::new();
+    let challenge_provider = ChallengeProvider::new(db.clone());
 
     let scheduler = Scheduler::new(bot.clone());
 
@@ -44,6 +46,7 @@ pub async fn start_dispatcher(
         .dependencies(dptree::deps![
             ehentai,
             config,
+            db,
             rate_limiter,
             trans,
             challenge_locker,
@@ -2,6 +2,7 @@ use teloxide::dispatching::DpHandlerDescription;
 use teloxide::prelude::*;
 use teloxide::types::{ChatKind, ChatMemberKind, Recipient};
 
+use super::handlers::channel_of;
 use super::utils::CallbackData;
 use super::Bot;
 use crate::config::Config;
@@ -10,13 +11,14 @@ pub fn filter_admin_msg<Output>() -> Handler<'static, DependencyMap, Output, DpH
 where
     Output: Send + Sync + 'static,
 {
+    // 只有指令所针对的频道的讨论组管理员可以使用管理员指令
     dptree::filter_async(|message: Message, bot: Bot, cfg: Config| async move {
-        bot.get_chat_member(cfg.telegram.group_id, message.from().unwrap().id)
-            .await
-            .map(|member| {
-                matches!(member.kind, ChatMemberKind::Administrator(_) | ChatMemberKind::Owner(_))
-            })
-            .unwrap_or_default()
+        let Some(user) = message.from() else { return false };
+        let group_id = channel_of(&cfg, &message).group_id;
+        let member = bot.get_chat_member(group_id, user.id).await;
+        member.is_ok_and(|member| {
+            matches!(member.kind, ChatMemberKind::Administrator(_) | ChatMemberKind::Owner(_))
+        })
     })
 }
 
@@ -27,7 +29,7 @@ where
     dptree::filter(|message: Message, cfg: Config| {
         message.from().map(|u| u.id.0 == 777000).unwrap_or_default()
             && message.text().map(|s| s.contains("原始地址")).unwrap_or_default()
-            && cfg.telegram.group_id == message.chat.id
+            && cfg.channel_by_group(message.chat.id).is_some()
     })
 }
 
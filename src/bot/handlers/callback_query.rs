@@ -5,12 +5,12 @@ use teloxide::prelude::*;
 use teloxide::utils::html::{link, user_mention};
 use tracing::info;
 
-use super::utils::gallery_preview_url;
+use super::utils::{channel_of, gallery_preview_url};
 use crate::bot::handlers::{cmd_best_keyboard, cmd_best_text, poll_keyboard};
 use crate::bot::utils::{CallbackData, ChallengeLocker, RateLimiter};
 use crate::bot::Bot;
 use crate::config::Config;
-use crate::database::{ChallengeHistory, GalleryEntity, PollEntity, VoteEntity};
+use crate::database::{ChallengeHistory, Database, GalleryEntity, PollEntity, VoteEntity};
 use crate::ehentai::GalleryInfo;
 use crate::tags::EhTagTransDB;
 
@@ -24,6 +24,7 @@ pub fn callback_query_handler() -> Handler<'static, DependencyMap, Result<()>, D
 
 async fn callback_challenge(
     bot: Bot,
+    db: Database,
     query: CallbackQuery,
     trans: EhTagTransDB,
     locker: ChallengeLocker,
@@ -35,14 +36,21 @@ async fn callback_challenge(
 
     if let Some((gallery, page, answer)) = locker.get_challenge(id) {
         let success = answer == artist;
-        let gallery_entity = GalleryEntity::get(gallery).await?.context("找不到画廊")?;
-        let preview = gallery_preview_url(cfg.telegram.channel_id, gallery).await?;
-        let poll = PollEntity::get_by_gallery(gallery).await?.context("找不到投票")?;
-        ChallengeHistory::create(query.from.id.0 as i64, gallery, page, success, message.chat.id.0)
-            .await?;
+        let gallery_entity = GalleryEntity::get(&db, gallery).await?.context("找不到画廊")?;
+        let preview = gallery_preview_url(&db, channel_of(&cfg, &message), gallery).await?;
+        let poll = PollEntity::get_by_gallery(&db, gallery).await?.context("找不到投票")?;
+        ChallengeHistory::create(
+            &db,
+            query.from.id.0 as i64,
+            gallery,
+            page,
+            success,
+            message.chat.id.0,
+        )
+        .await?;
 
         let (stat_success, stat_total) =
-            ChallengeHistory::answer_stats(query.from.id.0 as i64, message.chat.id.0).await?;
+            ChallengeHistory::answer_stats(&db, query.from.id.0 as i64, message.chat.id.0).await?;
 
         let mention = user_mention(query.from.id.0 as i64, &query.from.full_name());
         let result = if success { "答对了！" } else { "答错了……" };
@@ -50,7 +58,7 @@ async fn callback_challenge(
         let url = gallery_entity.url().url();
         let preview = link(&preview, &gallery_entity.title_jp.unwrap_or(gallery_entity.title));
         let score = poll.score * 100.;
-        let rank = poll.rank().await? * 100.;
+        let rank = poll.rank(&db).await? * 100.;
 
         let text = format!(
             "{mention} {result}，答案是 {artist}（{answer}）\n回答情况：{stat_success}/{stat_total}\n地址：{url}\n预览：{preview}\n评分：{score:.2}（{rank:.2}%）",
@@ -63,6 +71,7 @@ async fn callback_challenge(
 
 async fn callback_vote_for_poll(
     bot: Bot,
+    db: Database,
     query: CallbackQuery,
     limiter: RateLimiter,
     (poll, option): (i64, i32),
@@ -77,13 +86,13 @@ async fn callback_vote_for_poll(
 
     info!("用户投票：[{}] {} = {}", query.from.id, poll, option);
 
-    let old_votes = PollEntity::get_vote(poll).await?;
-    VoteEntity::create(query.from.id.0, poll, option).await?;
-    let votes = PollEntity::get_vote(poll).await?;
+    let old_votes = PollEntity::get_vote(&db, poll).await?;
+    VoteEntity::create(&db, query.from.id.0, poll, option).await?;
+    let votes = PollEntity::get_vote(&db, poll).await?;
 
     // 投票没有变化时不要更新，不然会报错 MessageNotModified
     if old_votes != votes {
-        let score = PollEntity::update_score(poll).await?;
+        let score = PollEntity::update_score(&db, poll).await?;
         info!("更新分数：{} = {}", poll, score);
         let sum = votes.iter().sum::<i32>();
         let keyboard = poll_keyboard(poll, &votes);
@@ -101,6 +110,7 @@ async fn callback_vote_for_poll(
 
 async fn callback_change_page(
     bot: Bot,
+    db: Database,
     query: CallbackQuery,
     callback: CallbackData,
     cfg: Config,
@@ -110,15 +120,14 @@ async fn callback_change_page(
         CallbackData::NextPage(from, to, offset) => (from, to, offset + 1),
         _ => unreachable!(),
     };
-    let text = cmd_best_text(from, to, offset, cfg.telegram.channel_id).await?;
+    let Some(message) = query.message else { return Ok(()) };
+    let text = cmd_best_text(&db, from, to, offset, channel_of(&cfg, &message)).await?;
     let keyboard = cmd_best_keyboard(from, to, offset);
 
-    if let Some(message) = query.message {
-        bot.edit_message_text(message.chat.id, message.id, text)
-            .reply_markup(keyboard)
-            .disable_web_page_preview(true)
-            .await?;
-    }
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .reply_markup(keyboard)
+        .disable_web_page_preview(true)
+        .await?;
 
     Ok(())
 }
@@ -1,27 +1,49 @@
+use std::fmt::Write;
+use std::time::Duration;
+
 use anyhow::{Context, Result};
 use teloxide::dispatching::DpHandlerDescription;
 use teloxide::dptree::case;
 use teloxide::prelude::*;
 use teloxide::types::MessageId;
-use tracing::info;
+use teloxide::{ApiError, RequestError};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{info, warn};
 
 use crate::bot::command::AdminCommand;
 use crate::bot::filter::filter_admin_msg;
+use crate::bot::handlers::channel_of;
 use crate::bot::Bot;
-use crate::database::{GalleryEntity, MessageEntity};
+use crate::config::Config;
+use crate::database::{
+    Database, GalleryEntity, HealthSummary, JobEntity, JobStatus, MessageEntity, StatusCount,
+};
 use crate::ehentai::EhGalleryUrl;
-use crate::uploader::ExloliUploader;
+use crate::uploader::{ExloliUploader, Progress, UploadProgress};
 use crate::{reply_to, try_with_reply};
 
+/// 编辑上传进度消息的最小间隔
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+/// 跟踪上传进度的最长时间，超过后不再编辑进度消息
+const PROGRESS_TIMEOUT: Duration = Duration::from_secs(2 * 3600);
+
 pub fn admin_command_handler() -> Handler<'static, DependencyMap, Result<()>, DpHandlerDescription>
 {
     teloxide::filter_command::<AdminCommand, _>()
         .chain(filter_admin_msg())
         .branch(case![AdminCommand::Upload(gallery)].endpoint(cmd_upload))
+        .branch(case![AdminCommand::Cancel(gallery)].endpoint(cmd_cancel))
+        .branch(case![AdminCommand::Jobs].endpoint(cmd_jobs))
+        .branch(case![AdminCommand::Queue].endpoint(cmd_queue))
+        .branch(case![AdminCommand::Move(gallery, position)].endpoint(cmd_move))
         .branch(case![AdminCommand::Delete].endpoint(cmd_delete))
         .branch(case![AdminCommand::Erase].endpoint(cmd_delete))
         .branch(case![AdminCommand::ReCheck].endpoint(cmd_recheck))
+        .branch(case![AdminCommand::Health].endpoint(cmd_health))
         .branch(case![AdminCommand::ReUpload].endpoint(cmd_reupload))
+        .branch(case![AdminCommand::ReRender].endpoint(cmd_rerender))
 }
 
 // TODO: 该功能需要移除
@@ -33,38 +55,233 @@ async fn cmd_reupload(bot: Bot, msg: Message, uploader: ExloliUploader) -> Resul
 
 async fn cmd_recheck(bot: Bot, msg: Message, uploader: ExloliUploader) -> Result<()> {
     info!("{}: /recheck", msg.from().unwrap().id);
-    try_with_reply!(bot, msg, uploader.recheck(vec![]).await);
+    uploader.recheck().await?;
+    reply_to!(bot, msg, "已清空检查记录，所有链接会在后台陆续重新检查").await?;
+    Ok(())
+}
+
+async fn cmd_health(bot: Bot, db: Database, msg: Message) -> Result<()> {
+    info!("{}: /health", msg.from().unwrap().id);
+    let summary = HealthSummary::get(&db).await?;
+    let count = |c: StatusCount| {
+        format!("正常 {}，失效 {}，出错 {}，未检查 {}", c.alive, c.dead, c.error, c.unchecked)
+    };
+    let mut text = String::new();
+    writeln!(text, "文章：{}", count(summary.articles))?;
+    writeln!(text, "图片：{}", count(summary.images))?;
+    match summary.last_checked {
+        Some(time) => writeln!(text, "最近检查：{}", time.format("%Y-%m-%d %H:%M:%S"))?,
+        None => writeln!(text, "还没有进行过检查")?,
+    }
+    if !summary.broken_galleries.is_empty() {
+        let ids = summary.broken_galleries.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        writeln!(text, "包含失效图片的画廊：{}", ids.join(", "))?;
+    }
+    reply_to!(bot, msg, text).await?;
+    Ok(())
+}
+
+async fn cmd_rerender(bot: Bot, msg: Message, uploader: ExloliUploader) -> Result<()> {
+    info!("{}: /rerender", msg.from().unwrap().id);
+    try_with_reply!(bot, msg, uploader.rerender(vec![]).await);
     Ok(())
 }
 
 async fn cmd_upload(
     bot: Bot,
+    db: Database,
     msg: Message,
     uploader: ExloliUploader,
+    cfg: Config,
     gallery: EhGalleryUrl,
 ) -> Result<()> {
     info!("{}: /upload {}", msg.from().unwrap().id, gallery);
-    try_with_reply!(bot, msg, uploader.try_upload(&gallery, false).await);
+    // 先订阅再加入队列，避免错过任务开始时的事件
+    let rx = uploader.subscribe();
+    let Some(id) = uploader.enqueue(&gallery, channel_of(&cfg, &msg), false).await? else {
+        return Ok(());
+    };
+    let job = JobEntity::get(&db, id).await?.context("找不到任务")?;
+    if job.status != JobStatus::Queued {
+        // 已有的任务正在执行或者已经上传完毕，跟踪不到完整的进度
+        let text = format!("该画廊已有任务，任务 ID：{id}，状态：{:?}", job.status);
+        reply_to!(bot, msg, text).await?;
+        return Ok(());
+    }
+    let reply = reply_to!(bot, msg, format!("已加入上传队列，任务 ID：{id}")).await?;
+    tokio::spawn(async move { follow_progress(&bot, &db, &reply, &job, rx).await });
+    Ok(())
+}
+
+/// 根据上传进度编辑回复的消息，直到任务结束或者超过 PROGRESS_TIMEOUT
+///
+/// 进度事件非常频繁，为了避免触发 telegram 的限制，每隔 PROGRESS_INTERVAL 才会编辑一次，
+/// 编辑失败时只记录日志，不会中断对进度的跟踪
+async fn follow_progress(
+    bot: &Bot,
+    db: &Database,
+    reply: &Message,
+    job: &JobEntity,
+    mut rx: broadcast::Receiver<(i32, Progress)>,
+) {
+    let mut progress = UploadProgress::default();
+    let mut dirty = false;
+    let mut interval = time::interval(PROGRESS_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let timeout = time::sleep(PROGRESS_TIMEOUT);
+    tokio::pin!(timeout);
+    while !progress.is_finished() {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok((id, event)) if id == job.gallery_id => {
+                    progress.apply(event);
+                    dirty = true;
+                }
+                Ok(_) => {}
+                // 错过的事件中可能包含任务结束的事件，从数据库确认任务是否还在执行
+                Err(RecvError::Lagged(_)) => match JobEntity::get(db, job.id).await {
+                    Ok(Some(current)) => {
+                        if let Some(event) = finished_event(job, &current) {
+                            progress.apply(event);
+                            dirty = true;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(err) => warn!("读取任务状态失败：{}", err),
+                },
+                Err(RecvError::Closed) => break,
+            },
+            _ = interval.tick(), if dirty => {
+                edit_progress(bot, reply, &progress).await;
+                dirty = false;
+            }
+            _ = &mut timeout => {
+                warn!("跟踪上传进度超时：{}", job.url);
+                break;
+            }
+        }
+    }
+    edit_progress(bot, reply, &progress).await;
+}
+
+/// 根据任务当前的状态判断上传是否已经结束，用于错过了进度事件的情况
+fn finished_event(job: &JobEntity, current: &JobEntity) -> Option<Progress> {
+    let error = current.last_error.clone();
+    match current.status {
+        JobStatus::Ready | JobStatus::Done | JobStatus::Skipped => {
+            Some(Progress::Finished { error: None, retry: false })
+        }
+        JobStatus::Failed => Some(Progress::Finished { error, retry: false }),
+        // 失败后重新排队等待重试
+        JobStatus::Queued if current.attempts > job.attempts => {
+            Some(Progress::Finished { error, retry: true })
+        }
+        _ => None,
+    }
+}
+
+async fn edit_progress(bot: &Bot, reply: &Message, progress: &UploadProgress) {
+    match bot.edit_message_text(reply.chat.id, reply.id, progress.to_string()).await {
+        // 两次编辑之间只收到了不影响显示的事件
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+        Err(err) => warn!("更新上传进度失败：{}", err),
+    }
+}
+
+async fn cmd_cancel(
+    bot: Bot,
+    msg: Message,
+    uploader: ExloliUploader,
+    gallery: EhGalleryUrl,
+) -> Result<()> {
+    info!("{}: /cancel {}", msg.from().unwrap().id, gallery);
+    let text = if uploader.cancel(gallery.id()).await? {
+        "已取消"
+    } else {
+        "没有找到可以取消的任务，可能已经开始发布"
+    };
+    reply_to!(bot, msg, text).await?;
     Ok(())
 }
 
-async fn cmd_delete(bot: Bot, msg: Message, command: AdminCommand) -> Result<()> {
+async fn cmd_jobs(bot: Bot, db: Database, msg: Message, uploader: ExloliUploader) -> Result<()> {
+    info!("{}: /jobs", msg.from().unwrap().id);
+    let running = uploader.running();
+    let jobs = JobEntity::list_active(&db).await?;
+    if jobs.is_empty() {
+        reply_to!(bot, msg, "上传队列为空").await?;
+        return Ok(());
+    }
+    let mut text = String::new();
+    for job in jobs {
+        let upload = running.iter().find(|upload| upload.job == job.id);
+        let status = match upload {
+            Some(upload) if upload.is_cancelled() => "取消中".to_string(),
+            Some(upload) => format!("上传中，开始于 {}", upload.started_at.format("%H:%M:%S")),
+            None => format!("{:?}（第 {} 次）", job.status, job.attempts),
+        };
+        text.push_str(&format!("{} {} {}\n", job.id, job.url, status));
+    }
+    reply_to!(bot, msg, text).await?;
+    Ok(())
+}
+
+async fn cmd_queue(bot: Bot, msg: Message, uploader: ExloliUploader, cfg: Config) -> Result<()> {
+    info!("{}: /queue", msg.from().unwrap().id);
+    let jobs = uploader.publish_queue(channel_of(&cfg, &msg)).await?;
+    reply_to!(bot, msg, publish_queue_text(&jobs)).await?;
+    Ok(())
+}
+
+async fn cmd_move(
+    bot: Bot,
+    msg: Message,
+    uploader: ExloliUploader,
+    cfg: Config,
+    (gallery, position): (EhGalleryUrl, usize),
+) -> Result<()> {
+    info!("{}: /move {} {}", msg.from().unwrap().id, gallery, position);
+    let jobs = uploader.move_in_queue(channel_of(&cfg, &msg), gallery.id(), position).await?;
+    reply_to!(bot, msg, publish_queue_text(&jobs)).await?;
+    Ok(())
+}
+
+fn publish_queue_text(jobs: &[JobEntity]) -> String {
+    if jobs.is_empty() {
+        return "发布队列为空".to_string();
+    }
+    jobs.iter()
+        .enumerate()
+        .map(|(i, job)| format!("{}. {}", i + 1, job.url))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn cmd_delete(
+    bot: Bot,
+    db: Database,
+    msg: Message,
+    cfg: Config,
+    command: AdminCommand,
+) -> Result<()> {
     info!("{}: /delete", msg.from().unwrap().id);
     let reply_to = msg.reply_to_message().context("没有回复消息")?;
 
     let channel = reply_to.forward_from_chat().context("该消息没有回复画廊")?;
     let channel_msg = reply_to.forward_from_message_id().context("获取转发来源失败")?;
+    let channel_id = cfg.channel_by_chat(channel).context("该消息不是来自已配置的频道")?.id();
 
-    let msg_entity = MessageEntity::get(channel_msg).await?.unwrap();
+    let msg_entity =
+        MessageEntity::get(&db, channel_msg, &channel_id).await?.context("找不到消息")?;
 
     bot.delete_message(reply_to.chat.id, reply_to.id).await?;
     bot.delete_message(channel.id, MessageId(msg_entity.id)).await?;
 
     if matches!(command, AdminCommand::Delete) {
-        GalleryEntity::update_deleted(msg_entity.gallery_id, true).await?;
+        GalleryEntity::update_deleted(&db, msg_entity.gallery_id, true).await?;
     } else {
-        GalleryEntity::delete(msg_entity.gallery_id).await?;
-        MessageEntity::delete(channel_msg).await?;
+        GalleryEntity::delete(&db, msg_entity.gallery_id).await?;
+        MessageEntity::delete(&db, channel_msg, &channel_id).await?;
     }
 
     Ok(())
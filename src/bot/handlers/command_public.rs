@@ -11,17 +11,17 @@ use tracing::info;
 
 use crate::bot::command::{AdminCommand, PublicCommand};
 use crate::bot::handlers::{
-    cmd_best_keyboard, cmd_best_text, cmd_challenge_keyboard, gallery_preview_url,
+    channel_of, cmd_best_keyboard, cmd_best_text, cmd_challenge_keyboard, gallery_preview_url,
 };
 use crate::bot::scheduler::Scheduler;
 use crate::bot::utils::{ChallengeLocker, ChallengeProvider};
 use crate::bot::Bot;
 use crate::config::Config;
-use crate::database::{GalleryEntity, MessageEntity, PollEntity};
+use crate::database::{Database, GalleryEntity, MessageEntity, PollEntity};
 use crate::ehentai::{EhGalleryUrl, GalleryInfo};
 use crate::tags::EhTagTransDB;
 use crate::uploader::ExloliUploader;
-use crate::{reply_to, try_with_reply};
+use crate::reply_to;
 
 pub fn public_command_handler(
     _config: Config,
@@ -47,15 +47,20 @@ async fn cmd_help(bot: Bot, msg: Message) -> Result<()> {
 
 async fn cmd_upload(
     bot: Bot,
+    db: Database,
     msg: Message,
     uploader: ExloliUploader,
+    cfg: Config,
     gallery: EhGalleryUrl,
 ) -> Result<()> {
     info!("{}: /upload {}", msg.from().unwrap().id, gallery);
-    if GalleryEntity::get(gallery.id()).await?.is_none() {
+    if GalleryEntity::get(&db, gallery.id()).await?.is_none() {
         reply_to!(bot, msg, "非管理员只能上传存在上传记录的画廊").await?;
     } else {
-        try_with_reply!(bot, msg, uploader.try_upload(&gallery, true).await);
+        match uploader.enqueue(&gallery, channel_of(&cfg, &msg), true).await? {
+            Some(id) => reply_to!(bot, msg, format!("已加入上传队列，任务 ID：{id}")).await?,
+            None => reply_to!(bot, msg, "该画廊已经上传过了").await?,
+        };
     }
     Ok(())
 }
@@ -90,13 +95,14 @@ async fn cmd_challenge(
 
 async fn cmd_best(
     bot: Bot,
+    db: Database,
     msg: Message,
     (end, start): (u16, u16),
     cfg: Config,
     scheduler: Scheduler,
 ) -> Result<()> {
     info!("{}: /best {} {}", msg.from().unwrap().id, end, start);
-    let text = cmd_best_text(start as i32, end as i32, 0, cfg.telegram.channel_id).await?;
+    let text = cmd_best_text(&db, start as i32, end as i32, 0, channel_of(&cfg, &msg)).await?;
     let keyboard = cmd_best_keyboard(start as i32, end as i32, 0);
     let reply =
         reply_to!(bot, msg, text).reply_markup(keyboard).disable_web_page_preview(true).await?;
@@ -107,7 +113,14 @@ async fn cmd_best(
     Ok(())
 }
 
-async fn cmd_update(bot: Bot, msg: Message, uploader: ExloliUploader, url: String) -> Result<()> {
+async fn cmd_update(
+    bot: Bot,
+    db: Database,
+    msg: Message,
+    uploader: ExloliUploader,
+    cfg: Config,
+    url: String,
+) -> Result<()> {
     info!("{}: /update {}", msg.from().unwrap().id, url);
     let msg_id = if url.is_empty() {
         msg.reply_to_message()
@@ -120,14 +133,16 @@ async fn cmd_update(bot: Bot, msg: Message, uploader: ExloliUploader, url: Strin
             .and_then(|id| id.parse::<i32>().ok())
             .ok_or(anyhow!("Invalid URL"))?
     };
-    let msg_entity = MessageEntity::get(msg_id).await?.ok_or(anyhow!("Message not found"))?;
+    let channel = channel_of(&cfg, &msg);
+    let msg_entity =
+        MessageEntity::get(&db, msg_id, &channel.id()).await?.ok_or(anyhow!("Message not found"))?;
     let gl_entity =
-        GalleryEntity::get(msg_entity.gallery_id).await?.ok_or(anyhow!("Gallery not found"))?;
+        GalleryEntity::get(&db, msg_entity.gallery_id).await?.ok_or(anyhow!("Gallery not found"))?;
 
     let reply = reply_to!(bot, msg, "更新中……").await?;
 
-    // 调用 rescan_gallery 把失效画廊重新上传
-    uploader.recheck(vec![gl_entity.clone()]).await?;
+    // 检查预览是否失效，失效时重新发布
+    uploader.check_gallery(&gl_entity).await?;
     // 看一下有没有 tag 或者标题需要更新
     uploader.try_update(&gl_entity.url(), false).await?;
     bot.edit_message_text(msg.chat.id, reply.id, "更新完成").await?;
@@ -145,12 +160,18 @@ async fn cmd_ping(bot: Bot, msg: Message, scheduler: Scheduler) -> Result<()> {
     Ok(())
 }
 
-async fn cmd_query(bot: Bot, msg: Message, cfg: Config, gallery: EhGalleryUrl) -> Result<()> {
+async fn cmd_query(
+    bot: Bot,
+    db: Database,
+    msg: Message,
+    cfg: Config,
+    gallery: EhGalleryUrl,
+) -> Result<()> {
     info!("{}: /query {}", msg.from().unwrap().id, gallery);
-    match GalleryEntity::get(gallery.id()).await? {
+    match GalleryEntity::get(&db, gallery.id()).await? {
         Some(gallery) => {
-            let poll = PollEntity::get_by_gallery(gallery.id).await?.context("找不到投票")?;
-            let preview = gallery_preview_url(cfg.telegram.channel_id, gallery.id).await?;
+            let poll = PollEntity::get_by_gallery(&db, gallery.id).await?.context("找不到投票")?;
+            let preview = gallery_preview_url(&db, channel_of(&cfg, &msg), gallery.id).await?;
             let url = gallery.url().url();
             reply_to!(
                 bot,
@@ -158,7 +179,7 @@ async fn cmd_query(bot: Bot, msg: Message, cfg: Config, gallery: EhGalleryUrl) -
                 format!(
                     "消息：{preview}\n地址：{url}\n评分：{:.2}（{:.2}）",
                     poll.score * 100.,
-                    poll.rank().await? * 100.
+                    poll.rank(&db).await? * 100.
                 )
             )
             .await?;
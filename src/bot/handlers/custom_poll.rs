@@ -4,22 +4,34 @@ use tracing::info;
 
 use crate::bot::handlers::utils;
 use crate::bot::Bot;
-use crate::database::{GalleryEntity, PollEntity};
+use crate::config::Config;
+use crate::database::{Database, GalleryEntity, PollEntity};
 use crate::reply_to;
 
-pub async fn custom_pool_sender(bot: Bot, message: Message) -> Result<()> {
+pub async fn custom_pool_sender(
+    bot: Bot,
+    db: Database,
+    message: Message,
+    cfg: Config,
+) -> Result<()> {
     info!("频道消息更新，发送投票");
 
     let msg_id = message.forward_from_message_id().context("找不到消息")?;
-    let gallery = GalleryEntity::get_by_msg(msg_id).await?.context("找不到画廊")?;
+    let channel = message
+        .forward_from_chat()
+        .and_then(|chat| cfg.channel_by_chat(chat))
+        .or_else(|| cfg.channel_by_group(message.chat.id))
+        .context("找不到频道")?;
+    let gallery =
+        GalleryEntity::get_by_msg(&db, msg_id, &channel.id()).await?.context("找不到画廊")?;
 
     // FIXME: 此处如果父画廊还没有记录，则无法找到投票，应该改成不断向 E 站请求父画廊直到有父画廊存在投票或者没有父画廊为止
     // 对于投票的 ID，如果该画廊有投票，则使用该画廊的投票 ID
-    let poll_id = match PollEntity::get_by_gallery(gallery.id).await? {
+    let poll_id = match PollEntity::get_by_gallery(&db, gallery.id).await? {
         Some(v) => v.id,
         // 如果没有，则尝试使用其父画廊的投票 ID
         None => match gallery.parent {
-            Some(id) => match PollEntity::get_by_gallery(id).await? {
+            Some(id) => match PollEntity::get_by_gallery(&db, id).await? {
                 Some(v) => v.id,
                 // 如果还是没有，则使用其画廊 ID
                 None => gallery.id as i64,
@@ -29,12 +41,12 @@ pub async fn custom_pool_sender(bot: Bot, message: Message) -> Result<()> {
     };
 
     // 此处存在重复插入，但可以忽略
-    PollEntity::create(poll_id, gallery.id).await?;
+    PollEntity::create(&db, poll_id, gallery.id).await?;
 
-    let votes = PollEntity::get_vote(poll_id).await?;
+    let votes = PollEntity::get_vote(&db, poll_id).await?;
     let markup = utils::poll_keyboard(poll_id, &votes);
 
-    let score = PollEntity::update_score(poll_id).await? * 100.;
+    let score = PollEntity::update_score(&db, poll_id).await? * 100.;
     let sum = votes.iter().sum::<i32>();
     reply_to!(bot, message, format!("当前 {sum} 人投票，{score:.2} 分"))
         .reply_markup(markup)
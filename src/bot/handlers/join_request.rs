@@ -5,9 +5,14 @@ use tracing::info;
 
 use crate::bot::Bot;
 use crate::config::Config;
-use crate::database::InviteLink;
+use crate::database::{Database, InviteLink};
 
-pub async fn join_request_handler(bot: Bot, jq: ChatJoinRequest, cfg: Config) -> Result<()> {
+pub async fn join_request_handler(
+    bot: Bot,
+    db: Database,
+    jq: ChatJoinRequest,
+    cfg: Config,
+) -> Result<()> {
     // 没有加入群组
     if matches!(
         bot.get_chat_member(cfg.telegram.auth_group_id, jq.from.id).await?.kind,
@@ -19,7 +24,11 @@ pub async fn join_request_handler(bot: Bot, jq: ChatJoinRequest, cfg: Config) ->
     }
 
     if let Some(invite_link) = jq.invite_link {
-        InviteLink::create(jq.from.id.0 as i64, &invite_link.invite_link).await?;
+        let channel_id = match cfg.channel_by_chat(&jq.chat) {
+            Some(channel) => channel.id(),
+            None => jq.chat.id.to_string(),
+        };
+        InviteLink::create(&db, jq.from.id.0 as i64, &channel_id, &invite_link.invite_link).await?;
     }
 
     info!("{}: 批准来自 {} 的加入请求", jq.chat.id, jq.from.id);
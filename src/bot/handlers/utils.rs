@@ -1,15 +1,15 @@
 use anyhow::{anyhow, Result};
 use chrono::{Duration, Utc};
-use reqwest::Url;
-use teloxide::prelude::*;
 use teloxide::types::{
-    InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup, MessageId, Recipient,
+    InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup, Message,
 };
 use teloxide::utils::html::link;
 
 use crate::bot::utils::CallbackData;
-use crate::database::{ChallengeView, GalleryEntity, MessageEntity, TelegraphEntity};
+use crate::config::{Channel, Config};
+use crate::database::{ChallengeView, Database, GalleryEntity, MessageEntity, TelegraphEntity};
 use crate::tags::EhTagTransDB;
+use crate::utils::url_of;
 
 pub fn cmd_challenge_keyboard(
     id: i64,
@@ -25,18 +25,19 @@ pub fn cmd_challenge_keyboard(
 }
 
 pub async fn cmd_best_text(
+    db: &Database,
     start: i32,
     end: i32,
     offset: i32,
-    channel: Recipient,
+    channel: &Channel,
 ) -> Result<String> {
     let start = Utc::now().date_naive() - Duration::days(start as i64);
     let end = Utc::now().date_naive() - Duration::days(end as i64);
 
     let mut text = format!("最近 {start} ~ {end} 天的本子排名（{offset}）");
 
-    for (score, title, gid) in GalleryEntity::list(start, end, 20, offset).await? {
-        let url = gallery_preview_url(channel.clone(), gid).await?;
+    for (score, title, gid) in GalleryEntity::list(db, start, end, 20, offset).await? {
+        let url = gallery_preview_url(db, channel, gid).await?;
         text.push_str(&format!("\n<code>{:.2}</code> - {}", score * 100., link(&url, &title),));
     }
 
@@ -50,15 +51,6 @@ pub fn cmd_best_keyboard(from: i32, to: i32, offset: i32) -> InlineKeyboardMarku
     ]])
 }
 
-pub fn url_of(channel: Recipient, id: i32) -> Url {
-    match channel {
-        Recipient::Id(chat_id) => Message::url_of(chat_id, None, MessageId(id)).unwrap(),
-        Recipient::ChannelUsername(username) => {
-            Message::url_of(ChatId(-1000000000000), Some(&username[1..]), MessageId(id)).unwrap()
-        }
-    }
-}
-
 pub fn poll_keyboard(poll_id: i64, votes: &[i32; 5]) -> InlineKeyboardMarkup {
     let sum = votes.iter().sum::<i32>();
     let votes: Box<dyn Iterator<Item = f32>> = if sum == 0 {
@@ -84,12 +76,25 @@ pub fn poll_keyboard(poll_id: i64, votes: &[i32; 5]) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new(options)
 }
 
-pub async fn gallery_preview_url(channel_id: Recipient, gallery_id: i32) -> Result<String> {
-    if let Some(msg) = MessageEntity::get_by_gallery(gallery_id).await? {
-        return Ok(url_of(channel_id, msg.id).to_string());
+pub async fn gallery_preview_url(
+    db: &Database,
+    channel: &Channel,
+    gallery_id: i32,
+) -> Result<String> {
+    if let Some(msg) = MessageEntity::get_by_gallery(db, gallery_id, &channel.id()).await? {
+        return Ok(url_of(channel.channel_id.clone(), msg.id).to_string());
     }
-    if let Some(telehraph) = TelegraphEntity::get(gallery_id).await? {
+    if let Some(telehraph) = TelegraphEntity::get(db, gallery_id).await? {
         return Ok(telehraph.url);
     }
     Err(anyhow!("找不到画廊"))
 }
+
+/// 消息对应的频道：优先使用所回复的频道消息的来源，其次是讨论组对应的频道，都没有时使用第一个频道
+pub fn channel_of<'a>(cfg: &'a Config, msg: &Message) -> &'a Channel {
+    msg.reply_to_message()
+        .and_then(|reply| reply.forward_from_chat())
+        .and_then(|chat| cfg.channel_by_chat(chat))
+        .or_else(|| cfg.channel_by_group(msg.chat.id))
+        .unwrap_or_else(|| cfg.default_channel())
+}
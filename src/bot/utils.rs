@@ -12,7 +12,7 @@ use tokio::sync::Mutex;
 use tokio::time::sleep;
 use tracing::{info, warn};
 
-use crate::database::ChallengeView;
+use crate::database::{ChallengeView, Database};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CallbackData {
@@ -126,11 +126,11 @@ impl ChallengeLocker {
 pub struct ChallengeProvider(Arc<Mutex<Receiver<Vec<ChallengeView>>>>);
 
 impl ChallengeProvider {
-    pub fn new() -> Self {
+    pub fn new(db: Database) -> Self {
         let (tx, rx) = channel(5);
         tokio::spawn(async move {
             loop {
-                match Self::_get_challenge().await {
+                match Self::_get_challenge(&db).await {
                     Ok(challenge) => {
                         tx.send(challenge).await.unwrap();
                     }
@@ -143,9 +143,9 @@ impl ChallengeProvider {
         Self(Arc::new(Mutex::new(rx)))
     }
 
-    async fn _get_challenge() -> Result<Vec<ChallengeView>> {
+    async fn _get_challenge(db: &Database) -> Result<Vec<ChallengeView>> {
         loop {
-            let challenge = ChallengeView::get_random().await?;
+            let challenge = ChallengeView::get_random(db).await?;
             if challenge.is_empty() {
                 sleep(Duration::from_secs(5)).await;
                 continue;
@@ -1,12 +1,13 @@
+use std::str::FromStr;
 use std::time::Duration;
 
-use anyhow::Result;
-use duration_str::deserialize_duration;
-use once_cell::sync::OnceCell;
+use anyhow::{bail, Context, Result};
+use chrono::NaiveTime;
+use duration_str::{deserialize_duration, deserialize_option_duration};
 use serde::Deserialize;
-use teloxide::types::{ChatId, Recipient};
+use teloxide::types::{Chat, ChatId, Recipient};
 
-pub static CHANNEL_ID: OnceCell<String> = OnceCell::new();
+use crate::host::HostKind;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
@@ -14,24 +15,67 @@ pub struct Config {
     pub log_level: String,
     /// 同时下载线程数量
     pub threads_num: usize,
+    /// 同时上传线程数量
+    #[serde(default = "default_upload_threads_num")]
+    pub upload_threads_num: usize,
     /// 定时爬取间隔
     #[serde(deserialize_with = "deserialize_duration")]
     pub interval: Duration,
     /// Sqlite 数据库位置
     pub database_url: String,
+    /// 只模拟扫描并报告将要进行的操作，不写入数据库，也不调用 Telegram、Telegraph 和 Catbox
+    #[serde(default)]
+    pub dry_run: bool,
     pub exhentai: ExHentai,
     pub telegraph: Telegraph,
     pub telegram: Telegram,
-    pub catbox: Catbox,  // Catbox 配置
+    pub catbox: Catbox, // Catbox 配置
+    /// 上传任务队列
+    #[serde(default)]
+    pub queue: Queue,
+    /// 图床配置
+    #[serde(default)]
+    pub host: Host,
+    /// 上传前的图片处理
+    #[serde(default)]
+    pub image: ImageConfig,
+    /// 基于感知哈希的去重和广告识别
+    #[serde(default)]
+    pub dedup: Dedup,
+    /// 对外部服务的限速和重试
+    #[serde(default)]
+    pub limit: Limit,
+    /// 发布到频道的节奏
+    #[serde(default)]
+    pub publish: Publish,
+    /// 后台检查文章和图片链接
+    #[serde(default)]
+    pub health: Health,
+    /// 所有频道，每个频道有独立的搜索条件、筛选规则、讨论组和消息模板，共用同一个图片库
+    ///
+    /// 为空时，使用 exhentai、telegram 中的搜索条件和频道，以及下面的 filter 和 message_template
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    /// 未配置 channels 时，上传前对画廊的筛选规则
+    #[serde(default)]
+    pub filter: Filter,
+    /// 未配置 channels 时，频道消息模板文件的路径，为空时使用内置的模板
+    pub message_template: Option<String>,
+}
+
+fn default_upload_threads_num() -> usize {
+    1
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ExHentai {
     /// 登陆 cookie
     pub cookie: String,
-    /// 搜索参数
+    /// 未配置 channels 时的搜索参数
+    #[serde(default)]
     pub search_params: Vec<(String, String)>,
-    /// 最大遍历画廊数量
+    /// 未配置 channels 时的最大遍历画廊数量
+    #[serde(default)]
     pub search_count: usize,
     /// 翻译文件的位置
     pub trans_file: String,
@@ -39,36 +83,532 @@ pub struct ExHentai {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Telegraph {
-    /// Telegraph token
-    pub access_token: String,
+    /// 未配置 accounts 时使用的 Telegraph token，配置了 accounts 时用于编辑添加账号池之前发布的文章
+    pub access_token: Option<String>,
+    /// Telegraph 账号池，发布文章被限流时会轮换到其他账号
+    #[serde(default)]
+    pub accounts: Vec<TelegraphAccount>,
     /// 文章作者名称
     pub author_name: String,
     /// 文章作者连接
     pub author_url: String,
+    /// 是否在文章开头显示画廊的标题、标签、来源和发布时间
+    #[serde(default = "default_true")]
+    pub header: bool,
+    /// 是否在每张图片下方显示页码
+    #[serde(default)]
+    pub page_captions: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelegraphAccount {
+    /// 账号名称，会记录在数据库中，用于找到发布文章的账号，配置后不能修改
+    pub name: String,
+    /// Telegraph token
+    pub access_token: String,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Telegram {
-    /// 频道 id
-    pub channel_id: Recipient,
+    /// 未配置 channels 时的频道 id
+    pub channel_id: Option<Recipient>,
     /// bot 名称
     pub bot_id: String,
     /// bot token
     pub token: String,
-    /// 讨论组 ID
-    pub group_id: ChatId,
+    /// 未配置 channels 时的讨论组 ID
+    pub group_id: Option<ChatId>,
     /// 入口讨论组 ID
     pub auth_group_id: ChatId,
+    /// 画廊消息的发送方式
+    #[serde(default)]
+    pub post_mode: PostMode,
+    /// 以图片组发送时包含的图片数量，最多 10 张
+    #[serde(default = "default_media_group_size")]
+    pub media_group_size: usize,
+    /// 是否为图片加上剧透遮罩
+    #[serde(default)]
+    pub spoiler: bool,
+}
+
+fn default_media_group_size() -> usize {
+    4
+}
+
+/// 画廊消息的发送方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostMode {
+    /// 纯文本消息
+    #[default]
+    Text,
+    /// 发送封面图片，消息正文作为图片说明
+    Cover,
+    /// 发送前几页组成的图片组，消息正文作为第一张图片的说明
+    MediaGroup,
+}
+
+/// 一个频道的配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct Channel {
+    /// 频道名称，用于日志和报告
+    pub name: String,
+    /// 频道 id
+    pub channel_id: Recipient,
+    /// 讨论组 ID
+    pub group_id: ChatId,
+    /// 搜索参数
+    pub search_params: Vec<(String, String)>,
+    /// 最大遍历画廊数量
+    pub search_count: usize,
+    /// 上传前对画廊的筛选规则
+    #[serde(default)]
+    pub filter: Filter,
+    /// 频道消息模板文件的路径，为空时使用内置的模板
+    pub message_template: Option<String>,
+}
+
+impl Channel {
+    /// 数据库中记录的频道 ID
+    pub fn id(&self) -> String {
+        self.channel_id.to_string()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Catbox {
-    pub userhash: String,  // Catbox 用户的 userhash
+    pub userhash: String, // Catbox 用户的 userhash
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Host {
+    /// 使用的图床
+    pub backend: HostKind,
+    /// 本地图床配置
+    pub local: Option<LocalConfig>,
+    /// S3 图床配置
+    pub s3: Option<S3Config>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalConfig {
+    /// 图片保存目录
+    pub path: String,
+    /// 该目录对外访问的 URL 前缀
+    pub base_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Config {
+    /// 服务地址，例如 http://127.0.0.1:9000
+    pub endpoint: String,
+    /// 区域
+    pub region: String,
+    /// bucket 名称
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// 图片对外访问的 URL 前缀，为空时使用 endpoint/bucket
+    pub public_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImageConfig {
+    /// 图片最长边的最大像素数，超出时会被缩小，为 0 时不限制
+    pub max_dimension: u32,
+    /// 图片的最大字节数，超出时会被转换为 JPEG 并压缩，为 0 时不限制
+    pub max_file_size: usize,
+    /// 转换为 JPEG 时使用的质量
+    pub jpeg_quality: u8,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self { max_dimension: 4096, max_file_size: 5 * 1024 * 1024, jpeg_quality: 85 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Dedup {
+    /// 与已有图片的汉明距离不超过该值时，直接复用已有图片而不重新上传，不能超过 3
+    ///
+    /// 为 0 时只复用感知哈希完全相同的图片，距离越大越容易把内容不同的图片当成同一张
+    pub reuse_distance: u32,
+    /// 判断两张图片相似的汉明距离，用于识别广告，不能超过 3
+    pub ad_distance: u32,
+    /// 相似图片出现在多少个不同的画廊中时，将其标记为广告，为 0 时不识别广告
+    pub ad_threshold: i64,
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Self { reuse_distance: 0, ad_distance: 3, ad_threshold: 5 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Limit {
+    /// 请求失败后的最大重试次数
+    pub retries: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub backoff: Duration,
+    /// 单次重试等待时间的上限
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_backoff: Duration,
+    /// exhentai.org 的请求速率
+    pub exhentai: RateLimit,
+    /// catbox.moe 的请求速率
+    pub catbox: RateLimit,
+    /// telegra.ph 的请求速率
+    pub telegraph: RateLimit,
+}
+
+impl Default for Limit {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+            exhentai: RateLimit { rate: 2.0, burst: 5 },
+            catbox: RateLimit { rate: 2.0, burst: 4 },
+            telegraph: RateLimit { rate: 0.5, burst: 3 },
+        }
+    }
+}
+
+/// 画廊筛选规则，任意一条规则不满足时跳过该画廊
+///
+/// 标签的格式为 `namespace:tag`，省略 namespace 时匹配任意 namespace 下的同名标签
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Filter {
+    /// 画廊必须至少包含其中一个标签，为空时不限制
+    pub include_tags: Vec<String>,
+    /// 包含其中任意一个标签的画廊会被跳过
+    pub exclude_tags: Vec<String>,
+    /// 最少页数
+    pub min_pages: Option<usize>,
+    /// 最多页数
+    pub max_pages: Option<usize>,
+    /// 最少收藏数
+    pub min_favorites: Option<i32>,
+    /// 发布时间距今至少多久，用于等待收藏数稳定
+    #[serde(deserialize_with = "deserialize_option_duration")]
+    pub min_age: Option<Duration>,
+    /// 发布时间距今最多多久，用于跳过搜索结果中的旧画廊
+    #[serde(deserialize_with = "deserialize_option_duration")]
+    pub max_age: Option<Duration>,
+    /// 不上传这些上传者的画廊
+    pub uploader_blacklist: Vec<String>,
+    /// 被跳过的画廊在多久之后重新检查，例如等待收藏数增长，默认为 1 天
+    #[serde(deserialize_with = "deserialize_option_duration")]
+    pub recheck_after: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimit {
+    /// 平均每秒允许的请求数量，为 0 时不限速
+    pub rate: f64,
+    /// 允许的最大突发请求数量
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Queue {
+    /// 同时处理的画廊数量
+    pub workers: usize,
+    /// 单个任务的最大尝试次数
+    pub max_attempts: i32,
+    /// 失败后重试的等待时间
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub retry_delay: Duration,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self { workers: 1, max_attempts: 3, retry_delay: Duration::from_secs(600) }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Health {
+    /// 是否在后台检查链接
+    pub enabled: bool,
+    /// 每一轮检查之间的间隔
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub interval: Duration,
+    /// 同一个链接两次检查之间的最小间隔
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub recheck_after: Duration,
+    /// 每一轮最多检查的文章数量和图片数量
+    pub batch: i64,
+    /// 同时进行的检查数量
+    pub concurrency: usize,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::from_secs(600),
+            recheck_after: Duration::from_secs(7 * 24 * 3600),
+            batch: 200,
+            concurrency: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Publish {
+    /// 两次发布之间的最小间隔，为 0 时上传完毕后立即发布
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub interval: Duration,
+    /// 允许发布的时间段（服务器本地时间），为空时不限制
+    #[serde(default)]
+    pub windows: Vec<TimeWindow>,
+}
+
+impl Publish {
+    /// 指定时间是否在允许发布的时间段内
+    pub fn in_window(&self, time: NaiveTime) -> bool {
+        self.windows.is_empty() || self.windows.iter().any(|w| w.contains(time))
+    }
+}
+
+/// 每天的一个时间段，格式为 `HH:MM-HH:MM`，结束时间早于开始时间时表示跨越午夜
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s.split_once('-').context("时间段格式应为 HH:MM-HH:MM")?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M")?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M")?;
+        Ok(Self { start, end })
+    }
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
 }
 
 impl Config {
     pub fn new(path: &str) -> Result<Self> {
-        let s = std::fs::read_to_string(path)?;
-        Ok(toml::from_str(&s)?)
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// 解析配置文件的内容，并补全默认的频道和 telegraph 账号
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut config: Self = toml::from_str(s)?;
+        if config.channels.is_empty() {
+            config.channels.push(config.legacy_channel()?);
+        }
+        for (i, channel) in config.channels.iter().enumerate() {
+            if config.channels[..i].iter().any(|c| c.channel_id == channel.channel_id) {
+                bail!("频道重复：{}", channel.channel_id);
+            }
+        }
+        if config.telegraph.accounts.is_empty() {
+            let access_token =
+                config.telegraph.access_token.clone().context("没有配置 telegraph 账号")?;
+            config
+                .telegraph
+                .accounts
+                .push(TelegraphAccount { name: "default".to_string(), access_token });
+        } else if let Some(access_token) = config.telegraph.access_token.clone() {
+            // 添加账号池之前发布的文章没有记录账号名称，需要用原来的 token 才能编辑
+            if config.telegraph.accounts[0].access_token != access_token {
+                let legacy = TelegraphAccount { name: String::new(), access_token };
+                config.telegraph.accounts.push(legacy);
+            }
+        }
+        // 超过 3 时 ImageEntity::find_similar 无法找到所有相似的图片
+        if config.dedup.reuse_distance > 3 || config.dedup.ad_distance > 3 {
+            bail!("dedup.reuse_distance 和 dedup.ad_distance 不能超过 3");
+        }
+        let accounts = &config.telegraph.accounts;
+        for (i, account) in accounts.iter().enumerate() {
+            if accounts[..i].iter().any(|a| a.name == account.name) {
+                bail!("telegraph 账号重复：{}", account.name);
+            }
+        }
+        Ok(config)
+    }
+
+    /// 根据单频道的旧配置生成频道配置
+    fn legacy_channel(&self) -> Result<Channel> {
+        Ok(Channel {
+            name: "default".to_string(),
+            channel_id: self.telegram.channel_id.clone().context("没有配置任何频道")?,
+            group_id: self.telegram.group_id.context("没有配置讨论组")?,
+            search_params: self.exhentai.search_params.clone(),
+            search_count: self.exhentai.search_count,
+            filter: self.filter.clone(),
+            message_template: self.message_template.clone(),
+        })
+    }
+
+    /// 第一个频道，无法确定是哪个频道时使用
+    pub fn default_channel(&self) -> &Channel {
+        &self.channels[0]
+    }
+
+    /// 根据数据库中记录的频道 ID 查找频道
+    pub fn channel(&self, id: &str) -> Option<&Channel> {
+        self.channels.iter().find(|c| c.id() == id)
+    }
+
+    /// 根据讨论组查找频道
+    pub fn channel_by_group(&self, group_id: ChatId) -> Option<&Channel> {
+        self.channels.iter().find(|c| c.group_id == group_id)
+    }
+
+    /// 根据 telegram 的会话查找频道，例如转发消息的来源
+    pub fn channel_by_chat(&self, chat: &Chat) -> Option<&Channel> {
+        let username = chat.username().map(|name| format!("@{}", name));
+        self.channels.iter().find(|c| match &c.channel_id {
+            Recipient::Id(id) => *id == chat.id,
+            Recipient::ChannelUsername(name) => Some(name) == username.as_ref(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_channel() {
+        let config = Config::new("config.toml.example").unwrap();
+        assert_eq!(config.channels.len(), 1);
+        let channel = config.default_channel();
+        assert_eq!(channel.id(), "@xxx");
+        assert_eq!(channel.search_count, 10);
+        assert_eq!(channel.filter.exclude_tags, ["other:ai generated"]);
+        assert!(config.channel_by_group(ChatId(-1001423106182)).is_some());
+    }
+
+    #[test]
+    fn legacy_telegraph_token() {
+        let example = include_str!("../config.toml.example");
+        let config = Config::parse(example).unwrap();
+        let names: Vec<_> = config.telegraph.accounts.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["default"]);
+
+        // 配置了账号池时，旧的 token 注册为名称为空的账号，用于编辑之前发布的文章
+        let pool = "[[telegraph.accounts]]\nname = \"main\"\naccess_token = \"yyyy\"\n";
+        let config = Config::parse(&format!("{}\n{}", example, pool)).unwrap();
+        let accounts: Vec<_> = config
+            .telegraph
+            .accounts
+            .iter()
+            .map(|a| (a.name.as_str(), a.access_token.as_str()))
+            .collect();
+        assert_eq!(accounts, [("main", "yyyy"), ("", "xxxx")]);
+
+        // 第一个账号就是旧的 token 时不需要重复注册
+        let config = Config::parse(&format!("{}\n{}", example, pool.replace("yyyy", "xxxx")));
+        assert_eq!(config.unwrap().telegraph.accounts.len(), 1);
+    }
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn time_window() {
+        let window: TimeWindow = "09:00-18:30".parse().unwrap();
+        assert_eq!((window.start, window.end), (time("09:00"), time("18:30")));
+        // 包含开始时间，不包含结束时间
+        assert!(window.contains(time("09:00")));
+        assert!(window.contains(time("12:00")));
+        assert!(!window.contains(time("18:30")));
+        assert!(!window.contains(time("08:59")));
+
+        // 结束时间早于开始时间时跨越午夜
+        let window: TimeWindow = "22:00 - 02:00".parse().unwrap();
+        assert!(window.contains(time("22:00")));
+        assert!(window.contains(time("23:59")));
+        assert!(window.contains(time("00:00")));
+        assert!(window.contains(time("01:59")));
+        assert!(!window.contains(time("02:00")));
+        assert!(!window.contains(time("12:00")));
+        assert!(!window.contains(time("21:59")));
+
+        for s in ["", "09:00", "09:00-", "9-18", "25:00-26:00", "09:00~18:00", "aa:bb-cc:dd"] {
+            assert!(s.parse::<TimeWindow>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn publish_in_window() {
+        let publish = Publish::default();
+        assert!(publish.in_window(time("03:00")));
+
+        let publish: Publish =
+            toml::from_str(r#"windows = ["08:00-10:00", "22:00-02:00"]"#).unwrap();
+        assert!(publish.in_window(time("08:00")));
+        assert!(publish.in_window(time("23:00")));
+        assert!(publish.in_window(time("01:00")));
+        assert!(!publish.in_window(time("10:00")));
+        assert!(!publish.in_window(time("03:00")));
+
+        assert!(toml::from_str::<Publish>(r#"windows = ["08:00"]"#).is_err());
+    }
+
+    #[test]
+    fn partial_tables() {
+        let health: Health = toml::from_str("enabled = false").unwrap();
+        assert!(!health.enabled);
+        assert_eq!(health.batch, Health::default().batch);
+        let queue: Queue = toml::from_str("workers = 4").unwrap();
+        assert_eq!((queue.workers, queue.max_attempts), (4, 3));
+        let dedup: Dedup = toml::from_str("ad_threshold = 0").unwrap();
+        assert_eq!(dedup.ad_distance, 3);
+        let limit: Limit =
+            toml::from_str("retries = 1\n[telegraph]\nrate = 1.0\nburst = 1").unwrap();
+        assert_eq!(limit.backoff, Duration::from_secs(2));
+        let image: ImageConfig = toml::from_str("jpeg_quality = 70").unwrap();
+        assert_eq!(image.max_dimension, 4096);
+    }
+
+    #[test]
+    fn dedup_distance_limit() {
+        let example = include_str!("../config.toml.example");
+        assert_eq!(Config::parse(example).unwrap().dedup.reuse_distance, 0);
+        let config = example.replace("ad_distance = 3", "ad_distance = 4");
+        assert!(Config::parse(&config).is_err());
+        let config = example.replace("reuse_distance = 0", "reuse_distance = 5");
+        assert!(Config::parse(&config).is_err());
     }
 }
@@ -0,0 +1,482 @@
+//! 数据库的导出和导入
+//!
+//! 归档为 JSON Lines 格式，第一行是记录了格式版本的 header，之后每行是一条记录，
+//! 通过 `type` 字段区分所属的表。上传任务、catbox 专辑和链接检查的结果不会被导出。
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteRow;
+use sqlx::types::Json;
+use sqlx::{FromRow, SqliteConnection};
+
+use super::db::Database;
+use super::gallery::TagsEntity;
+
+/// 当前的归档格式版本，格式发生不兼容的变化时递增
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// 导入时遇到已经存在的记录的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnConflict {
+    /// 保留数据库中已有的记录
+    Skip,
+    /// 使用归档中的记录覆盖已有的记录
+    Replace,
+    /// 停止导入并回滚
+    Abort,
+}
+
+impl OnConflict {
+    fn insert(self) -> &'static str {
+        match self {
+            OnConflict::Skip => "INSERT OR IGNORE",
+            OnConflict::Replace => "INSERT OR REPLACE",
+            OnConflict::Abort => "INSERT OR ABORT",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Header(Header),
+    Gallery(GalleryRecord),
+    Image(ImageRecord),
+    Page(PageRecord),
+    Telegraph(TelegraphRecord),
+    Message(MessageRecord),
+    Poll(PollRecord),
+    Vote(VoteRecord),
+    Challenge(ChallengeRecord),
+    InviteLink(InviteLinkRecord),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    /// 归档格式版本
+    pub version: u32,
+    /// 导出时间
+    pub exported_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct GalleryRecord {
+    pub id: i32,
+    pub token: String,
+    pub title: String,
+    pub title_jp: Option<String>,
+    pub tags: TagsEntity,
+    pub favorite: Option<i32>,
+    pub pages: i32,
+    pub parent: Option<i32>,
+    pub deleted: bool,
+    pub posted: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ImageRecord {
+    pub id: u32,
+    pub hash: String,
+    pub url: String,
+    pub host: String,
+    pub phash: Option<i64>,
+    pub ad: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct PageRecord {
+    pub gallery_id: i32,
+    pub page: i32,
+    pub image_id: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct TelegraphRecord {
+    pub gallery_id: i32,
+    pub url: String,
+    pub parts: Json<Vec<String>>,
+    pub account: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct MessageRecord {
+    pub id: i32,
+    pub channel_id: String,
+    pub gallery_id: i32,
+    pub publish_date: NaiveDate,
+    pub kind: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct PollRecord {
+    pub id: i64,
+    pub gallery_id: i32,
+    pub score: f64,
+    pub old_vote: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct VoteRecord {
+    pub user_id: i64,
+    pub poll_id: i64,
+    pub option: i32,
+    pub vote_time: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ChallengeRecord {
+    pub id: i64,
+    pub user_id: i64,
+    pub gallery_id: i32,
+    pub page: i32,
+    pub success: bool,
+    pub answer_time: NaiveDateTime,
+    pub chat_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct InviteLinkRecord {
+    pub user_id: i64,
+    pub chat_id: String,
+    pub link: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl Record {
+    /// 记录所属的表
+    fn table(&self) -> &'static str {
+        match self {
+            Record::Header(_) => "header",
+            Record::Gallery(_) => "gallery",
+            Record::Image(_) => "image",
+            Record::Page(_) => "page",
+            Record::Telegraph(_) => "telegraph",
+            Record::Message(_) => "message",
+            Record::Poll(_) => "poll",
+            Record::Vote(_) => "vote",
+            Record::Challenge(_) => "challenge_history",
+            Record::InviteLink(_) => "invite_link",
+        }
+    }
+}
+
+/// 每张表导出或导入的记录数量
+#[derive(Debug, Default)]
+pub struct ArchiveStats {
+    /// 表名 -> (写入的数量, 因冲突跳过的数量)
+    pub tables: BTreeMap<&'static str, (u64, u64)>,
+}
+
+impl ArchiveStats {
+    fn add(&mut self, table: &'static str, written: bool) {
+        let (w, s) = self.tables.entry(table).or_default();
+        match written {
+            true => *w += 1,
+            false => *s += 1,
+        }
+    }
+}
+
+impl Display for ArchiveStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (table, (written, skipped)) in &self.tables {
+            write!(f, "{}：{} 条", table, written)?;
+            if *skipped > 0 {
+                write!(f, "，跳过 {} 条", skipped)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// 将整个数据库导出到 writer 中
+///
+/// 所有表在同一个读事务中读取，导出期间 bot 写入的数据不会导致各表之间不一致
+pub async fn export(db: &Database, mut writer: impl Write) -> Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    let header = Header { version: ARCHIVE_VERSION, exported_at: Utc::now().naive_utc() };
+    write_record(&mut writer, &Record::Header(header))?;
+
+    // 记录只会读取需要的列，其余的列（例如链接检查的结果）会被忽略
+    let mut tx = db.pool.begin().await?;
+    let (c, w, s) = (&mut *tx, &mut writer, &mut stats);
+    export_table(c, w, s, "SELECT * FROM gallery ORDER BY id", Record::Gallery).await?;
+    export_table(c, w, s, "SELECT * FROM image ORDER BY id", Record::Image).await?;
+    export_table(c, w, s, "SELECT * FROM page ORDER BY gallery_id, page", Record::Page).await?;
+    export_table(c, w, s, "SELECT * FROM telegraph ORDER BY gallery_id", Record::Telegraph).await?;
+    export_table(c, w, s, "SELECT * FROM message ORDER BY channel_id, id", Record::Message).await?;
+    export_table(c, w, s, "SELECT * FROM poll ORDER BY id", Record::Poll).await?;
+    export_table(c, w, s, "SELECT * FROM vote ORDER BY poll_id, user_id", Record::Vote).await?;
+    export_table(c, w, s, "SELECT * FROM challenge_history ORDER BY id", Record::Challenge).await?;
+    export_table(c, w, s, "SELECT * FROM invite_link ORDER BY created_at", Record::InviteLink)
+        .await?;
+    tx.commit().await?;
+
+    writer.flush()?;
+    Ok(stats)
+}
+
+async fn export_table<T>(
+    conn: &mut SqliteConnection,
+    writer: &mut impl Write,
+    stats: &mut ArchiveStats,
+    sql: &str,
+    wrap: fn(T) -> Record,
+) -> Result<()>
+where
+    T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin,
+{
+    let mut rows = sqlx::query_as::<_, T>(sql).fetch(conn);
+    while let Some(row) = rows.try_next().await? {
+        let record = wrap(row);
+        write_record(writer, &record)?;
+        stats.add(record.table(), true);
+    }
+    Ok(())
+}
+
+fn write_record(writer: &mut impl Write, record: &Record) -> Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// 从 reader 中读取归档并导入数据库，所有记录在同一个事务中写入，出错时不会导入任何记录
+pub async fn import(
+    db: &Database,
+    reader: impl BufRead,
+    on_conflict: OnConflict,
+) -> Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    let mut tx = db.pool.begin().await?;
+    let mut lines = reader.lines().enumerate();
+
+    let header = match lines.next() {
+        Some((_, line)) => {
+            serde_json::from_str::<Record>(&line?).context("无法解析归档的 header")?
+        }
+        None => bail!("归档为空"),
+    };
+    match header {
+        Record::Header(header) if header.version <= ARCHIVE_VERSION => {}
+        Record::Header(header) => bail!("不支持的归档版本：{}", header.version),
+        _ => bail!("归档的第一行不是 header"),
+    }
+
+    for (i, line) in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str::<Record>(&line)
+            .with_context(|| format!("第 {} 行格式错误", i + 1))?;
+        let written = insert(&mut tx, &record, on_conflict)
+            .await
+            .with_context(|| format!("第 {} 行导入失败", i + 1))?;
+        stats.add(record.table(), written);
+    }
+
+    tx.commit().await?;
+    Ok(stats)
+}
+
+/// 写入一条记录，返回是否写入，因冲突被跳过时返回 false
+async fn insert(
+    conn: &mut SqliteConnection,
+    record: &Record,
+    on_conflict: OnConflict,
+) -> Result<bool> {
+    let verb = on_conflict.insert();
+    let result = match record {
+        Record::Header(_) => bail!("header 只能出现在第一行"),
+        Record::Gallery(r) => {
+            let sql = format!("{verb} INTO gallery (id, token, title, title_jp, tags, favorite, pages, parent, deleted, posted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)");
+            sqlx::query(&sql)
+                .bind(r.id)
+                .bind(&r.token)
+                .bind(&r.title)
+                .bind(&r.title_jp)
+                .bind(serde_json::to_string(&r.tags.0)?)
+                .bind(r.favorite)
+                .bind(r.pages)
+                .bind(r.parent)
+                .bind(r.deleted)
+                .bind(r.posted)
+                .execute(&mut *conn)
+                .await?
+        }
+        Record::Image(r) => {
+            let sql = format!(
+                "{verb} INTO image (id, hash, url, host, phash, ad) VALUES (?, ?, ?, ?, ?, ?)"
+            );
+            sqlx::query(&sql)
+                .bind(r.id)
+                .bind(&r.hash)
+                .bind(&r.url)
+                .bind(&r.host)
+                .bind(r.phash)
+                .bind(r.ad)
+                .execute(&mut *conn)
+                .await?
+        }
+        Record::Page(r) => {
+            let sql = format!("{verb} INTO page (gallery_id, page, image_id) VALUES (?, ?, ?)");
+            sqlx::query(&sql)
+                .bind(r.gallery_id)
+                .bind(r.page)
+                .bind(r.image_id)
+                .execute(&mut *conn)
+                .await?
+        }
+        Record::Telegraph(r) => {
+            let sql = format!(
+                "{verb} INTO telegraph (gallery_id, url, parts, account) VALUES (?, ?, ?, ?)"
+            );
+            sqlx::query(&sql)
+                .bind(r.gallery_id)
+                .bind(&r.url)
+                .bind(&r.parts)
+                .bind(&r.account)
+                .execute(&mut *conn)
+                .await?
+        }
+        Record::Message(r) => {
+            let sql = format!("{verb} INTO message (id, channel_id, gallery_id, publish_date, kind) VALUES (?, ?, ?, ?, ?)");
+            sqlx::query(&sql)
+                .bind(r.id)
+                .bind(&r.channel_id)
+                .bind(r.gallery_id)
+                .bind(r.publish_date)
+                .bind(&r.kind)
+                .execute(&mut *conn)
+                .await?
+        }
+        Record::Poll(r) => {
+            let sql =
+                format!("{verb} INTO poll (id, gallery_id, score, old_vote) VALUES (?, ?, ?, ?)");
+            sqlx::query(&sql)
+                .bind(r.id)
+                .bind(r.gallery_id)
+                .bind(r.score)
+                .bind(&r.old_vote)
+                .execute(&mut *conn)
+                .await?
+        }
+        Record::Vote(r) => {
+            let sql = format!(
+                "{verb} INTO vote (user_id, poll_id, option, vote_time) VALUES (?, ?, ?, ?)"
+            );
+            sqlx::query(&sql)
+                .bind(r.user_id)
+                .bind(r.poll_id)
+                .bind(r.option)
+                .bind(r.vote_time)
+                .execute(&mut *conn)
+                .await?
+        }
+        Record::Challenge(r) => {
+            let sql = format!("{verb} INTO challenge_history (id, user_id, gallery_id, page, success, answer_time, chat_id) VALUES (?, ?, ?, ?, ?, ?, ?)");
+            sqlx::query(&sql)
+                .bind(r.id)
+                .bind(r.user_id)
+                .bind(r.gallery_id)
+                .bind(r.page)
+                .bind(r.success)
+                .bind(r.answer_time)
+                .bind(r.chat_id)
+                .execute(&mut *conn)
+                .await?
+        }
+        Record::InviteLink(r) => {
+            // invite_link 没有主键，用户、群组和链接都相同的记录视为已存在
+            let exists: Option<i64> = sqlx::query_scalar(
+                "SELECT 1 FROM invite_link WHERE user_id = ? AND chat_id = ? AND link = ?",
+            )
+            .bind(r.user_id)
+            .bind(&r.chat_id)
+            .bind(&r.link)
+            .fetch_optional(&mut *conn)
+            .await?;
+            let sql = match (exists, on_conflict) {
+                (None, _) => {
+                    "INSERT INTO invite_link (created_at, user_id, chat_id, link) VALUES (?, ?, ?, ?)"
+                }
+                (Some(_), OnConflict::Skip) => return Ok(false),
+                (Some(_), OnConflict::Abort) => bail!("邀请链接已存在：{}", r.link),
+                (Some(_), OnConflict::Replace) => {
+                    "UPDATE invite_link SET created_at = ? WHERE user_id = ? AND chat_id = ? AND link = ?"
+                }
+            };
+            sqlx::query(sql)
+                .bind(r.created_at)
+                .bind(r.user_id)
+                .bind(&r.chat_id)
+                .bind(&r.link)
+                .execute(&mut *conn)
+                .await?
+        }
+    };
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn export_and_import() {
+        let db = Database::memory().await.unwrap();
+        for sql in [
+            r#"INSERT INTO gallery (id, token, title, tags, pages, deleted) VALUES (1, 't', 'a', '{"artist":["x"]}', 1, FALSE)"#,
+            "INSERT INTO image (id, hash, url, host) VALUES (2, 'h', 'https://i', 'catbox')",
+            "INSERT INTO page (gallery_id, page, image_id) VALUES (1, 1, 2)",
+            r#"INSERT INTO telegraph (gallery_id, url, parts) VALUES (1, 'https://telegra.ph/a', '["https://telegra.ph/a"]')"#,
+            "INSERT INTO message (id, channel_id, gallery_id, publish_date) VALUES (3, '@c', 1, '2026-10-18')",
+            "INSERT INTO poll (id, gallery_id, score) VALUES (4, 1, 0.5)",
+            "INSERT INTO vote (user_id, poll_id, option, vote_time) VALUES (5, 4, 3, '2026-10-18 00:00:00')",
+            "INSERT INTO invite_link (user_id, chat_id, link, created_at) VALUES (5, '@c', 'https://t.me/+x', '2026-10-18 00:00:00')",
+        ] {
+            sqlx::query(sql).execute(&db.pool).await.unwrap();
+        }
+
+        let mut archive = vec![];
+        let stats = export(&db, &mut archive).await.unwrap();
+        assert_eq!(stats.tables.values().map(|(w, _)| w).sum::<u64>(), 8);
+
+        let restored = Database::memory().await.unwrap();
+        let stats = import(&restored, archive.as_slice(), OnConflict::Abort).await.unwrap();
+        assert_eq!(stats.tables["gallery"], (1, 0));
+        let tags: String = sqlx::query_scalar("SELECT tags FROM gallery WHERE id = 1")
+            .fetch_one(&restored.pool)
+            .await
+            .unwrap();
+        assert_eq!(tags, r#"{"artist":["x"]}"#);
+
+        // 再次导入时所有记录都已存在
+        let stats = import(&restored, archive.as_slice(), OnConflict::Skip).await.unwrap();
+        assert!(stats.tables.values().all(|(w, _)| *w == 0));
+        assert!(import(&restored, archive.as_slice(), OnConflict::Abort).await.is_err());
+        let stats = import(&restored, archive.as_slice(), OnConflict::Replace).await.unwrap();
+        assert!(stats.tables.values().all(|(_, s)| *s == 0));
+        let links: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM invite_link")
+            .fetch_one(&restored.pool)
+            .await
+            .unwrap();
+        assert_eq!(links, 1);
+
+        // 只有邀请链接冲突时也会按照 on_conflict 处理
+        let lines =
+            archive.split(|&b| b == b'\n').filter(|line| !line.is_empty()).collect::<Vec<_>>();
+        let link = [lines[0], lines[lines.len() - 1]].join(&b'\n');
+        assert!(import(&restored, link.as_slice(), OnConflict::Abort).await.is_err());
+        let stats = import(&restored, link.as_slice(), OnConflict::Skip).await.unwrap();
+        assert_eq!(stats.tables["invite_link"], (0, 1));
+    }
+}
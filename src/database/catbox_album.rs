@@ -0,0 +1,91 @@
+use chrono::{NaiveDateTime, Utc};
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::types::Json;
+use sqlx::Result;
+use tracing::Level;
+
+use super::db::Database;
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct CatboxAlbumEntity {
+    /// 专辑短链接，例如 https://catbox.moe/c/abc123 中的 abc123
+    pub short: String,
+    /// 画廊 ID
+    pub gallery_id: i32,
+    /// 专辑在该画廊中的序号，从 0 开始
+    pub part: i32,
+    /// 按顺序排列的专辑中的文件名
+    pub file_list: Json<Vec<String>>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl CatboxAlbumEntity {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn create(
+        db: &Database,
+        short: &str,
+        gallery_id: i32,
+        part: i32,
+        files: &[String],
+    ) -> Result<SqliteQueryResult> {
+        let now = Utc::now().naive_utc();
+        let file_list = Json(files);
+        sqlx::query!(
+            "REPLACE INTO catbox_album (short, gallery_id, part, file_list, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
+            short,
+            gallery_id,
+            part,
+            file_list,
+            now,
+            now,
+        )
+        .execute(&db.pool)
+        .await
+    }
+
+    /// 获取画廊的所有专辑，按序号排列
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn list(db: &Database, gallery_id: i32) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT
+                short,
+                gallery_id as "gallery_id: i32",
+                part as "part: i32",
+                file_list as "file_list: Json<Vec<String>>",
+                created_at,
+                updated_at
+            FROM catbox_album WHERE gallery_id = ? ORDER BY part
+            "#,
+            gallery_id
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// 更新专辑中的文件
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn update_files(
+        db: &Database,
+        short: &str,
+        files: &[String],
+    ) -> Result<SqliteQueryResult> {
+        let now = Utc::now().naive_utc();
+        let file_list = Json(files);
+        sqlx::query!(
+            "UPDATE catbox_album SET file_list = ?, updated_at = ? WHERE short = ?",
+            file_list,
+            now,
+            short
+        )
+        .execute(&db.pool)
+        .await
+    }
+
+    /// 专辑的访问地址
+    pub fn url(&self) -> String {
+        format!("https://catbox.moe/c/{}", self.short)
+    }
+}
@@ -3,7 +3,7 @@ use sqlx::prelude::*;
 use sqlx::sqlite::SqliteQueryResult;
 use sqlx::Result;
 
-use super::db::DB;
+use super::db::Database;
 
 #[derive(FromRow, Clone)]
 pub struct ChallengeView {
@@ -27,7 +27,7 @@ pub struct ChallengeHistory {
 }
 
 impl ChallengeView {
-    pub async fn get_random() -> Result<Vec<Self>> {
+    pub async fn get_random(db: &Database) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
             r#"
@@ -44,11 +44,10 @@ impl ChallengeView {
                 SELECT * FROM (
                     SELECT * FROM challenge_view
                     WHERE score > 0.8 AND image_id NOT IN (
-                        -- 此处过滤掉出现在大于 5 个画廊中的图片，因为大概率是广告
+                        -- 此处过滤掉被标记为广告的图片（相似图片出现在多个画廊中）
                         -- 还有第一页和最后一页
-                        -- 这个查询太耗时了，现在有基于二维码的过滤了，暂时禁用看一下效果
-                        -- SELECT image_id FROM page GROUP BY image_id HAVING COUNT(gallery_id) > 5
-                        -- UNION
+                        SELECT id FROM image WHERE ad
+                        UNION
                         SELECT image_id FROM page GROUP BY gallery_id HAVING page = MAX(page)
                         UNION
                         SELECT image_id FROM page GROUP BY gallery_id HAVING page = 1
@@ -56,13 +55,14 @@ impl ChallengeView {
                 ) GROUP BY artist
             ) ORDER BY random() LIMIT 4"#,
         )
-        .fetch_all(&*DB)
+        .fetch_all(&db.pool)
         .await
     }
 }
 
 impl ChallengeHistory {
     pub async fn create(
+        db: &Database,
         user: i64,
         gallery: i32,
         page: i32,
@@ -79,15 +79,15 @@ impl ChallengeHistory {
             now,
             chat_id,
         )
-        .execute(&*DB)
+        .execute(&db.pool)
         .await
     }
 
-    pub async fn answer_stats(user: i64, chat_id: i64) -> Result<(i32, i32)> {
+    pub async fn answer_stats(db: &Database, user: i64, chat_id: i64) -> Result<(i32, i32)> {
         let record = sqlx::query!(
             r#"SELECT SUM(success) as "success!", COUNT(*) as "total!" FROM challenge_history WHERE user_id = ? AND chat_id = ?"#, user, chat_id,
         )
-        .fetch_one(&*DB)
+        .fetch_one(&db.pool)
         .await?;
         Ok((record.success, record.total))
     }
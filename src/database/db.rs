@@ -1,25 +1,94 @@
-use std::env;
+use std::str::FromStr;
 
-use futures::executor::block_on;
-use once_cell::sync::Lazy;
 use sqlx::sqlite::*;
+use sqlx::Result;
 use tracing::info;
 
-pub static DB: Lazy<SqlitePool> = Lazy::new(|| {
-    let url = env::var("DATABASE_URL").expect("数据库连接字符串未设置");
-    block_on(get_connection_pool(&url))
-});
-
-pub async fn get_connection_pool(url: &str) -> SqlitePool {
-    info!("初始化数据库连接：{}", url);
-    let options = SqliteConnectOptions::new()
-        .journal_mode(SqliteJournalMode::Wal)
-        .synchronous(SqliteSynchronous::Normal)
-        .foreign_keys(false)
-        .filename(url)
-        .create_if_missing(true);
-    let pool = SqlitePoolOptions::new().connect_with(options).await.expect("数据库连接失败");
-    info!("检查数据库迁移");
-    sqlx::migrate!("./migrations").run(&pool).await.expect("数据库迁移失败");
-    pool
+/// 数据库连接池，所有实体的方法都需要显式传入
+#[derive(Debug, Clone)]
+pub struct Database {
+    pub(super) pool: SqlitePool,
+}
+
+impl Database {
+    /// 连接到指定的数据库文件，文件不存在时自动创建，并执行数据库迁移
+    pub async fn connect(url: &str) -> Result<Self> {
+        info!("初始化数据库连接：{}", url);
+        let options = SqliteConnectOptions::new()
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .foreign_keys(false)
+            .filename(url)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        Self::migrate(pool).await
+    }
+
+    /// 以只读方式打开已有的数据库文件，不会创建文件，也不会执行迁移
+    ///
+    /// 用于 dry-run 模式，如果还有未执行的迁移则返回错误
+    pub async fn connect_read_only(url: &str) -> Result<Self> {
+        info!("以只读方式打开数据库：{}", url);
+        let options = SqliteConnectOptions::new().filename(url).read_only(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        let applied: Vec<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+                .fetch_all(&pool)
+                .await
+                .unwrap_or_default();
+        let pending =
+            sqlx::migrate!("./migrations").iter().filter(|m| !applied.contains(&m.version)).count();
+        if pending > 0 {
+            return Err(sqlx::Error::Configuration(
+                format!("数据库有 {} 个未执行的迁移，请先以正常模式启动一次", pending).into(),
+            ));
+        }
+        Ok(Self { pool })
+    }
+
+    /// 创建一个执行过迁移的内存数据库，用于测试
+    pub async fn memory() -> Result<Self> {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")?.foreign_keys(false);
+        // 内存数据库只存在于单个连接中，因此连接池只能保留一个连接，并且不能被回收
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+        Self::migrate(pool).await
+    }
+
+    async fn migrate(pool: SqlitePool) -> Result<Self> {
+        info!("检查数据库迁移");
+        sqlx::migrate!("./migrations").run(&pool).await?;
+        Ok(Self { pool })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn read_only() {
+        let path = std::env::temp_dir().join(format!("exloli-db-{}.sqlite", std::process::id()));
+        let url = path.to_str().unwrap();
+        // 文件不存在时不会被创建
+        assert!(Database::connect_read_only(url).await.is_err());
+        assert!(!path.exists());
+        // 没有执行过迁移的数据库会被拒绝
+        let options = SqliteConnectOptions::new().filename(url).create_if_missing(true);
+        SqlitePoolOptions::new().connect_with(options).await.unwrap().close().await;
+        assert!(Database::connect_read_only(url).await.is_err());
+
+        Database::connect(url).await.unwrap().pool.close().await;
+        let db = Database::connect_read_only(url).await.unwrap();
+        assert!(sqlx::query("DELETE FROM gallery").execute(&db.pool).await.is_err());
+        db.pool.close().await;
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", url, suffix));
+        }
+    }
 }
@@ -3,19 +3,19 @@ use std::ops::Deref;
 use chrono::prelude::*;
 use chrono::Duration;
 use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 use sqlx::database::HasValueRef;
 use sqlx::error::BoxDynError;
 use sqlx::prelude::*;
 use sqlx::sqlite::SqliteQueryResult;
-use sqlx::{Database, Result, Sqlite};
+use sqlx::{Result, Sqlite};
 use tracing::Level;
 
-use super::db::DB;
-use crate::config::CHANNEL_ID;
+use super::db::Database;
 use crate::ehentai::EhGallery;
 
 // 此处使用 IndexMap，因为我们需要保证相同的 tag 每次序列化的结果都是一样的
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TagsEntity(pub IndexMap<String, Vec<String>>);
 
 #[derive(Debug, Clone, FromRow)]
@@ -43,10 +43,21 @@ pub struct GalleryEntity {
     pub posted: Option<NaiveDateTime>,
 }
 
+/// 已经发布到频道的画廊，附带投票分数和文章地址
+#[derive(Debug, FromRow)]
+pub struct PublishedGallery {
+    #[sqlx(flatten)]
+    pub gallery: GalleryEntity,
+    /// 投票分数，为 0~1 的小数，没有投票时为空
+    pub score: Option<f32>,
+    /// telegraph 文章的地址
+    pub preview: Option<String>,
+}
+
 impl GalleryEntity {
     /// 创建一条记录
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn create(g: &EhGallery) -> Result<SqliteQueryResult> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn create(db: &Database, g: &EhGallery) -> Result<SqliteQueryResult> {
         let id = g.url.id();
         let token = g.url.token();
         let tags = serde_json::to_string(&g.tags).unwrap();
@@ -65,64 +76,79 @@ impl GalleryEntity {
             false,
             g.posted,
         )
-            .execute(&*DB)
+            .execute(&db.pool)
             .await
     }
 
     /// 根据 ID 获取一条记录
     ///
     /// 注意，此处不会返回已被标记为删除的记录
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn get(id: i32) -> Result<Option<GalleryEntity>> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get(db: &Database, id: i32) -> Result<Option<GalleryEntity>> {
         sqlx::query_as("SELECT * FROM gallery WHERE id = ? AND deleted = FALSE")
             .bind(id)
-            .fetch_optional(&*DB)
+            .fetch_optional(&db.pool)
             .await
     }
 
     /// 根据消息 ID 获取一条记录
-    pub async fn get_by_msg(id: i32) -> Result<Option<GalleryEntity>> {
+    pub async fn get_by_msg(
+        db: &Database,
+        id: i32,
+        channel_id: &str,
+    ) -> Result<Option<GalleryEntity>> {
         sqlx::query_as(
             "SELECT gallery.* FROM gallery JOIN message ON gallery.id = message.gallery_id AND message.channel_id = ? WHERE message.id = ? AND gallery.deleted = FALSE"
         )
-            .bind(CHANNEL_ID.get().unwrap())
+            .bind(channel_id)
             .bind(id)
-            .fetch_optional(&*DB)
+            .fetch_optional(&db.pool)
             .await
     }
 
     /// 检查画廊是否存在，此处不会考虑删除标记
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn check(id: i32) -> Result<bool> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn check(db: &Database, id: i32) -> Result<bool> {
         sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM gallery WHERE id = ?)", id)
-            .fetch_one(&*DB)
+            .fetch_one(&db.pool)
             .await
             .map(|x| x == Some(1))
     }
 
     /// 根据 ID 更新 tag
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn update_tags(id: i32, tags: &[(String, Vec<String>)]) -> Result<SqliteQueryResult> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn update_tags(
+        db: &Database,
+        id: i32,
+        tags: &[(String, Vec<String>)],
+    ) -> Result<SqliteQueryResult> {
         let tags = serde_json::to_string(tags).unwrap();
-        sqlx::query!("UPDATE gallery SET tags = ? WHERE id = ?", tags, id).execute(&*DB).await
+        sqlx::query!("UPDATE gallery SET tags = ? WHERE id = ?", tags, id).execute(&db.pool).await
     }
 
     /// 根据 ID 更新删除状态
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn update_deleted(id: i32, deleted: bool) -> Result<SqliteQueryResult> {
-        sqlx::query!("UPDATE gallery SET deleted = ? WHERE id = ?", deleted, id).execute(&*DB).await
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn update_deleted(
+        db: &Database,
+        id: i32,
+        deleted: bool,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query!("UPDATE gallery SET deleted = ? WHERE id = ?", deleted, id)
+            .execute(&db.pool)
+            .await
     }
 
     /// 彻底删除一个画廊
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn delete(id: i32) -> Result<SqliteQueryResult> {
-        sqlx::query!("DELETE FROM gallery WHERE id = ?", id).execute(&*DB).await
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn delete(db: &Database, id: i32) -> Result<SqliteQueryResult> {
+        sqlx::query!("DELETE FROM gallery WHERE id = ?", id).execute(&db.pool).await
     }
 
     /// 查询自指定日期以来的本子，结果按分数从高到低排列
     /// 返回 分数、标题、画廊 ID
-    #[tracing::instrument(level = Level::DEBUG)]
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
     pub async fn list(
+        db: &Database,
         start: NaiveDate,
         end: NaiveDate,
         limit: i32,
@@ -142,13 +168,28 @@ impl GalleryEntity {
             limit,
             offset,
         )
-        .fetch_all(&*DB)
+        .fetch_all(&db.pool)
         .await?;
         Ok(record.into_iter().map(|x| (x.score as f32, x.title, x.id as i32)).collect())
     }
 
+    /// 列出所有已经发布到频道且未删除的画廊
+    pub async fn list_published(db: &Database) -> Result<Vec<PublishedGallery>> {
+        sqlx::query_as(
+            r#"SELECT gallery.*, poll.score, telegraph.url AS preview
+            FROM gallery
+            LEFT JOIN poll ON poll.gallery_id = gallery.id
+            LEFT JOIN telegraph ON telegraph.gallery_id = gallery.id
+            WHERE gallery.deleted = FALSE
+                AND EXISTS (SELECT 1 FROM message WHERE message.gallery_id = gallery.id)
+            GROUP BY gallery.id"#,
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
     /// 列出所有 80 分以上或最近两个月上传的画廊
-    pub async fn list_scans() -> Result<Vec<Self>> {
+    pub async fn list_scans(db: &Database) -> Result<Vec<Self>> {
         let since = Utc::now().date_naive() - Duration::days(60);
         sqlx::query_as(
             r#"SELECT gallery.*
@@ -157,7 +198,7 @@ impl GalleryEntity {
             WHERE gallery.deleted = FALSE AND (poll.score >= 0.8 OR gallery.posted >= ?)"#,
         )
         .bind(since)
-        .fetch_all(&*DB)
+        .fetch_all(&db.pool)
         .await
     }
 }
@@ -176,11 +217,11 @@ impl<'q> Decode<'q, Sqlite> for TagsEntity {
 }
 
 impl Type<Sqlite> for TagsEntity {
-    fn type_info() -> <Sqlite as Database>::TypeInfo {
+    fn type_info() -> <Sqlite as sqlx::Database>::TypeInfo {
         <String as Type<Sqlite>>::type_info()
     }
 
-    fn compatible(ty: &<Sqlite as Database>::TypeInfo) -> bool {
+    fn compatible(ty: &<Sqlite as sqlx::Database>::TypeInfo) -> bool {
         <String as Type<Sqlite>>::compatible(ty)
     }
 }
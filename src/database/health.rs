@@ -0,0 +1,152 @@
+use chrono::NaiveDateTime;
+use sqlx::{Result, Type};
+
+use super::db::Database;
+
+/// 链接的检查结果
+#[derive(Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(rename_all = "lowercase")]
+pub enum LinkStatus {
+    /// 还没有检查过
+    Unchecked,
+    /// 可以正常访问
+    Alive,
+    /// 已经失效
+    Dead,
+    /// 检查时出错，无法确定是否失效
+    Error,
+}
+
+/// 各种检查结果的数量
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatusCount {
+    pub unchecked: i64,
+    pub alive: i64,
+    pub dead: i64,
+    pub error: i64,
+}
+
+/// 链接检查的汇总，只统计未删除的画廊
+#[derive(Debug)]
+pub struct HealthSummary {
+    /// telegraph 文章
+    pub articles: StatusCount,
+    /// 图片，不包括广告
+    pub images: StatusCount,
+    /// 最近一次检查的时间
+    pub last_checked: Option<NaiveDateTime>,
+    /// 包含失效图片的画廊，最多列出 20 个
+    pub broken_galleries: Vec<i32>,
+}
+
+impl HealthSummary {
+    pub async fn get(db: &Database) -> Result<Self> {
+        let articles = sqlx::query_as(
+            r#"SELECT telegraph.status, COUNT(*) FROM telegraph
+            JOIN gallery ON gallery.id = telegraph.gallery_id
+            WHERE gallery.deleted = FALSE
+            GROUP BY telegraph.status"#,
+        )
+        .fetch_all(&db.pool)
+        .await?;
+        let images = sqlx::query_as(
+            r#"SELECT image.status, COUNT(*) FROM image
+            WHERE image.ad = FALSE AND EXISTS (
+                SELECT 1 FROM page JOIN gallery ON gallery.id = page.gallery_id
+                WHERE page.image_id = image.id AND gallery.deleted = FALSE
+            )
+            GROUP BY image.status"#,
+        )
+        .fetch_all(&db.pool)
+        .await?;
+        let last_checked = sqlx::query_scalar(
+            "SELECT MAX(last_checked) FROM (SELECT last_checked FROM telegraph UNION ALL SELECT last_checked FROM image)",
+        )
+        .fetch_one(&db.pool)
+        .await?;
+        let broken_galleries = sqlx::query_scalar(
+            r#"SELECT DISTINCT page.gallery_id FROM page
+            JOIN image ON image.id = page.image_id
+            JOIN gallery ON gallery.id = page.gallery_id
+            WHERE image.status = 'dead' AND image.ad = FALSE AND gallery.deleted = FALSE
+            ORDER BY page.gallery_id DESC
+            LIMIT 20"#,
+        )
+        .fetch_all(&db.pool)
+        .await?;
+        Ok(Self {
+            articles: StatusCount::from_rows(articles),
+            images: StatusCount::from_rows(images),
+            last_checked,
+            broken_galleries,
+        })
+    }
+
+    /// 清空所有文章和图片的检查时间，使它们在接下来的几轮中被重新检查
+    pub async fn reset(db: &Database) -> Result<()> {
+        sqlx::query("UPDATE telegraph SET last_checked = NULL").execute(&db.pool).await?;
+        sqlx::query("UPDATE image SET last_checked = NULL").execute(&db.pool).await?;
+        Ok(())
+    }
+}
+
+impl StatusCount {
+    fn from_rows(rows: Vec<(LinkStatus, i64)>) -> Self {
+        let mut count = Self::default();
+        for (status, n) in rows {
+            match status {
+                LinkStatus::Unchecked => count.unchecked += n,
+                LinkStatus::Alive => count.alive += n,
+                LinkStatus::Dead => count.dead += n,
+                LinkStatus::Error => count.error += n,
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::database::{ImageEntity, PageEntity, TelegraphEntity};
+    use crate::host::HostKind;
+
+    #[tokio::test]
+    async fn rolling_check() {
+        let db = Database::memory().await.unwrap();
+        sqlx::query(
+            "INSERT INTO gallery (id, token, title, tags, pages, deleted) VALUES (1, 't', 'a', '', 2, FALSE)",
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        TelegraphEntity::create(&db, 1, "default", &["https://telegra.ph/a".into()]).await.unwrap();
+        for (id, hash) in [(1, "a"), (2, "b")] {
+            ImageEntity::create(&db, id, hash, "https://i", HostKind::Catbox, None).await.unwrap();
+            PageEntity::create(&db, 1, id as i32, id).await.unwrap();
+        }
+
+        let before = Utc::now().naive_utc() - Duration::days(7);
+        assert_eq!(TelegraphEntity::list_unchecked(&db, before, 10).await.unwrap().len(), 1);
+        let images = ImageEntity::list_unchecked(&db, before, 10).await.unwrap();
+        assert_eq!(images.len(), 2);
+
+        // 检查过的链接在 recheck_after 之内不会再被列出
+        TelegraphEntity::update_status(&db, 1, LinkStatus::Alive).await.unwrap();
+        ImageEntity::update_status(&db, 2, LinkStatus::Dead).await.unwrap();
+        assert!(TelegraphEntity::list_unchecked(&db, before, 10).await.unwrap().is_empty());
+        let images = ImageEntity::list_unchecked(&db, before, 10).await.unwrap();
+        assert_eq!(images.iter().map(|img| img.id).collect::<Vec<_>>(), [1]);
+
+        let summary = HealthSummary::get(&db).await.unwrap();
+        assert_eq!((summary.articles.alive, summary.articles.unchecked), (1, 0));
+        assert_eq!((summary.images.dead, summary.images.unchecked), (1, 1));
+        assert_eq!(summary.broken_galleries, [1]);
+        assert!(summary.last_checked.is_some());
+
+        HealthSummary::reset(&db).await.unwrap();
+        assert_eq!(TelegraphEntity::list_unchecked(&db, before, 10).await.unwrap().len(), 1);
+    }
+}
@@ -1,8 +1,11 @@
+use chrono::{NaiveDateTime, Utc};
 use sqlx::sqlite::SqliteQueryResult;
 use sqlx::Result;
 use tracing::Level;
 
-use super::db::DB;
+use super::db::Database;
+use super::health::LinkStatus;
+use crate::host::HostKind;
 
 #[derive(sqlx::FromRow, Debug)]
 pub struct PageEntity {
@@ -20,41 +23,89 @@ pub struct ImageEntity {
     pub id: u32,
     /// 图片的 sha1sum 前 10 位
     pub hash: String,
-    /// 相对 https://telegra.ph 的图片 URL
+    /// 图片 URL，如果是 telegraph 图床，则为相对 https://telegra.ph 的 URL
     url: String,
+    /// 图片所在的图床
+    pub host: HostKind,
+    /// 图片的感知哈希（dHash），旧图片可能为空
+    pub phash: Option<i64>,
+    /// 是否被判断为广告
+    pub ad: bool,
+}
+
+/// 画廊中的一页，以及这一页对应的图片
+#[derive(sqlx::FromRow, Debug)]
+pub struct PageImage {
+    /// 页面编号
+    pub page: i32,
+    #[sqlx(flatten)]
+    pub image: ImageEntity,
+}
+
+impl PageImage {
+    /// 获取指定画廊的所有页面和对应的图片，按页码排列
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get_by_gallery_id(db: &Database, gallery_id: i32) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"SELECT page.page, image.* FROM page
+            JOIN image ON page.image_id = image.id
+            WHERE page.gallery_id = ?
+            ORDER BY page.page"#,
+        )
+        .bind(gallery_id)
+        .fetch_all(&db.pool)
+        .await
+    }
 }
 
 impl ImageEntity {
     /// 创建一条记录
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn create(id: u32, hash: &str, url: &str) -> Result<SqliteQueryResult> {
-        sqlx::query!("INSERT OR IGNORE INTO image (id, hash, url) VALUES (?, ?, ?)", id, hash, url)
-            .execute(&*DB)
-            .await
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn create(
+        db: &Database,
+        id: u32,
+        hash: &str,
+        url: &str,
+        host: HostKind,
+        phash: Option<i64>,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO image (id, hash, url, host, phash) VALUES (?, ?, ?, ?, ?)",
+            id,
+            hash,
+            url,
+            host,
+            phash
+        )
+        .execute(&db.pool)
+        .await
     }
 
     /// 根据图片 hash 获取一张图片
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn get_by_hash(hash: &str) -> Result<Option<Self>> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get_by_hash(db: &Database, hash: &str) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            r#"SELECT id as "id: u32", hash, url FROM image WHERE hash = ?"#,
+            r#"SELECT id as "id: u32", hash, url, host as "host: HostKind", phash, ad FROM image WHERE hash = ?"#,
             hash
         )
-        .fetch_optional(&*DB)
+        .fetch_optional(&db.pool)
         .await
     }
 
     /// 获取指定画廊的所有图片，并且按页码排列
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn get_by_gallery_id(gallery_id: i32) -> Result<Vec<Self>> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get_by_gallery_id(db: &Database, gallery_id: i32) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
             r#"
             SELECT
                 image.id as "id: u32",
                 image.hash as hash,
-                image.url as url
+                image.url as url,
+                image.host as "host: HostKind",
+                image.phash,
+                image.ad
             FROM image
             JOIN page ON page.image_id = image.id
             WHERE page.gallery_id = ?
@@ -62,37 +113,253 @@ impl ImageEntity {
             "#,
             gallery_id,
         )
-        .fetch_all(&*DB)
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// 查找与指定感知哈希的汉明距离不超过 max_distance 的图片，结果按距离从近到远排列
+    ///
+    /// 注意：由于索引的设计，max_distance 超过 3 时结果可能不完整
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn find_similar(
+        db: &Database,
+        phash: i64,
+        max_distance: u32,
+    ) -> Result<Vec<(Self, u32)>> {
+        let bands =
+            [(phash >> 48) & 65535, (phash >> 32) & 65535, (phash >> 16) & 65535, phash & 65535];
+        let candidates = sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id as "id: u32", hash, url, host as "host: HostKind", phash, ad
+            FROM image
+            WHERE (phash >> 48) & 65535 = ?
+                OR (phash >> 32) & 65535 = ?
+                OR (phash >> 16) & 65535 = ?
+                OR phash & 65535 = ?
+            "#,
+            bands[0],
+            bands[1],
+            bands[2],
+            bands[3],
+        )
+        .fetch_all(&db.pool)
+        .await?;
+        let mut result = candidates
+            .into_iter()
+            .filter_map(|img| {
+                let distance = (img.phash? ^ phash).count_ones();
+                (distance <= max_distance).then_some((img, distance))
+            })
+            .collect::<Vec<_>>();
+        result.sort_by_key(|(_, distance)| *distance);
+        Ok(result)
+    }
+
+    /// 将指定图片标记为广告
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn mark_ad(db: &Database, ids: &[u32]) -> Result<SqliteQueryResult> {
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let sql = format!("UPDATE image SET ad = TRUE WHERE id IN ({})", placeholders);
+        let mut query = sqlx::query(&sql);
+        for id in ids {
+            query = query.bind(id);
+        }
+        query.execute(&db.pool).await
+    }
+
+    /// 列出未删除的画廊中，在 before 之后没有检查过的图片，不包括广告，最久没有检查的排在前面
+    pub async fn list_unchecked(
+        db: &Database,
+        before: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"SELECT image.* FROM image
+            WHERE image.ad = FALSE
+                AND (image.last_checked IS NULL OR image.last_checked < ?)
+                AND EXISTS (
+                    SELECT 1 FROM page JOIN gallery ON gallery.id = page.gallery_id
+                    WHERE page.image_id = image.id AND gallery.deleted = FALSE
+                )
+            ORDER BY image.last_checked
+            LIMIT ?"#,
+        )
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// 记录图片的检查结果
+    pub async fn update_status(
+        db: &Database,
+        id: u32,
+        status: LinkStatus,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query("UPDATE image SET status = ?, last_checked = ? WHERE id = ?")
+            .bind(status)
+            .bind(Utc::now().naive_utc())
+            .bind(id)
+            .execute(&db.pool)
+            .await
+    }
+
+    /// 图片重新上传之后，更新图片的 URL 和所在的图床，并标记为正常
+    pub async fn update_url(
+        db: &Database,
+        id: u32,
+        url: &str,
+        host: HostKind,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query(
+            "UPDATE image SET url = ?, host = ?, status = ?, last_checked = ? WHERE id = ?",
+        )
+        .bind(url)
+        .bind(host)
+        .bind(LinkStatus::Alive)
+        .bind(Utc::now().naive_utc())
+        .bind(id)
+        .execute(&db.pool)
         .await
     }
 
+    /// 数据库中记录的原始 URL，telegraph 图床的 URL 是相对路径
+    pub fn raw_url(&self) -> &str {
+        &self.url
+    }
+
     pub fn url(&self) -> String {
-        if self.url.starts_with("/file/") {
-            format!("https://telegra.ph{}", self.url)
-        } else {
-            self.url.clone()
+        match self.host {
+            HostKind::Telegraph => format!("https://telegra.ph{}", self.url),
+            _ => self.url.clone(),
         }
     }
 }
 
 impl PageEntity {
     /// 创建一条记录，有冲突时则忽略
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn create(gallery_id: i32, page: i32, image_id: u32) -> Result<SqliteQueryResult> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn create(
+        db: &Database,
+        gallery_id: i32,
+        page: i32,
+        image_id: u32,
+    ) -> Result<SqliteQueryResult> {
         sqlx::query!(
             "INSERT OR IGNORE INTO page (gallery_id, page, image_id) VALUES (?, ?, ?)",
             gallery_id,
             page,
             image_id
         )
-        .execute(&*DB)
+        .execute(&db.pool)
         .await
     }
 
+    /// 统计包含指定图片的画廊数量
+    pub async fn count_galleries(db: &Database, image_ids: &[u32]) -> Result<i64> {
+        let placeholders = vec!["?"; image_ids.len()].join(", ");
+        let sql = format!(
+            "SELECT COUNT(DISTINCT gallery_id) FROM page WHERE image_id IN ({})",
+            placeholders
+        );
+        let mut query = sqlx::query_scalar(&sql);
+        for id in image_ids {
+            query = query.bind(id);
+        }
+        query.fetch_one(&db.pool).await
+    }
+
+    /// 列出包含指定图片的画廊
+    pub async fn list_galleries(db: &Database, image_ids: &[u32]) -> Result<Vec<i32>> {
+        let placeholders = vec!["?"; image_ids.len()].join(", ");
+        let sql =
+            format!("SELECT DISTINCT gallery_id FROM page WHERE image_id IN ({})", placeholders);
+        let mut query = sqlx::query_scalar(&sql);
+        for id in image_ids {
+            query = query.bind(id);
+        }
+        query.fetch_all(&db.pool).await
+    }
+
+    /// 列出某个画廊已有记录的页面编号
+    pub async fn list_pages(db: &Database, gallery_id: i32) -> Result<Vec<i32>> {
+        sqlx::query_scalar("SELECT page FROM page WHERE gallery_id = ?")
+            .bind(gallery_id)
+            .fetch_all(&db.pool)
+            .await
+    }
+
+    /// 删除某个画廊中除了 keep 以外的页面记录
+    #[tracing::instrument(level = Level::DEBUG, skip(db, keep))]
+    pub async fn delete_except(
+        db: &Database,
+        gallery_id: i32,
+        keep: &[i32],
+    ) -> Result<SqliteQueryResult> {
+        let mut sql = "DELETE FROM page WHERE gallery_id = ?".to_string();
+        if !keep.is_empty() {
+            let placeholders = vec!["?"; keep.len()].join(", ");
+            sql.push_str(&format!(" AND page NOT IN ({})", placeholders));
+        }
+        let mut query = sqlx::query(&sql).bind(gallery_id);
+        for page in keep {
+            query = query.bind(page);
+        }
+        query.execute(&db.pool).await
+    }
+
     /// 统计某个画廊的有记录页面数量
-    pub async fn count(gallery_id: i32) -> Result<i32> {
+    pub async fn count(db: &Database, gallery_id: i32) -> Result<i32> {
         sqlx::query_scalar!("SELECT COUNT(*) FROM page WHERE gallery_id = ?", gallery_id)
-            .fetch_one(&*DB)
+            .fetch_one(&db.pool)
+            .await
+    }
+}
+
+#[cfg(test)]
+impl PageEntity {
+    /// 测试用：按顺序记录画廊的第 1、2、3…… 页，图片 ID 与页码相同，哈希为 `hash{页码}`
+    pub(crate) async fn seed(
+        db: &Database,
+        gallery_id: i32,
+        urls: impl IntoIterator<Item = String>,
+    ) {
+        for (page, url) in (1..).zip(urls) {
+            let hash = format!("hash{page}");
+            ImageEntity::create(db, page, &hash, &url, HostKind::Catbox, None).await.unwrap();
+            PageEntity::create(db, gallery_id, page as i32, page).await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn similar_and_pages() {
+        let db = Database::memory().await.unwrap();
+        let phash = 0x0123_4567_89ab_cdef;
+        ImageEntity::create(&db, 1, "a", "https://a", HostKind::Catbox, Some(phash)).await.unwrap();
+        ImageEntity::create(&db, 2, "b", "https://b", HostKind::Catbox, Some(phash ^ 0b101))
+            .await
+            .unwrap();
+        ImageEntity::create(&db, 3, "c", "https://c", HostKind::Catbox, Some(!phash))
             .await
+            .unwrap();
+        let similar = ImageEntity::find_similar(&db, phash, 2).await.unwrap();
+        let similar = similar.iter().map(|(img, d)| (img.id, *d)).collect::<Vec<_>>();
+        assert_eq!(similar, [(1, 0), (2, 2)]);
+
+        for (page, image) in [(1, 1), (2, 2), (3, 3)] {
+            PageEntity::create(&db, 10, page, image).await.unwrap();
+        }
+        PageEntity::create(&db, 11, 1, 1).await.unwrap();
+        assert_eq!(PageEntity::count_galleries(&db, &[1, 2]).await.unwrap(), 2);
+        PageEntity::delete_except(&db, 10, &[1, 3]).await.unwrap();
+        assert_eq!(PageEntity::list_pages(&db, 10).await.unwrap(), [1, 3]);
+        let images = ImageEntity::get_by_gallery_id(&db, 10).await.unwrap();
+        assert_eq!(images.iter().map(|img| img.id).collect::<Vec<_>>(), [1, 3]);
     }
 }
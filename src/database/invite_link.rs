@@ -2,8 +2,7 @@ use chrono::{NaiveDateTime, Utc};
 use sqlx::sqlite::SqliteQueryResult;
 use sqlx::Result;
 
-use super::db::DB;
-use crate::config::CHANNEL_ID;
+use super::db::Database;
 
 #[derive(sqlx::FromRow, Debug)]
 pub struct InviteLink {
@@ -14,8 +13,12 @@ pub struct InviteLink {
 }
 
 impl InviteLink {
-    pub async fn create(user_id: i64, link: &str) -> Result<SqliteQueryResult> {
-        let channel_id = CHANNEL_ID.get().unwrap();
+    pub async fn create(
+        db: &Database,
+        user_id: i64,
+        channel_id: &str,
+        link: &str,
+    ) -> Result<SqliteQueryResult> {
         let now = Utc::now().naive_utc();
         sqlx::query!(
             "INSERT INTO invite_link (user_id, chat_id, link, created_at) VALUES (?, ?, ?, ?)",
@@ -24,14 +27,13 @@ impl InviteLink {
             link,
             now,
         )
-        .execute(&*DB)
+        .execute(&db.pool)
         .await
     }
 
-    pub async fn get(user_id: i64) -> Result<Option<InviteLink>> {
-        let channel_id = CHANNEL_ID.get().unwrap();
+    pub async fn get(db: &Database, user_id: i64, channel_id: &str) -> Result<Option<InviteLink>> {
         sqlx::query_as!(InviteLink, "SELECT * FROM invite_link WHERE user_id = ? AND chat_id = ? ORDER BY created_at DESC LIMIT 1", user_id, channel_id)
-            .fetch_optional(&*DB)
+            .fetch_optional(&db.pool)
             .await
     }
 }
@@ -0,0 +1,395 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::{Result, Type};
+use tracing::Level;
+
+use super::db::Database;
+use crate::ehentai::EhGalleryUrl;
+
+/// 上传任务的状态
+#[derive(Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// 等待执行
+    Queued,
+    /// 正在下载并上传图片
+    Downloading,
+    /// 正在发布文章
+    Publishing,
+    /// 已上传完毕，等待发布到频道
+    Ready,
+    /// 已完成
+    Done,
+    /// 重试次数用尽，已放弃
+    Failed,
+    /// 不满足频道的筛选规则，在 run_after 之前不会再次加入队列
+    Skipped,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct JobEntity {
+    /// 任务 ID
+    pub id: i64,
+    /// 画廊 ID
+    pub gallery_id: i32,
+    /// 画廊 URL
+    pub url: String,
+    /// 发布到的频道
+    pub channel_id: String,
+    /// 是否跳过「已上传」检查
+    pub force: bool,
+    /// 任务状态
+    pub status: JobStatus,
+    /// 已尝试的次数
+    pub attempts: i32,
+    /// 最后一次失败的原因
+    pub last_error: Option<String>,
+    /// 在此时间之前不会被执行
+    pub run_after: NaiveDateTime,
+    /// 在发布队列中的顺序，越小越先发布
+    pub position: i64,
+    /// 创建时间
+    pub created_at: NaiveDateTime,
+    /// 最后更新时间
+    pub updated_at: NaiveDateTime,
+}
+
+impl JobEntity {
+    /// 添加一个任务，如果该画廊在该频道已经有未完成的任务，则直接返回该任务的 ID
+    ///
+    /// 被跳过的任务不算作未完成，调用者需要先用 is_skipped 检查
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn create(
+        db: &Database,
+        url: &EhGalleryUrl,
+        channel_id: &str,
+        force: bool,
+    ) -> Result<i64> {
+        let gallery_id = url.id();
+        let now = Utc::now().naive_utc();
+        // job_active_idx 保证同时添加同一个画廊时只有一个任务会被插入
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"INSERT INTO job (gallery_id, url, channel_id, force, status, run_after, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT DO NOTHING
+            RETURNING id"#,
+        )
+        .bind(gallery_id)
+        .bind(url.url())
+        .bind(channel_id)
+        .bind(force)
+        .bind(JobStatus::Queued)
+        .bind(now)
+        .bind(now)
+        .bind(now)
+        .fetch_optional(&db.pool)
+        .await?;
+        if let Some(id) = id {
+            return Ok(id);
+        }
+        sqlx::query_scalar(
+            "SELECT id FROM job WHERE gallery_id = ? AND channel_id = ? AND status NOT IN ('done', 'failed', 'skipped')",
+        )
+        .bind(gallery_id)
+        .bind(channel_id)
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    /// 根据 ID 获取任务
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get(db: &Database, id: i64) -> Result<Option<Self>> {
+        sqlx::query_as("SELECT * FROM job WHERE id = ?").bind(id).fetch_optional(&db.pool).await
+    }
+
+    /// 领取一个可以执行的任务，并将其标记为下载中
+    ///
+    /// 同一个画廊在多个频道的任务共用图片和文章，因此不会同时执行
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn claim(db: &Database) -> Result<Option<Self>> {
+        let now = Utc::now().naive_utc();
+        sqlx::query_as(
+            r#"UPDATE job SET status = 'downloading', attempts = attempts + 1, updated_at = ?
+            WHERE id = (
+                SELECT id FROM job WHERE status = 'queued' AND run_after <= ? AND gallery_id NOT IN (
+                    SELECT gallery_id FROM job WHERE status IN ('downloading', 'publishing')
+                )
+                ORDER BY id LIMIT 1
+            )
+            RETURNING *"#,
+        )
+        .bind(now)
+        .bind(now)
+        .fetch_optional(&db.pool)
+        .await
+    }
+
+    /// 更新指定画廊正在执行的任务的状态
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn update_status(
+        db: &Database,
+        gallery_id: i32,
+        status: JobStatus,
+    ) -> Result<SqliteQueryResult> {
+        let now = Utc::now().naive_utc();
+        sqlx::query(
+            "UPDATE job SET status = ?, updated_at = ? WHERE gallery_id = ? AND status IN ('downloading', 'publishing')",
+        )
+        .bind(status)
+        .bind(now)
+        .bind(gallery_id)
+        .execute(&db.pool)
+        .await
+    }
+
+    /// 将任务标记为已完成
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn done(db: &Database, id: i64) -> Result<SqliteQueryResult> {
+        let now = Utc::now().naive_utc();
+        sqlx::query("UPDATE job SET status = ?, updated_at = ? WHERE id = ?")
+            .bind(JobStatus::Done)
+            .bind(now)
+            .bind(id)
+            .execute(&db.pool)
+            .await
+    }
+
+    /// 将任务标记为等待发布，排在所属频道发布队列的末尾
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn ready(db: &Database, id: i64) -> Result<SqliteQueryResult> {
+        let now = Utc::now().naive_utc();
+        sqlx::query(
+            r#"UPDATE job SET status = ?, updated_at = ?,
+            position = (
+                SELECT IFNULL(MAX(position), 0) + 1 FROM job AS other
+                WHERE other.status = 'ready' AND other.channel_id = job.channel_id
+            )
+            WHERE id = ?"#,
+        )
+        .bind(JobStatus::Ready)
+        .bind(now)
+        .bind(id)
+        .execute(&db.pool)
+        .await
+    }
+
+    /// 按发布顺序列出指定频道所有等待发布的任务
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn list_ready(db: &Database, channel_id: &str) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            "SELECT * FROM job WHERE status = 'ready' AND channel_id = ? ORDER BY position, id",
+        )
+        .bind(channel_id)
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// 按照 ids 的顺序重新设置发布顺序
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn reorder(db: &Database, ids: &[i64]) -> Result<()> {
+        let mut tx = db.pool.begin().await?;
+        for (position, id) in ids.iter().enumerate() {
+            sqlx::query("UPDATE job SET position = ? WHERE id = ?")
+                .bind(position as i64 + 1)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    /// 记录一次失败，如果 retry 为 true，则在 delay 之后重新排队，否则标记为失败
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn fail(
+        db: &Database,
+        id: i64,
+        error: &str,
+        retry: bool,
+        delay: Duration,
+    ) -> Result<SqliteQueryResult> {
+        let now = Utc::now().naive_utc();
+        let status = if retry { JobStatus::Queued } else { JobStatus::Failed };
+        sqlx::query(
+            "UPDATE job SET status = ?, last_error = ?, run_after = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(status)
+        .bind(error)
+        .bind(now + delay)
+        .bind(now)
+        .bind(id)
+        .execute(&db.pool)
+        .await
+    }
+
+    /// 记录一次发布失败，任务留在发布队列中，并在 delay 之后再次尝试发布
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn retry_publish(
+        db: &Database,
+        id: i64,
+        error: &str,
+        delay: Duration,
+    ) -> Result<SqliteQueryResult> {
+        let now = Utc::now().naive_utc();
+        sqlx::query(
+            "UPDATE job SET attempts = attempts + 1, last_error = ?, run_after = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(error)
+        .bind(now + delay)
+        .bind(now)
+        .bind(id)
+        .execute(&db.pool)
+        .await
+    }
+
+    /// 记录画廊因为筛选规则被跳过，在 until 之前 is_skipped 会返回 true
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn skip(
+        db: &Database,
+        id: i64,
+        rule: &str,
+        until: NaiveDateTime,
+    ) -> Result<SqliteQueryResult> {
+        let now = Utc::now().naive_utc();
+        sqlx::query(
+            "UPDATE job SET status = ?, last_error = ?, run_after = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(JobStatus::Skipped)
+        .bind(rule)
+        .bind(until)
+        .bind(now)
+        .bind(id)
+        .execute(&db.pool)
+        .await
+    }
+
+    /// 检查画廊最近是否因为筛选规则在该频道被跳过
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn is_skipped(db: &Database, gallery_id: i32, channel_id: &str) -> Result<bool> {
+        let now = Utc::now().naive_utc();
+        sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM job WHERE gallery_id = ? AND channel_id = ? AND status = 'skipped' AND run_after > ?)",
+        )
+        .bind(gallery_id)
+        .bind(channel_id)
+        .bind(now)
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    /// 取消指定画廊还在排队或等待发布的任务，返回是否有任务被取消
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn cancel_pending(db: &Database, gallery_id: i32) -> Result<bool> {
+        let now = Utc::now().naive_utc();
+        sqlx::query(
+            "UPDATE job SET status = 'failed', last_error = '已取消', updated_at = ? WHERE gallery_id = ? AND status IN ('queued', 'ready')",
+        )
+        .bind(now)
+        .bind(gallery_id)
+        .execute(&db.pool)
+        .await
+        .map(|r| r.rows_affected() > 0)
+    }
+
+    /// 将上次运行时中断的任务重新放回队列，返回恢复的任务数量
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn resume(db: &Database) -> Result<u64> {
+        let now = Utc::now().naive_utc();
+        sqlx::query(
+            "UPDATE job SET status = 'queued', updated_at = ? WHERE status IN ('downloading', 'publishing')",
+        )
+        .bind(now)
+        .execute(&db.pool)
+        .await
+        .map(|r| r.rows_affected())
+    }
+
+    /// 将没有记录频道的旧任务分配给指定频道，返回分配的任务数量
+    ///
+    /// 该频道已经有同一个画廊的未完成任务时，旧任务保持不变
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn adopt(db: &Database, channel_id: &str) -> Result<u64> {
+        sqlx::query("UPDATE OR IGNORE job SET channel_id = ? WHERE channel_id = ''")
+            .bind(channel_id)
+            .execute(&db.pool)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    /// 列出所有未完成的任务
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn list_active(db: &Database) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            "SELECT * FROM job WHERE status NOT IN ('done', 'failed', 'skipped') ORDER BY id",
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(jobs: Vec<JobEntity>) -> Vec<i64> {
+        jobs.into_iter().map(|job| job.id).collect()
+    }
+
+    #[tokio::test]
+    async fn publish_queue() {
+        let db = Database::memory().await.unwrap();
+        let a = "https://exhentai.org/g/1/aaaaaaaaaa/".parse().unwrap();
+        let b = "https://exhentai.org/g/2/bbbbbbbbbb/".parse().unwrap();
+        let x = JobEntity::create(&db, &a, "@x", false).await.unwrap();
+        assert_eq!(JobEntity::create(&db, &a, "@x", false).await.unwrap(), x);
+        let y = JobEntity::create(&db, &a, "@y", false).await.unwrap();
+        let z = JobEntity::create(&db, &b, "@x", false).await.unwrap();
+        assert_ne!(x, y);
+
+        // 同一个画廊在另一个频道的任务需要等待前一个任务上传完毕
+        assert_eq!(JobEntity::claim(&db).await.unwrap().unwrap().id, x);
+        assert_eq!(JobEntity::claim(&db).await.unwrap().unwrap().id, z);
+        assert!(JobEntity::claim(&db).await.unwrap().is_none());
+
+        JobEntity::ready(&db, z).await.unwrap();
+        JobEntity::ready(&db, x).await.unwrap();
+        assert_eq!(ids(JobEntity::list_ready(&db, "@x").await.unwrap()), [z, x]);
+        assert!(JobEntity::list_ready(&db, "@y").await.unwrap().is_empty());
+        JobEntity::reorder(&db, &[x, z]).await.unwrap();
+        assert_eq!(ids(JobEntity::list_ready(&db, "@x").await.unwrap()), [x, z]);
+
+        assert_eq!(JobEntity::claim(&db).await.unwrap().unwrap().id, y);
+        JobEntity::ready(&db, y).await.unwrap();
+        assert_eq!(JobEntity::list_ready(&db, "@y").await.unwrap()[0].position, 1);
+    }
+
+    #[tokio::test]
+    async fn skip() {
+        let db = Database::memory().await.unwrap();
+        let url = "https://exhentai.org/g/1/aaaaaaaaaa/".parse().unwrap();
+        let now = Utc::now().naive_utc();
+        let x = JobEntity::create(&db, &url, "@x", false).await.unwrap();
+        JobEntity::skip(&db, x, "min_favorites = 50 (10)", now + Duration::days(1)).await.unwrap();
+        assert!(JobEntity::is_skipped(&db, 1, "@x").await.unwrap());
+        assert!(!JobEntity::is_skipped(&db, 1, "@y").await.unwrap());
+        assert!(JobEntity::list_active(&db).await.unwrap().is_empty());
+
+        // 过期后可以重新加入队列
+        let y = JobEntity::create(&db, &url, "@x", false).await.unwrap();
+        assert_ne!(x, y);
+        JobEntity::skip(&db, y, "min_age", now - Duration::minutes(1)).await.unwrap();
+        JobEntity::skip(&db, x, "min_age", now - Duration::minutes(1)).await.unwrap();
+        assert!(!JobEntity::is_skipped(&db, 1, "@x").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn create_once() {
+        let db = Database::memory().await.unwrap();
+        let url = "https://exhentai.org/g/1/aaaaaaaaaa/".parse().unwrap();
+        let create = || JobEntity::create(&db, &url, "@x", false);
+        let (a, b, c) = tokio::join!(create(), create(), create());
+        let id = a.unwrap();
+        assert_eq!((b.unwrap(), c.unwrap()), (id, id));
+        assert_eq!(JobEntity::list_active(&db).await.unwrap().len(), 1);
+        assert_ne!(JobEntity::create(&db, &url, "@y", false).await.unwrap(), id);
+    }
+}
@@ -1,10 +1,21 @@
 use chrono::{NaiveDate, Utc};
 use sqlx::sqlite::SqliteQueryResult;
-use sqlx::Result;
+use sqlx::{Result, Type};
 use tracing::Level;
 
-use super::db::DB;
-use crate::config::CHANNEL_ID;
+use super::db::Database;
+
+/// 频道消息的类型，决定了编辑时使用的接口
+#[derive(Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(rename_all = "snake_case")]
+pub enum MessageKind {
+    /// 纯文本消息
+    Text,
+    /// 带说明的封面图片
+    Photo,
+    /// 图片组，说明在第一张图片上，记录的是第一张图片的消息 ID
+    MediaGroup,
+}
 
 #[derive(sqlx::FromRow, Debug)]
 pub struct MessageEntity {
@@ -16,28 +27,35 @@ pub struct MessageEntity {
     pub gallery_id: i32,
     /// 消息发布日期
     pub publish_date: NaiveDate,
+    /// 消息类型
+    pub kind: MessageKind,
 }
 
 impl MessageEntity {
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn create(id: i32, gid: i32) -> Result<SqliteQueryResult> {
-        let channel_id = CHANNEL_ID.get().unwrap();
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn create(
+        db: &Database,
+        id: i32,
+        channel_id: &str,
+        gid: i32,
+        kind: MessageKind,
+    ) -> Result<SqliteQueryResult> {
         let now = Utc::now().date_naive();
         sqlx::query!(
-            "INSERT INTO message (id, channel_id, gallery_id, publish_date) VALUES (?, ?, ?, ?)",
+            "INSERT INTO message (id, channel_id, gallery_id, publish_date, kind) VALUES (?, ?, ?, ?, ?)",
             id,
             channel_id,
             gid,
             now,
+            kind,
         )
-        .execute(&*DB)
+        .execute(&db.pool)
         .await
     }
 
     // TODO: 如果存在与否不重要，其实不需要返回 Option，否则反而不方便上抛错误
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn get(id: i32) -> Result<Option<MessageEntity>> {
-        let channel_id = CHANNEL_ID.get().unwrap();
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get(db: &Database, id: i32, channel_id: &str) -> Result<Option<MessageEntity>> {
         sqlx::query_as!(
             MessageEntity,
             r#"
@@ -45,27 +63,30 @@ impl MessageEntity {
                 id as "id: i32",
                 channel_id,
                 gallery_id as "gallery_id: i32",
-                publish_date
+                publish_date,
+                kind as "kind: MessageKind"
             FROM message WHERE id = ? AND channel_id = ?
             "#,
             id,
             channel_id,
         )
-        .fetch_optional(&*DB)
+        .fetch_optional(&db.pool)
         .await
     }
 
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn delete(id: i32) -> Result<SqliteQueryResult> {
-        let channel_id = CHANNEL_ID.get().unwrap();
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn delete(db: &Database, id: i32, channel_id: &str) -> Result<SqliteQueryResult> {
         sqlx::query!("DELETE FROM message WHERE id = ? AND channel_id = ?", id, channel_id)
-            .execute(&*DB)
+            .execute(&db.pool)
             .await
     }
 
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn get_by_gallery(gid: i32) -> Result<Option<MessageEntity>> {
-        let channel_id = CHANNEL_ID.get().unwrap();
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get_by_gallery(
+        db: &Database,
+        gid: i32,
+        channel_id: &str,
+    ) -> Result<Option<MessageEntity>> {
         sqlx::query_as!(
             MessageEntity,
             r#"
@@ -73,7 +94,8 @@ impl MessageEntity {
                 id as "id: i32",
                 channel_id,
                 gallery_id as "gallery_id: i32",
-                publish_date
+                publish_date,
+                kind as "kind: MessageKind"
             FROM message
             WHERE gallery_id = ? AND channel_id = ?
             ORDER BY publish_date DESC
@@ -81,7 +103,69 @@ impl MessageEntity {
             gid,
             channel_id
         )
-        .fetch_optional(&*DB)
+        .fetch_optional(&db.pool)
         .await
     }
+
+    /// 获取指定画廊的最新版本（子画廊、子画廊的子画廊……中 ID 最大的一个）在频道中的消息
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get_newest_version(
+        db: &Database,
+        gid: i32,
+        channel_id: &str,
+    ) -> Result<Option<MessageEntity>> {
+        sqlx::query_as!(
+            MessageEntity,
+            r#"
+            WITH RECURSIVE version(id) AS (
+                SELECT id FROM gallery WHERE parent = ?
+                UNION
+                SELECT gallery.id FROM gallery JOIN version ON gallery.parent = version.id
+            )
+            SELECT
+                message.id as "id!: i32",
+                message.channel_id as "channel_id!",
+                message.gallery_id as "gallery_id!: i32",
+                message.publish_date as "publish_date!",
+                message.kind as "kind!: MessageKind"
+            FROM message
+            JOIN version ON message.gallery_id = version.id
+            WHERE message.channel_id = ?
+            ORDER BY message.gallery_id DESC
+            "#,
+            gid,
+            channel_id
+        )
+        .fetch_optional(&db.pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::GalleryEntity;
+    use crate::ehentai::EhGallery;
+
+    fn gallery(id: i32, parent: Option<i32>) -> EhGallery {
+        EhGallery { parent: parent.map(|p| EhGallery::mock(p, 0).url), ..EhGallery::mock(id, 0) }
+    }
+
+    #[tokio::test]
+    async fn channels() {
+        let db = Database::memory().await.unwrap();
+        GalleryEntity::create(&db, &gallery(1, None)).await.unwrap();
+        GalleryEntity::create(&db, &gallery(2, Some(1))).await.unwrap();
+        MessageEntity::create(&db, 10, "@x", 1, MessageKind::Text).await.unwrap();
+        MessageEntity::create(&db, 11, "@y", 1, MessageKind::Text).await.unwrap();
+        MessageEntity::create(&db, 20, "@x", 2, MessageKind::Photo).await.unwrap();
+
+        assert_eq!(MessageEntity::get_by_gallery(&db, 1, "@y").await.unwrap().unwrap().id, 11);
+        assert!(MessageEntity::get(&db, 20, "@y").await.unwrap().is_none());
+        let newest = MessageEntity::get_newest_version(&db, 1, "@x").await.unwrap().unwrap();
+        assert_eq!((newest.id, newest.kind), (20, MessageKind::Photo));
+        assert!(MessageEntity::get_newest_version(&db, 1, "@y").await.unwrap().is_none());
+        assert_eq!(GalleryEntity::get_by_msg(&db, 11, "@y").await.unwrap().unwrap().id, 1);
+        assert!(GalleryEntity::get_by_msg(&db, 11, "@x").await.unwrap().is_none());
+    }
 }
@@ -1,16 +1,25 @@
+mod archive;
+mod catbox_album;
 mod challenge;
 mod db;
 mod gallery;
+mod health;
 mod image;
 mod invite_link;
+mod job;
 mod message;
 mod poll;
 mod telegraph;
 
+pub use archive::{export, import, ArchiveStats, OnConflict, ARCHIVE_VERSION};
+pub use catbox_album::*;
 pub use challenge::*;
+pub use db::Database;
 pub use gallery::*;
+pub use health::*;
 pub use image::*;
 pub use invite_link::*;
+pub use job::*;
 pub use message::*;
 pub use poll::*;
 pub use telegraph::*;
@@ -3,7 +3,7 @@ use sqlx::sqlite::SqliteQueryResult;
 use sqlx::Result;
 use tracing::Level;
 
-use super::db::DB;
+use super::db::Database;
 
 #[derive(sqlx::FromRow, Debug)]
 pub struct PollEntity {
@@ -32,30 +32,30 @@ pub struct VoteEntity {
 
 impl PollEntity {
     /// 插入一条记录，如果冲突则忽略
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn create(id: i64, gallery_id: i32) -> Result<SqliteQueryResult> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn create(db: &Database, id: i64, gallery_id: i32) -> Result<SqliteQueryResult> {
         sqlx::query!(
             "INSERT OR IGNORE INTO poll (id, gallery_id, score) VALUES (?, ?, 0.0)",
             id,
             gallery_id
         )
-        .execute(&*DB)
+        .execute(&db.pool)
         .await
     }
 
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn get_by_gallery(gallery_id: i32) -> Result<Option<Self>> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get_by_gallery(db: &Database, gallery_id: i32) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
             r#"SELECT id, gallery_id as "gallery_id: i32", score as "score: f32", old_vote FROM poll WHERE gallery_id = ?"#,
             gallery_id
         )
-        .fetch_optional(&*DB)
+        .fetch_optional(&db.pool)
         .await
     }
 
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn get_vote(id: i64) -> Result<[i32; 5]> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn get_vote(db: &Database, id: i64) -> Result<[i32; 5]> {
         let mut result = [0; 5];
         let rows = sqlx::query!(
             r#"
@@ -67,7 +67,7 @@ impl PollEntity {
             "#,
             id, id
         )
-            .fetch_all(&*DB)
+            .fetch_all(&db.pool)
             .await?;
         for row in rows {
             result[row.option as usize - 1] = row.cnt;
@@ -75,21 +75,21 @@ impl PollEntity {
         Ok(result)
     }
 
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn update_score(id: i64) -> Result<f32> {
-        let vote = Self::get_vote(id).await?;
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn update_score(db: &Database, id: i64) -> Result<f32> {
+        let vote = Self::get_vote(db, id).await?;
         let score = wilson_score(&vote);
-        sqlx::query!("UPDATE poll SET score = ? WHERE id = ?", score, id).execute(&*DB).await?;
+        sqlx::query!("UPDATE poll SET score = ? WHERE id = ?", score, id).execute(&db.pool).await?;
         Ok(score)
     }
 
     /// 获取指定投票的分数排名区段，结果为一个 0~1 的小数
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn rank(&self) -> Result<f32> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn rank(&self, db: &Database) -> Result<f32> {
         let record = sqlx::query!(
             r#"SELECT COUNT(*) as "higher!: f32", (SELECT COUNT(*) FROM poll) as "total!: f32" FROM poll WHERE score > ?"#, self.score
         )
-        .fetch_one(&*DB)
+        .fetch_one(&db.pool)
         .await?;
         Ok(record.higher / record.total)
     }
@@ -97,8 +97,13 @@ impl PollEntity {
 
 impl VoteEntity {
     /// 创建一个用户投票，创建完毕后请调用 PollEntity::update_score 来更新分数
-    #[tracing::instrument(level = Level::DEBUG)]
-    pub async fn create(user_id: u64, poll_id: i64, option: i32) -> Result<SqliteQueryResult> {
+    #[tracing::instrument(level = Level::DEBUG, skip(db))]
+    pub async fn create(
+        db: &Database,
+        user_id: u64,
+        poll_id: i64,
+        option: i32,
+    ) -> Result<SqliteQueryResult> {
         let now = Utc::now().naive_utc();
         let user_id = user_id as i64;
         sqlx::query!(
@@ -108,7 +113,7 @@ impl VoteEntity {
             option,
             now,
         )
-        .execute(&*DB)
+        .execute(&db.pool)
         .await
     }
 }
@@ -1,40 +1,104 @@
+use chrono::{NaiveDateTime, Utc};
 use sqlx::sqlite::SqliteQueryResult;
+use sqlx::types::Json;
 use sqlx::Result;
 
-use super::db::DB;
+use super::db::Database;
+use super::health::LinkStatus;
 
 #[derive(sqlx::FromRow, Debug)]
 pub struct TelegraphEntity {
     /// 画廊 ID
     pub gallery_id: i32,
-    /// telegraph 文章 URL
+    /// telegraph 文章 URL，文章被拆分时为第一篇的 URL
     pub url: String,
+    /// 按顺序排列的所有文章 URL
+    pub parts: Json<Vec<String>>,
+    /// 发布文章的 telegraph 账号名称，为空时表示配置账号池之前的默认账号
+    pub account: String,
 }
 
 impl TelegraphEntity {
-    pub async fn create(gallery_id: i32, telegraph: &str) -> Result<SqliteQueryResult> {
+    /// 创建一条记录，parts 不能为空
+    pub async fn create(
+        db: &Database,
+        gallery_id: i32,
+        account: &str,
+        parts: &[String],
+    ) -> Result<SqliteQueryResult> {
+        let json = Json(parts);
         sqlx::query!(
-            "REPLACE INTO telegraph (gallery_id, url) VALUES (?, ?)",
+            "REPLACE INTO telegraph (gallery_id, url, parts, account) VALUES (?, ?, ?, ?)",
             gallery_id,
-            telegraph
+            parts[0],
+            json,
+            account
         )
-        .execute(&*DB)
+        .execute(&db.pool)
         .await
     }
 
-    pub async fn get(gallery_id: i32) -> Result<Option<TelegraphEntity>> {
+    pub async fn get(db: &Database, gallery_id: i32) -> Result<Option<TelegraphEntity>> {
         sqlx::query_as!(
             TelegraphEntity,
-            r#"SELECT gallery_id as "gallery_id: i32", url FROM telegraph WHERE gallery_id = ?"#,
+            r#"SELECT gallery_id as "gallery_id: i32", url, parts as "parts: Json<Vec<String>>", account FROM telegraph WHERE gallery_id = ?"#,
             gallery_id
         )
-        .fetch_optional(&*DB)
+        .fetch_optional(&db.pool)
         .await
     }
 
-    pub async fn update(gallery_id: i32, telegraph: &str) -> Result<SqliteQueryResult> {
-        sqlx::query!("UPDATE telegraph SET url = ? WHERE gallery_id = ?", telegraph, gallery_id)
-            .execute(&*DB)
+    /// 更新文章 URL 和发布文章的账号，parts 不能为空
+    pub async fn update(
+        db: &Database,
+        gallery_id: i32,
+        account: &str,
+        parts: &[String],
+    ) -> Result<SqliteQueryResult> {
+        let json = Json(parts);
+        sqlx::query!(
+            "UPDATE telegraph SET url = ?, parts = ?, account = ? WHERE gallery_id = ?",
+            parts[0],
+            json,
+            account,
+            gallery_id
+        )
+        .execute(&db.pool)
+        .await
+    }
+
+    /// 列出未删除的画廊中，在 before 之后没有检查过的文章，最久没有检查的排在前面
+    pub async fn list_unchecked(
+        db: &Database,
+        before: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"SELECT telegraph.gallery_id, telegraph.url, telegraph.parts, telegraph.account
+            FROM telegraph
+            JOIN gallery ON gallery.id = telegraph.gallery_id
+            WHERE gallery.deleted = FALSE
+                AND (telegraph.last_checked IS NULL OR telegraph.last_checked < ?)
+            ORDER BY telegraph.last_checked
+            LIMIT ?"#,
+        )
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// 记录文章的检查结果
+    pub async fn update_status(
+        db: &Database,
+        gallery_id: i32,
+        status: LinkStatus,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query("UPDATE telegraph SET status = ?, last_checked = ? WHERE gallery_id = ?")
+            .bind(status)
+            .bind(Utc::now().naive_utc())
+            .bind(gallery_id)
+            .execute(&db.pool)
             .await
     }
 }
@@ -4,7 +4,7 @@ use indexmap::IndexMap;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::header::*;
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder, Response};
 use scraper::{Html, Selector};
 use serde::Serialize;
 use std::fmt::Debug;
@@ -14,6 +14,9 @@ use tracing::{debug, error, info, Instrument};
 use super::error::*;
 use super::types::*;
 use crate::utils::html::SelectorExtend;
+use crate::utils::ratelimit::{Backoff, RateLimiter};
+
+const EXHENTAI: &str = "https://exhentai.org";
 
 macro_rules! headers {
     ($($k:ident => $v:expr), *) => {{
@@ -36,7 +39,15 @@ macro_rules! selector {
 }
 
 #[derive(Debug, Clone)]
-pub struct EhClient(pub Client);
+pub struct EhClient {
+    client: Client,
+    /// E 站的地址，测试时可以指向本地的 mock 服务
+    endpoint: String,
+    /// 所有对 E 站的请求共享同一个限速器
+    limiter: RateLimiter,
+    /// 遇到暂时性错误时的重试策略
+    backoff: Backoff,
+}
 
 impl EhClient {
     #[tracing::instrument(skip(cookie))]
@@ -66,7 +77,66 @@ impl EhClient {
         let _response = send!(client.get("https://exhentai.org/uconfig.php"))?;
         let _response = send!(client.get("https://exhentai.org/mytags"))?;
 
-        Ok(Self(client))
+        let backoff = Backoff::new(&Default::default());
+        Ok(Self {
+            client,
+            endpoint: EXHENTAI.to_string(),
+            limiter: RateLimiter::unlimited(),
+            backoff,
+        })
+    }
+
+    /// 创建一个将 E 站的请求发送到 endpoint 的客户端，不会登陆，用于测试
+    #[cfg(test)]
+    pub(crate) fn with_endpoint(endpoint: &str) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            limiter: RateLimiter::unlimited(),
+            backoff: Backoff::new(&Default::default()),
+        }
+    }
+
+    /// 设置请求的限速器
+    pub fn with_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// 设置请求的重试策略
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// 构造 GET 请求，E 站的地址会被替换为 endpoint
+    fn get(&self, url: &str) -> RequestBuilder {
+        match url.strip_prefix(EXHENTAI) {
+            Some(path) => self.client.get(format!("{}{}", self.endpoint, path)),
+            None => self.client.get(url),
+        }
+    }
+
+    /// 等待限速器放行后发送请求
+    async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        self.limiter.acquire().await;
+        send!(request)
+    }
+
+    /// 发送请求并读取页面内容，识别 E 站的各种错误页面，遇到暂时性的错误时按照退避策略重试
+    async fn fetch(&self, request: impl Fn() -> RequestBuilder) -> Result<String> {
+        let fetch = || async {
+            let resp = self.send(request()).await?;
+            // cookie 无效时，E 站会返回一张熊猫图片
+            let content_type = resp.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+            if content_type.is_some_and(|v| v.starts_with("image/")) {
+                return Err(EhError::SadPanda);
+            }
+            let text = resp.text().await?;
+            EhError::check_page(&text)?;
+            Ok(text)
+        };
+        self.backoff.retry_if(fetch, EhError::is_transient).await
     }
 
     /// 访问指定页面，返回画廊列表
@@ -77,8 +147,9 @@ impl EhClient {
         params: &T,
         next: &str,
     ) -> Result<(Vec<EhGalleryUrl>, Option<String>)> {
-        let resp = send!(self.0.get(url).query(params).query(&[("next", next)]))?;
-        let html = Html::parse_document(&resp.text().await?);
+        let text =
+            self.fetch(|| self.get(url).query(params).query(&[("next", next)])).await?;
+        let html = Html::parse_document(&text);
 
         let selector = selector!("table.itg.gltc tr");
         let gl_list = html.select(&selector);
@@ -86,8 +157,12 @@ impl EhClient {
         let mut ret = vec![];
         // 第一个是 header
         for gl in gl_list.skip(1) {
-            let title = gl.select_text("td.gl3c.glname a div.glink").unwrap();
-            let url = gl.select_attr("td.gl3c.glname a", "href").unwrap();
+            let title = gl
+                .select_text("td.gl3c.glname a div.glink")
+                .ok_or(EhError::SelectorMismatch("td.gl3c.glname a div.glink"))?;
+            let url = gl
+                .select_attr("td.gl3c.glname a", "href")
+                .ok_or(EhError::SelectorMismatch("td.gl3c.glname a"))?;
             debug!(url, title);
             ret.push(url.parse()?)
         }
@@ -140,17 +215,23 @@ impl EhClient {
     pub async fn archive_gallery(&self, url: &EhGalleryUrl) -> Result<()> {
         static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"or=(?P<or>[0-9a-z-]+)").unwrap());
 
-        let resp = send!(self.0.get(url.url()))?;
-        let html = Html::parse_document(&resp.text().await?);
-        let onclick = html.select_attr("p.g2 a", "onclick").unwrap();
+        let text = self.fetch(|| self.get(&url.url())).await?;
+        let html = Html::parse_document(&text);
+        let onclick =
+            html.select_attr("p.g2 a", "onclick").ok_or(EhError::SelectorMismatch("p.g2 a"))?;
 
-        let or = RE.captures(&onclick).and_then(|c| c.name("or")).unwrap().as_str();
+        let or = RE
+            .captures(&onclick)
+            .and_then(|c| c.name("or"))
+            .ok_or(EhError::SelectorMismatch("p.g2 a"))?
+            .as_str();
 
-        send!(self
-            .0
+        let request = self
+            .client
             .post("https://exhentai.org/archiver.php")
             .query(&[("gid", &*url.id().to_string()), ("token", url.token()), ("or", or)])
-            .form(&[("hathdl_xres", "org")]))?;
+            .form(&[("hathdl_xres", "org")]);
+        self.send(request).await?;
 
         Ok(())
     }
@@ -159,22 +240,25 @@ impl EhClient {
     pub async fn get_gallery(&self, url: &EhGalleryUrl) -> Result<EhGallery> {
         // NOTE: 由于 Html 是 !Send 的，为了避免它被包含在 Future 上下文中，这里将它放在一个单独的作用域内
         // 参见：https://rust-lang.github.io/async-book/07_workarounds/03_send_approximation.html
-        let (title, title_jp, parent, tags, favorite, mut pages, posted, mut next_page) = {
-            let resp = send!(self.0.get(url.url()))?;
-            let html = Html::parse_document(&resp.text().await?);
+        let (title, title_jp, parent, uploader, tags, favorite, mut pages, posted, mut next_page) = {
+            let text = self.fetch(|| self.get(&url.url())).await?;
+            let html = Html::parse_document(&text);
 
             // 英文标题、日文标题、父画廊
-            let title = html.select_text("h1#gn").expect("xpath fail: h1#gn");
+            let title = html.select_text("h1#gn").ok_or(EhError::SelectorMismatch("h1#gn"))?;
             let title_jp = html.select_text("h1#gj");
             let parent = html.select_attr("td.gdt2 a", "href").and_then(|s| s.parse().ok());
 
+            // 上传者，账号被删除时没有链接
+            let uploader = html.select_text("#gdn a").or_else(|| html.select_text("#gdn"));
+
             // 画廊 tag
             let mut tags = IndexMap::new();
             let selector = selector!("div#taglist tr");
             for ele in html.select(&selector) {
                 let namespace = ele
                     .select_text("td.tc")
-                    .expect("xpath fail: td.tc")
+                    .ok_or(EhError::SelectorMismatch("td.tc"))?
                     .trim_matches(':')
                     .to_string();
                 let tag = ele.select_texts("td div a");
@@ -182,12 +266,18 @@ impl EhClient {
             }
 
             // 收藏数量
-            let favorite = html.select_text("#favcount").expect("xpath fail: #favcount");
-            let favorite = favorite.split(' ').next().unwrap().parse().unwrap();
+            let favorite = html
+                .select_text("#favcount")
+                .and_then(|s| s.split(' ').next()?.parse().ok())
+                .ok_or(EhError::SelectorMismatch("#favcount"))?;
 
             // 发布时间
-            let posted = &html.select_texts("td.gdt2")[0];
-            let posted = NaiveDateTime::parse_from_str(posted, "%Y-%m-%d %H:%M")?;
+            let posted = html
+                .select_texts("td.gdt2")
+                .into_iter()
+                .next()
+                .ok_or(EhError::SelectorMismatch("td.gdt2"))?;
+            let posted = NaiveDateTime::parse_from_str(&posted, "%Y-%m-%d %H:%M")?;
 
             // 每一页的 URL
             let pages = html.select_attrs("div#gdt a", "href");
@@ -195,13 +285,13 @@ impl EhClient {
             // 下一页的 URL
             let next_page = html.select_attr("table.ptb td:last-child a", "href");
 
-            (title, title_jp, parent, tags, favorite, pages, posted, next_page)
+            (title, title_jp, parent, uploader, tags, favorite, pages, posted, next_page)
         };
 
         while let Some(next_page_url) = &next_page {
             debug!(next_page_url);
-            let resp = send!(self.0.get(next_page_url))?;
-            let html = Html::parse_document(&resp.text().await?);
+            let text = self.fetch(|| self.get(next_page_url)).await?;
+            let html = Html::parse_document(&text);
             // 每一页的 URL
             pages.extend(html.select_attrs("#gdt a", "href"));
             // 下一页的 URL
@@ -218,6 +308,7 @@ impl EhClient {
             title,
             title_jp,
             parent,
+            uploader,
             tags,
             favorite,
             pages,
@@ -229,21 +320,27 @@ impl EhClient {
     /// 获取画廊的某一页的图片的 fileindex 和实际地址和 nl
     #[tracing::instrument(skip(self))]
     pub async fn get_image_url(&self, page: &EhPageUrl) -> Result<(u32, String)> {
-        let resp = send!(self.0.get(&page.url()))?;
+        let text = self.fetch(|| self.get(&page.url())).await?;
         let (url, nl, fileindex) = {
-            let html = Html::parse_document(&resp.text().await?);
-            let url = html.select_attr("img#img", "src").unwrap();
+            let html = Html::parse_document(&text);
+            let url =
+                html.select_attr("img#img", "src").ok_or(EhError::SelectorMismatch("img#img"))?;
             let nl = html.select_attr("img#img", "onerror").and_then(extract_nl);
-            let fileindex = extract_fileindex(&url).unwrap();
+            let fileindex =
+                extract_fileindex(&url).ok_or_else(|| EhError::InvalidURL(url.clone()))?;
             (url, nl, fileindex)
         };
+        check_quota(&url)?;
 
-        return if send!(self.0.head(&url)).is_ok() {
+        // 图片位于 H@H 节点上，不需要经过 E 站的限速
+        return if send!(self.client.head(&url)).is_ok() {
             Ok((fileindex, url))
-        } else if nl.is_some() {
-            let resp = send!(self.0.get(&page.with_nl(&nl.unwrap()).url()))?;
-            let html = Html::parse_document(&resp.text().await?);
-            let url = html.select_attr("img#img", "src").unwrap();
+        } else if let Some(nl) = nl {
+            let text = self.fetch(|| self.get(&page.with_nl(&nl).url())).await?;
+            let html = Html::parse_document(&text);
+            let url =
+                html.select_attr("img#img", "src").ok_or(EhError::SelectorMismatch("img#img"))?;
+            check_quota(&url)?;
             Ok((fileindex, url))
         } else {
             Err(EhError::HaHUrlBroken(url))
@@ -251,6 +348,14 @@ impl EhClient {
     }
 }
 
+/// 超出图片配额时，E 站会将图片替换为 509.gif
+fn check_quota(url: &str) -> Result<()> {
+    if url.ends_with("/509.gif") || url.ends_with("/509s.gif") {
+        return Err(EhError::QuotaExceeded);
+    }
+    Ok(())
+}
+
 fn extract_fileindex(url: &str) -> Option<u32> {
     static RE1: Lazy<Regex> = Lazy::new(|| Regex::new(r"fileindex=(?P<fileindex>\d+)").unwrap());
     static RE2: Lazy<Regex> = Lazy::new(|| Regex::new(r"/om/(?P<fileindex>\d+)/").unwrap());
@@ -264,3 +369,37 @@ fn extract_nl(onerror: String) -> Option<String> {
     let captures = RE.captures(&onerror)?;
     Some(captures.name("nl")?.as_str().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::mock::MockServer;
+
+    #[tokio::test]
+    async fn ban_is_not_retried() {
+        let ban = "Your IP address has been temporarily banned for excessive pageloads. The ban expires in 2 hours";
+        let server = MockServer::start(ban.as_bytes().to_vec()).await;
+        let result = EhClient::with_endpoint(&server.url())
+            .page("https://exhentai.org", &[("f_search", "")], "")
+            .await;
+        assert!(matches!(result, Err(EhError::RateLimited(_))));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn selector_mismatch() {
+        // E 站改版后，画廊列表中找不到标题
+        let html = r#"<table class="itg gltc">
+            <tr><th>Title</th></tr>
+            <tr><td class="gl3c glname"><a href="https://exhentai.org/g/1/abcdef/"><span>title</span></a></td></tr>
+        </table>"#;
+        let server = MockServer::start(html.as_bytes().to_vec()).await;
+        let result = EhClient::with_endpoint(&server.url())
+            .page("https://exhentai.org", &[("f_search", "")], "")
+            .await;
+        assert!(matches!(
+            result,
+            Err(EhError::SelectorMismatch("td.gl3c.glname a div.glink"))
+        ));
+    }
+}
@@ -1,3 +1,4 @@
+use reqwest::StatusCode;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, EhError>;
@@ -14,4 +15,97 @@ pub enum EhError {
     DateTimeError(#[from] chrono::format::ParseError),
     #[error("h@h url broken: {0}")]
     HaHUrlBroken(String),
+    #[error("sad panda: cookie is invalid or lacks exhentai access")]
+    SadPanda,
+    #[error("login expired")]
+    LoginExpired,
+    #[error("gallery removed or unavailable")]
+    GalleryRemoved,
+    #[error("image viewing limit exceeded (509)")]
+    QuotaExceeded,
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+    #[error("selector mismatch: {0}")]
+    SelectorMismatch(&'static str),
+}
+
+impl EhError {
+    /// 是否是暂时性的错误，稍等片刻后重试大概率可以成功
+    ///
+    /// 注意，IP 被封禁通常会持续几分钟到几小时，短时间内重试只会延长封禁时间，因此不属于暂时性的错误
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::ReqwestError(err) => is_transient(err),
+            Self::HaHUrlBroken(_) => true,
+            _ => false,
+        }
+    }
+
+    /// 是否是永久性的错误，无论重试多少次都不会成功，需要人工处理
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidURL(_)
+                | Self::SadPanda
+                | Self::LoginExpired
+                | Self::GalleryRemoved
+                | Self::SelectorMismatch(_)
+        )
+    }
+
+    /// 根据页面内容识别 E 站返回的错误页面
+    pub(super) fn check_page(text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Err(Self::SadPanda);
+        }
+        if text.starts_with("Your IP address has been temporarily banned") {
+            return Err(Self::RateLimited(text.trim().to_string()));
+        }
+        if text.contains("This page requires you to log on.") {
+            return Err(Self::LoginExpired);
+        }
+        if text.contains("This gallery has been removed or is unavailable.")
+            || text.contains("Key missing, or incorrect key provided.")
+        {
+            return Err(Self::GalleryRemoved);
+        }
+        if text.contains("You have exceeded your image viewing limits") {
+            return Err(Self::QuotaExceeded);
+        }
+        Ok(())
+    }
+}
+
+/// 超时、连接失败、429 和 5xx 被认为是暂时性的网络错误
+pub fn is_transient(err: &reqwest::Error) -> bool {
+    match err.status() {
+        Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        None => err.is_timeout() || err.is_connect() || err.is_request() || err.is_body(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_page() {
+        assert!(EhError::check_page("<html><h1 id=\"gn\">title</h1></html>").is_ok());
+        assert!(matches!(EhError::check_page(""), Err(EhError::SadPanda)));
+
+        let ban = "Your IP address has been temporarily banned for excessive pageloads which indicates that you are using automated mirroring/harvesting software. The ban expires in 59 minutes and 48 seconds\n";
+        let err = EhError::check_page(ban).unwrap_err();
+        assert!(matches!(&err, EhError::RateLimited(text) if text.ends_with("48 seconds")));
+        assert!(!err.is_transient() && !err.is_permanent());
+
+        let removed = "<div class=\"d\"><p>This gallery has been removed or is unavailable.</p></div>";
+        let err = EhError::check_page(removed).unwrap_err();
+        assert!(matches!(err, EhError::GalleryRemoved));
+        assert!(err.is_permanent());
+
+        let login = "<p>This page requires you to log on.</p>";
+        assert!(matches!(EhError::check_page(login), Err(EhError::LoginExpired)));
+        let quota = "<p>You have exceeded your image viewing limits. Note that you can reset these limits by going here.</p>";
+        assert!(matches!(EhError::check_page(quota), Err(EhError::QuotaExceeded)));
+    }
 }
@@ -74,6 +74,11 @@ pub struct EhPageUrl {
 }
 
 impl EhPageUrl {
+    /// 根据页面哈希、画廊 ID 和页码构造页面地址，例如根据数据库中记录的图片重新下载
+    pub fn new(hash: &str, gallery_id: i32, page: i32) -> Self {
+        Self { hash: hash.to_owned(), gallery_id, page, nl: None }
+    }
+
     pub fn url(&self) -> String {
         match &self.nl {
             None => {
@@ -149,6 +154,8 @@ pub struct EhGallery {
     pub favorite: i32,
     /// 父画廊地址
     pub parent: Option<EhGalleryUrl>,
+    /// 上传者
+    pub uploader: Option<String>,
     /// 画廊页面
     pub pages: Vec<EhPageUrl>,
     /// 发布时间
@@ -157,6 +164,27 @@ pub struct EhGallery {
     pub cover: usize,
 }
 
+#[cfg(test)]
+impl EhGallery {
+    /// 测试用的画廊，地址为 `https://exhentai.org/g/{id}/aaaaaaaaaa/`，第 n 页图片的哈希为 `hash{n}`
+    pub(crate) fn mock(id: i32, pages: i32) -> Self {
+        Self {
+            url: format!("https://exhentai.org/g/{}/aaaaaaaaaa/", id).parse().unwrap(),
+            title: "title".to_string(),
+            title_jp: None,
+            tags: Default::default(),
+            favorite: 0,
+            parent: None,
+            uploader: None,
+            pages: (1..=pages)
+                .map(|page| EhPageUrl::new(&format!("hash{page}"), id, page))
+                .collect(),
+            posted: "2024-01-01T00:00:00".parse().unwrap(),
+            cover: 0,
+        }
+    }
+}
+
 pub trait GalleryInfo {
     fn url(&self) -> EhGalleryUrl;
 
@@ -169,6 +197,15 @@ pub trait GalleryInfo {
     fn pages(&self) -> usize;
 
     fn cover(&self) -> usize;
+
+    /// 父画廊（上一个版本）的 ID
+    fn parent(&self) -> Option<i32>;
+
+    /// 收藏数量，旧画廊可能为空
+    fn favorite(&self) -> Option<i32>;
+
+    /// 发布时间，旧画廊可能为空
+    fn posted(&self) -> Option<NaiveDateTime>;
 }
 
 impl GalleryInfo for EhGallery {
@@ -195,6 +232,18 @@ impl GalleryInfo for EhGallery {
     fn cover(&self) -> usize {
         self.cover
     }
+
+    fn parent(&self) -> Option<i32> {
+        self.parent.as_ref().map(|p| p.id())
+    }
+
+    fn favorite(&self) -> Option<i32> {
+        Some(self.favorite)
+    }
+
+    fn posted(&self) -> Option<NaiveDateTime> {
+        Some(self.posted)
+    }
 }
 
 impl GalleryInfo for GalleryEntity {
@@ -221,6 +270,18 @@ impl GalleryInfo for GalleryEntity {
     fn cover(&self) -> usize {
         0
     }
+
+    fn parent(&self) -> Option<i32> {
+        self.parent
+    }
+
+    fn favorite(&self) -> Option<i32> {
+        self.favorite
+    }
+
+    fn posted(&self) -> Option<NaiveDateTime> {
+        self.posted
+    }
 }
 
 #[cfg(test)]
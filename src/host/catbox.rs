@@ -0,0 +1,224 @@
+use reqwest::multipart::{Form, Part};
+use reqwest::Client;
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::AsyncReadExt;
+
+use super::{mime_of, HostKind, ImageHost};
+
+/// Catbox 的 API 地址
+const CATBOX_API: &str = "https://catbox.moe/user/api.php";
+
+#[derive(Debug, Clone)]
+pub struct CatboxUploader {
+    userhash: String, // Catbox 用户的 userhash
+    endpoint: String, // API 地址，测试时可以指向本地的 mock 服务
+    client: Client,   // HTTP 客户端
+}
+
+impl CatboxUploader {
+    // 构造方法
+    pub fn new(userhash: &str) -> Self {
+        Self::with_endpoint(userhash, CATBOX_API)
+    }
+
+    // 使用指定的 API 地址构造
+    pub fn with_endpoint(userhash: &str, endpoint: &str) -> Self {
+        let client = Client::new(); // 初始化 HTTP 客户端
+        Self {
+            userhash: userhash.to_string(), // 用户哈希赋值
+            endpoint: endpoint.to_string(),
+            client,
+        }
+    }
+
+    // 上传本地文件到 Catbox
+    pub async fn upload_file(&self, file_path: &str) -> Result<String> {
+        let mut file = tokio::fs::File::open(file_path).await?; // 异步打开文件
+        let mut buffer = Vec::new(); // 用于存储文件内容
+        file.read_to_end(&mut buffer).await?; // 读取文件内容到缓冲区
+
+        let filename = std::path::Path::new(file_path)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or(file_path);
+        self.upload_bytes(buffer, filename, mime_of(filename)).await
+    }
+
+    // 上传内存中的文件内容到 Catbox，返回文件 URL
+    pub async fn upload_bytes(&self, data: Vec<u8>, filename: &str, mime: &str) -> Result<String> {
+        let file_part = Part::bytes(data) // 创建一个 multipart 部分，包含文件内容
+            .file_name(filename.to_string())
+            .mime_str(mime)?;
+        let form = Form::new()
+            .text("reqtype", "fileupload") // 表单参数：请求类型为文件上传
+            .text("userhash", self.userhash.clone()) // 表单参数：用户哈希
+            .part("fileToUpload", file_part); // 附加文件部分
+
+        // 发起上传请求
+        let res = self.client.post(&self.endpoint)
+            .multipart(form)
+            .send()
+            .await?; // 异步发送请求
+
+        if res.status().is_success() {
+            let text = res.text().await?; // 返回的是文件 URL 文本
+            Ok(text)
+        } else {
+            Err(anyhow!("Failed to upload file: {}", res.status())) // 上传失败时返回错误
+        }
+    }
+
+    // 上传 URL 到 Catbox
+    pub async fn upload_url(&self, image_url: &str) -> Result<String> {
+        let form = Form::new()
+            .text("reqtype", "urlupload") // 请求类型：URL 上传
+            .text("userhash", self.userhash.clone())
+            .text("url", image_url.to_string());
+
+        let res = self.client.post(&self.endpoint)
+            .multipart(form)
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            let text = res.text().await?; // 返回的是文件 URL 文本
+            Ok(text)
+        } else {
+            Err(anyhow!("Failed to upload URL")) // 上传失败时返回错误
+        }
+    }
+
+    // 创建专辑，files 为专辑中的文件名（例如 abcdef.jpg），返回专辑的短链接
+    pub async fn create_album(&self, title: &str, description: &str, files: &[String]) -> Result<String> {
+        let form = Form::new()
+            .text("reqtype", "createalbum")
+            .text("userhash", self.userhash.clone())
+            .text("title", title.to_string())
+            .text("desc", description.to_string())
+            .text("files", files.join(" ")); // 文件名以空格分隔，最多 500 个
+
+        // 发起请求创建专辑
+        let res = self.client.post(&self.endpoint)
+            .multipart(form)
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            let text = res.text().await?; // 返回的是专辑地址，例如 https://catbox.moe/c/abc123
+            album_short(&text)
+        } else {
+            Err(anyhow!("Failed to create album: {}", res.status())) // 创建专辑失败时返回错误
+        }
+    }
+
+    // 编辑专辑，专辑中的文件会被替换为 files
+    pub async fn edit_album(&self, short: &str, title: &str, description: &str, files: &[String]) -> Result<()> {
+        let form = Form::new()
+            .text("reqtype", "editalbum")
+            .text("userhash", self.userhash.clone())
+            .text("short", short.to_string())
+            .text("title", title.to_string())
+            .text("desc", description.to_string())
+            .text("files", files.join(" ")); // 文件名以空格分隔
+
+        let res = self.client.post(&self.endpoint)
+            .multipart(form)
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!("Failed to edit album: {}", res.status())) // 编辑专辑失败时返回错误
+        }
+    }
+}
+
+// 从专辑地址中提取短链接
+fn album_short(url: &str) -> Result<String> {
+    match url.trim().rsplit_once("/c/") {
+        Some((_, short)) if !short.is_empty() => Ok(short.to_string()),
+        _ => Err(anyhow!("Unexpected album response: {}", url)),
+    }
+}
+
+impl ImageHost for CatboxUploader {
+    fn kind(&self) -> HostKind {
+        HostKind::Catbox
+    }
+
+    fn upload<'a>(&'a self, filename: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<String>> {
+        self.upload_bytes(data, filename, mime_of(filename)).boxed()
+    }
+
+    fn delete<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>> {
+        async move {
+            // Catbox 删除文件时只需要文件名，例如 https://files.catbox.moe/abcdef.jpg -> abcdef.jpg
+            let filename = url.rsplit('/').next().unwrap_or(url);
+            let form = Form::new()
+                .text("reqtype", "deletefiles")
+                .text("userhash", self.userhash.clone())
+                .text("files", filename.to_string());
+
+            let res = self.client.post(&self.endpoint)
+                .multipart(form)
+                .send()
+                .await?;
+
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err(anyhow!("Failed to delete file"))
+            }
+        }
+        .boxed()
+    }
+
+    fn health_check(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            self.client.head("https://catbox.moe").send().await?.error_for_status()?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::mock::MockServer;
+
+    #[tokio::test]
+    async fn upload_bytes() {
+        let server = MockServer::start(b"https://files.catbox.moe/abcdef.jpg".to_vec()).await;
+        let catbox = CatboxUploader::with_endpoint("userhash", &server.url());
+
+        let url = catbox.upload_bytes(b"image".to_vec(), "0123456789.jpg", "image/jpeg").await;
+        assert_eq!(url.unwrap(), "https://files.catbox.moe/abcdef.jpg");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let body = String::from_utf8_lossy(&requests[0]);
+        assert!(body.starts_with("POST / HTTP/1.1"));
+        assert!(body.contains("name=\"reqtype\"\r\n\r\nfileupload"));
+        assert!(body.contains("name=\"userhash\"\r\n\r\nuserhash"));
+        assert!(body.contains("filename=\"0123456789.jpg\""));
+        assert!(body.contains("Content-Type: image/jpeg\r\n\r\nimage"));
+    }
+
+    #[tokio::test]
+    async fn create_album() {
+        let server = MockServer::start(b"https://catbox.moe/c/abc123".to_vec()).await;
+        let catbox = CatboxUploader::with_endpoint("userhash", &server.url());
+
+        let files = vec!["a.jpg".to_string(), "b.png".to_string()];
+        let short = catbox.create_album("title", "desc", &files).await.unwrap();
+        assert_eq!(short, "abc123");
+
+        let body = String::from_utf8_lossy(&server.requests()[0]).to_string();
+        assert!(body.contains("name=\"reqtype\"\r\n\r\ncreatealbum"));
+        assert!(body.contains("name=\"files\"\r\n\r\na.jpg b.png"));
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use super::{HostKind, ImageHost};
+
+/// 将图片保存到本地目录，并通过静态文件服务器对外提供访问
+#[derive(Debug, Clone)]
+pub struct LocalHost {
+    /// 图片保存目录
+    path: PathBuf,
+    /// 该目录对外访问的 URL 前缀
+    base_url: String,
+}
+
+impl LocalHost {
+    pub fn new(path: &str, base_url: &str) -> Self {
+        Self { path: PathBuf::from(path), base_url: base_url.trim_end_matches('/').to_string() }
+    }
+}
+
+impl ImageHost for LocalHost {
+    fn kind(&self) -> HostKind {
+        HostKind::Local
+    }
+
+    fn upload<'a>(&'a self, filename: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<String>> {
+        async move {
+            tokio::fs::create_dir_all(&self.path).await?;
+            tokio::fs::write(self.path.join(filename), data).await?;
+            Ok(format!("{}/{}", self.base_url, filename))
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let filename = url
+                .strip_prefix(&self.base_url)
+                .map(|s| s.trim_start_matches('/'))
+                .context("该图片不属于本地图床")?;
+            // 防止通过 ../ 删除目录以外的文件
+            if filename.contains('/') || filename.contains('\\') {
+                bail!("非法的文件名：{}", filename);
+            }
+            tokio::fs::remove_file(self.path.join(filename)).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn health_check(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            tokio::fs::create_dir_all(&self.path).await?;
+            let metadata = tokio::fs::metadata(&self.path).await?;
+            if metadata.permissions().readonly() {
+                bail!("图片目录不可写：{}", self.path.display());
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn upload_and_delete() {
+        let dir = std::env::temp_dir().join(format!("exloli-local-{}", rand::random::<u32>()));
+        let host = LocalHost::new(dir.to_str().unwrap(), "https://example.com/static/");
+        host.health_check().await.unwrap();
+
+        let url = host.upload("0123456789.jpg", b"image".to_vec()).await.unwrap();
+        assert_eq!(url, "https://example.com/static/0123456789.jpg");
+        assert_eq!(std::fs::read(dir.join("0123456789.jpg")).unwrap(), b"image");
+
+        host.delete(&url).await.unwrap();
+        assert!(!dir.join("0123456789.jpg").exists());
+        assert!(host.delete("https://example.com/static/../secret").await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
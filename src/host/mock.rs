@@ -0,0 +1,83 @@
+//! 测试用的简易 HTTP 服务，根据请求行返回响应，并记录收到的原始请求
+
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+pub struct MockServer {
+    addr: std::net::SocketAddr,
+    requests: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl MockServer {
+    /// 启动服务，每个请求都以 200 和 body 作为响应
+    pub async fn start(body: Vec<u8>) -> Self {
+        Self::route(move |_| (200, body.clone())).await
+    }
+
+    /// 启动服务，根据请求行（例如 `GET /path HTTP/1.1`）决定响应的状态码和 body
+    pub async fn route<F>(handler: F) -> Self
+    where
+        F: Fn(&str) -> (u16, Vec<u8>) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let request = read_request(&mut stream).await;
+                let line = String::from_utf8_lossy(&request)
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                received.lock().unwrap().push(request);
+                let (status, body) = handler(&line);
+                let header = format!(
+                    "HTTP/1.1 {} MOCK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes()).await;
+                let _ = stream.write_all(&body).await;
+            }
+        });
+        Self { addr, requests }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// 到目前为止收到的所有请求
+    pub fn requests(&self) -> Vec<Vec<u8>> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// 读取一个完整的请求，请求体的长度由 Content-Length 决定
+async fn read_request(stream: &mut tokio::net::TcpStream) -> Vec<u8> {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).await.unwrap_or(0);
+        if n == 0 {
+            return buf;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            continue;
+        };
+        let header = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+        let length = header
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        if buf.len() >= end + 4 + length {
+            return buf;
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+
+mod catbox;
+mod local;
+#[cfg(test)]
+pub(crate) mod mock;
+mod s3;
+
+pub use catbox::CatboxUploader;
+pub use local::LocalHost;
+pub use s3::S3Host;
+
+use crate::config::Config;
+
+/// 图床类型，同时也会被记录到 image 表中，用于区分每张图片的来源
+#[derive(sqlx::Type, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum HostKind {
+    /// 早期上传到 telegra.ph 的图片，仅用于兼容旧数据
+    Telegraph,
+    #[default]
+    Catbox,
+    Local,
+    S3,
+}
+
+/// 图床的统一接口
+pub trait ImageHost: Debug + Send + Sync {
+    /// 图床类型
+    fn kind(&self) -> HostKind;
+
+    /// 上传图片，返回可以公开访问的 URL
+    fn upload<'a>(&'a self, filename: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<String>>;
+
+    /// 根据 URL 删除一张图片
+    fn delete<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>>;
+
+    /// 检查图床当前是否可用
+    fn health_check(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+/// 根据文件扩展名推断图片的 MIME 类型
+pub fn mime_of(filename: &str) -> &'static str {
+    match filename.rsplit('.').next().map(|s| s.to_ascii_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+/// 根据配置文件创建图床
+pub fn new_host(config: &Config) -> Result<Arc<dyn ImageHost>> {
+    Ok(match config.host.backend {
+        HostKind::Catbox => Arc::new(CatboxUploader::new(&config.catbox.userhash)),
+        HostKind::Local => {
+            let local = config.host.local.as_ref().context("缺少 [host.local] 配置")?;
+            Arc::new(LocalHost::new(&local.path, &local.base_url))
+        }
+        HostKind::S3 => {
+            let s3 = config.host.s3.as_ref().context("缺少 [host.s3] 配置")?;
+            Arc::new(S3Host::new(s3.clone())?)
+        }
+        HostKind::Telegraph => anyhow::bail!("telegraph 图床已不再支持上传"),
+    })
+}
@@ -0,0 +1,183 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, Url};
+use sha2::{Digest, Sha256};
+
+use super::{mime_of, HostKind, ImageHost};
+use crate::config::S3Config;
+
+/// S3 兼容的对象存储，使用 path-style 访问，因此同样适用于 MinIO 等自建服务
+#[derive(Debug, Clone)]
+pub struct S3Host {
+    config: S3Config,
+    /// endpoint 的 host 部分，用于签名
+    host: String,
+    client: Client,
+}
+
+impl S3Host {
+    pub fn new(config: S3Config) -> Result<Self> {
+        let endpoint = Url::parse(&config.endpoint)?;
+        let host = endpoint.host_str().context("无效的 S3 endpoint")?;
+        let host = match endpoint.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        Ok(Self { config, host, client: Client::new() })
+    }
+
+    /// 对象的访问路径
+    fn object_path(&self, key: &str) -> String {
+        format!("/{}/{}", self.config.bucket, key)
+    }
+
+    /// 对象对外公开的 URL
+    fn public_url(&self, key: &str) -> String {
+        match &self.config.public_url {
+            Some(url) if !url.is_empty() => format!("{}/{}", url.trim_end_matches('/'), key),
+            _ => format!("{}{}", self.config.endpoint.trim_end_matches('/'), self.object_path(key)),
+        }
+    }
+
+    /// 发送一个经过 AWS Signature V4 签名的请求
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        content_type: Option<&str>,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response> {
+        let now = Utc::now();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let authorization = self.authorization(method.as_str(), path, &payload_hash, now);
+        let url = format!("{}{}", self.config.endpoint.trim_end_matches('/'), path);
+
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string())
+            .header("authorization", authorization);
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type);
+        }
+        Ok(request.body(body).send().await?)
+    }
+
+    /// 计算 Authorization 头
+    ///
+    /// 参见：https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> String {
+        let date = now.format("%Y%m%d").to_string();
+        let datetime = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical_request = format!(
+            "{method}\n{path}\n\nhost:{}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{datetime}\n\n{signed_headers}\n{payload_hash}",
+            self.host
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{datetime}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = format!("AWS4{}", self.config.secret_key);
+        let key = hmac_sha256(key.as_bytes(), date.as_bytes());
+        let key = hmac_sha256(&key, self.config.region.as_bytes());
+        let key = hmac_sha256(&key, b"s3");
+        let key = hmac_sha256(&key, b"aws4_request");
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.config.access_key
+        )
+    }
+}
+
+impl ImageHost for S3Host {
+    fn kind(&self) -> HostKind {
+        HostKind::S3
+    }
+
+    fn upload<'a>(&'a self, filename: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<String>> {
+        async move {
+            let path = self.object_path(filename);
+            let resp = self.request(Method::PUT, &path, Some(mime_of(filename)), data).await?;
+            if !resp.status().is_success() {
+                bail!("S3 上传失败：{} {}", resp.status(), resp.text().await?);
+            }
+            Ok(self.public_url(filename))
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let key = url.rsplit('/').next().context("无效的图片 URL")?;
+            let resp = self.request(Method::DELETE, &self.object_path(key), None, vec![]).await?;
+            if !resp.status().is_success() {
+                bail!("S3 删除失败：{}", resp.status());
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn health_check(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let path = format!("/{}", self.config.bucket);
+            let resp = self.request(Method::HEAD, &path, None, vec![]).await?;
+            if !resp.status().is_success() {
+                bail!("S3 bucket 不可用：{}", resp.status());
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC 可以接受任意长度的 key");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 需要一个本地的 MinIO 实例，例如：
+    /// docker run -p 9000:9000 -e MINIO_ROOT_USER=minioadmin -e MINIO_ROOT_PASSWORD=minioadmin minio/minio server /data
+    /// 并提前创建好名为 exloli 的 bucket，设置为允许公开读取
+    #[tokio::test]
+    #[ignore]
+    async fn minio() {
+        let endpoint =
+            std::env::var("S3_ENDPOINT").unwrap_or_else(|_| "http://127.0.0.1:9000".to_string());
+        let host = S3Host::new(S3Config {
+            endpoint,
+            region: "us-east-1".to_string(),
+            bucket: "exloli".to_string(),
+            access_key: "minioadmin".to_string(),
+            secret_key: "minioadmin".to_string(),
+            public_url: None,
+        })
+        .unwrap();
+        host.health_check().await.unwrap();
+
+        let url = host.upload("0123456789.jpg", b"image".to_vec()).await.unwrap();
+        assert_eq!(reqwest::get(&url).await.unwrap().bytes().await.unwrap().as_ref(), b"image");
+        host.delete(&url).await.unwrap();
+    }
+}
@@ -2,7 +2,9 @@ pub mod bot;
 pub mod config;
 pub mod database;
 pub mod ehentai;
-mod catbox;
+pub mod host;
+pub mod processor;
+pub mod site;
 pub mod tags;
 pub mod uploader;
 pub mod utils;
@@ -0,0 +1,178 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageReader};
+use tracing::debug;
+
+use crate::config::ImageConfig;
+
+/// 上传前对图片进行规范化处理：限制尺寸和体积，必要时将 PNG 转为 JPEG
+///
+/// 处理只影响上传的文件内容和扩展名，数据库中仍然使用 E 站的 fileindex 和页面哈希来记录图片
+#[derive(Debug, Clone)]
+pub struct ImageProcessor {
+    config: ImageConfig,
+}
+
+impl ImageProcessor {
+    pub fn new(config: ImageConfig) -> Self {
+        Self { config }
+    }
+
+    /// 处理一张图片，返回处理后的内容和文件名
+    ///
+    /// 不需要处理或者无法识别的图片会原样返回
+    pub fn process(&self, data: Vec<u8>, filename: &str) -> Result<(Vec<u8>, String)> {
+        let format = match image::guess_format(&data) {
+            Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => format,
+            _ => return Ok((data, filename.to_string())),
+        };
+
+        // 先只读取图片头部获取尺寸，避免对不需要处理的图片进行完整解码
+        let (width, height) =
+            ImageReader::with_format(Cursor::new(&data), format).into_dimensions()?;
+        let max = self.config.max_dimension;
+        let oversize = max != 0 && width.max(height) > max;
+        let overweight = !self.within_limit(&data);
+        if !oversize && !overweight {
+            return Ok((data, filename.to_string()));
+        }
+
+        let image = image::load_from_memory_with_format(&data, format)?;
+        let mut image = if oversize { image.resize(max, max, FilterType::Lanczos3) } else { image };
+
+        // 只缩小了尺寸的 PNG 仍然保留为 PNG
+        if format == ImageFormat::Png && !overweight {
+            let mut buf = Cursor::new(vec![]);
+            image.write_to(&mut buf, ImageFormat::Png)?;
+            let buf = buf.into_inner();
+            if self.within_limit(&buf) {
+                debug!("缩放图片：{} {}x{} -> {:?}", filename, width, height, image.dimensions());
+                return Ok((buf, filename.to_string()));
+            }
+        }
+
+        // 先逐步降低质量，如果仍然超出限制，则继续缩小尺寸
+        let mut quality = self.config.jpeg_quality;
+        loop {
+            let buf = encode_jpeg(&image, quality)?;
+            if self.within_limit(&buf) || image.width().max(image.height()) < 256 {
+                debug!(
+                    "压缩图片：{} {}x{} {}B -> {:?} {}B (q={})",
+                    filename,
+                    width,
+                    height,
+                    data.len(),
+                    image.dimensions(),
+                    buf.len(),
+                    quality
+                );
+                return Ok((buf, with_extension(filename, "jpg")));
+            }
+            if quality > 50 {
+                quality = quality.saturating_sub(10).max(50);
+            } else {
+                let (w, h) = image.dimensions();
+                image = image.resize(w * 4 / 5, h * 4 / 5, FilterType::Lanczos3);
+            }
+        }
+    }
+
+    fn within_limit(&self, data: &[u8]) -> bool {
+        self.config.max_file_size == 0 || data.len() <= self.config.max_file_size
+    }
+}
+
+/// 计算图片的差值哈希（dHash），用于识别内容相同但编码不同的图片
+///
+/// 将图片缩放为 9x8 的灰度图，逐行比较相邻像素的亮度，得到 64 位的指纹
+pub fn dhash(data: &[u8]) -> Result<u64> {
+    let image = image::load_from_memory(data)?;
+    let gray = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if gray.get_pixel(x, y)[0] > gray.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    JpegEncoder::new_with_quality(&mut buf, quality).encode_image(&image.to_rgb8())?;
+    Ok(buf)
+}
+
+/// 替换文件扩展名
+fn with_extension(filename: &str, ext: &str) -> String {
+    match filename.rsplit_once('.') {
+        Some((name, _)) => format!("{}.{}", name, ext),
+        None => format!("{}.{}", filename, ext),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn noise_png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |_, _| Rgb(rand::random()));
+        let mut buf = Cursor::new(vec![]);
+        image.write_to(&mut buf, ImageFormat::Png).unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn process() {
+        let processor = ImageProcessor::new(ImageConfig {
+            max_dimension: 400,
+            max_file_size: 100 * 1024,
+            jpeg_quality: 85,
+        });
+
+        // 符合要求的图片原样返回
+        let small = noise_png(100, 50);
+        let (data, name) = processor.process(small.clone(), "a.png").unwrap();
+        assert_eq!((data, name.as_str()), (small, "a.png"));
+
+        // 过大的 PNG 被缩放并转换为 JPEG
+        let (data, name) = processor.process(noise_png(800, 200), "b.png").unwrap();
+        assert_eq!(name, "b.jpg");
+        assert!(data.len() <= 100 * 1024);
+        let image = image::load_from_memory(&data).unwrap();
+        assert_eq!(image.dimensions(), (400, 100));
+    }
+
+    #[test]
+    fn dhash_distance() {
+        let gradient =
+            RgbImage::from_fn(300, 200, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 0]));
+        let png = {
+            let mut buf = Cursor::new(vec![]);
+            gradient.write_to(&mut buf, ImageFormat::Png).unwrap();
+            buf.into_inner()
+        };
+        let jpeg = encode_jpeg(
+            &DynamicImage::ImageRgb8(gradient).resize(150, 100, FilterType::Lanczos3),
+            60,
+        )
+        .unwrap();
+
+        // 重新编码、缩放后的图片哈希几乎不变
+        let a = dhash(&png).unwrap();
+        let b = dhash(&jpeg).unwrap();
+        assert!((a ^ b).count_ones() <= 3);
+
+        // 内容不同的图片哈希差异很大
+        let c = dhash(&noise_png(300, 200)).unwrap();
+        assert!((a ^ c).count_ones() > 10);
+    }
+}
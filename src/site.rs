@@ -0,0 +1,419 @@
+//! 根据数据库生成静态的画廊目录网站，可以部署为频道的镜像，在 Telegram 或 Telegraph 无法访问时浏览
+//!
+//! 生成的目录结构如下，所有链接均为相对路径：
+//!
+//! - `index.html`、`index-2.html`……：按评分排列的画廊
+//! - `latest.html`、`latest-2.html`……：按发布时间排列的画廊
+//! - `artists.html`、`artist/*.html`：作者列表以及每个作者的画廊
+//! - `tags.html`、`tag/*.html`：除作者以外的标签列表以及每个标签的画廊
+//! - `g/*.html`：每个画廊的详情和图片
+//! - `search.html`、`search.json`：在浏览器中运行的搜索页面及其索引
+//!
+//! 只包含已经发布到频道且没有被删除的画廊
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use minijinja::{context, escape_formatter, Environment, Value};
+use serde::Serialize;
+use tracing::info;
+
+use crate::database::{Database, GalleryEntity, ImageEntity, PublishedGallery};
+use crate::ehentai::GalleryInfo;
+use crate::tags::EhTagTransDB;
+
+/// 列表中每一页的画廊数量
+const PAGE_SIZE: usize = 50;
+
+/// 每次生成时会被清空的目录
+const GENERATED_DIRS: [&str; 3] = ["g", "tag", "artist"];
+
+/// 分页的列表，每次生成时会删除旧的分页，例如 index-2.html
+const GENERATED_LISTS: [&str; 2] = ["index", "latest"];
+
+const TEMPLATES: [(&str, &str); 5] = [
+    ("layout.html", include_str!("../templates/site/layout.html")),
+    ("list.html", include_str!("../templates/site/list.html")),
+    ("gallery.html", include_str!("../templates/site/gallery.html")),
+    ("tags.html", include_str!("../templates/site/tags.html")),
+    ("search.html", include_str!("../templates/site/search.html")),
+];
+
+/// 渲染画廊时可以使用的变量
+#[derive(Debug, Serialize)]
+struct GalleryItem {
+    id: i32,
+    title: String,
+    /// 日文标题，没有时与标题相同
+    title_jp: String,
+    /// 百分制的分数，保留两位小数
+    score: Option<String>,
+    /// 发布时间，格式为 YYYY-MM-DD
+    posted: Option<String>,
+    pages: i32,
+    /// telegraph 文章的地址
+    preview: Option<String>,
+    /// E 站的原始地址
+    url: String,
+    /// 画廊页面相对于网站根目录的路径
+    href: String,
+    /// 翻译后的标签，按 namespace 分组
+    tags: Vec<TagGroup>,
+    artists: Vec<TagLink>,
+    #[serde(skip)]
+    raw_score: f32,
+    #[serde(skip)]
+    posted_at: Option<NaiveDateTime>,
+    /// 原始的 namespace 和标签
+    #[serde(skip)]
+    raw_tags: Vec<(String, String)>,
+}
+
+#[derive(Debug, Serialize)]
+struct TagGroup {
+    namespace: String,
+    tags: Vec<TagLink>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TagLink {
+    /// 翻译后的名称
+    name: String,
+    /// 标签页面相对于网站根目录的路径
+    href: String,
+}
+
+/// 搜索索引中的一条记录
+#[derive(Serialize)]
+struct SearchEntry<'a> {
+    title: &'a str,
+    title_jp: &'a str,
+    /// 翻译后的标签以及 namespace:tag 格式的原始标签
+    tags: Vec<String>,
+    score: Option<&'a str>,
+    posted: Option<&'a str>,
+    href: &'a str,
+}
+
+struct Site<'a> {
+    env: Environment<'static>,
+    trans: &'a EhTagTransDB,
+    output: &'a Path,
+    /// 已经生成的页面数量
+    written: usize,
+}
+
+/// 在 output 目录中生成静态网站，返回生成的页面数量
+///
+/// 注意，output 中由生成器管理的子目录会先被清空，以免残留已删除的画廊
+pub async fn generate(db: &Database, trans: &EhTagTransDB, output: &Path) -> Result<usize> {
+    let mut env = Environment::new();
+    // 默认的 HTML 转义会把 / 也转义掉，使生成的链接难以阅读，这里只转义必要的字符
+    env.set_formatter(|out, state, value| match value.as_str() {
+        Some(s) if !value.is_safe() => Ok(out.write_str(&escape_html(s))?),
+        _ => escape_formatter(out, state, value),
+    });
+    for (name, source) in TEMPLATES {
+        env.add_template(name, source)?;
+    }
+    let mut site = Site { env, trans, output, written: 0 };
+
+    fs::create_dir_all(output)?;
+    for dir in GENERATED_DIRS {
+        let path = output.join(dir);
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir(&path)?;
+    }
+    // 画廊变少时页数也会减少，多出来的旧分页不会被覆盖
+    for entry in fs::read_dir(output)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let paged = |list: &&str| {
+            name.strip_prefix(list).is_some_and(|s| s.starts_with('-') && s.ends_with(".html"))
+        };
+        if GENERATED_LISTS.iter().any(paged) {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    let rows = GalleryEntity::list_published(db).await?;
+    let mut galleries = rows.into_iter().map(|row| site.gallery_item(row)).collect::<Vec<_>>();
+    info!("生成静态网站：{} 个画廊", galleries.len());
+
+    for gallery in &galleries {
+        let images = ImageEntity::get_by_gallery_id(db, gallery.id)
+            .await?
+            .into_iter()
+            .filter(|img| !img.ad)
+            .map(|img| img.url())
+            .collect::<Vec<_>>();
+        site.render(
+            &gallery.href,
+            "gallery.html",
+            context! {
+                title => gallery.title,
+                gallery,
+                images,
+            },
+        )?;
+    }
+
+    galleries.sort_by(|a, b| b.posted_at.cmp(&a.posted_at).then(b.id.cmp(&a.id)));
+    let by_date = galleries.iter().collect::<Vec<_>>();
+    site.render_list("latest", "最新", &by_date)?;
+    site.render_tags(&by_date)?;
+
+    galleries.sort_by(|a, b| b.raw_score.total_cmp(&a.raw_score).then(b.id.cmp(&a.id)));
+    let by_score = galleries.iter().collect::<Vec<_>>();
+    site.render_list("index", "评分", &by_score)?;
+
+    site.render("search.html", "search.html", context! { title => "搜索" })?;
+    let index = galleries.iter().map(|g| site.search_entry(g)).collect::<Vec<_>>();
+    serde_json::to_writer(BufWriter::new(File::create(output.join("search.json"))?), &index)?;
+
+    Ok(site.written)
+}
+
+impl Site<'_> {
+    fn gallery_item(&self, row: PublishedGallery) -> GalleryItem {
+        let gallery = row.gallery;
+        let mut tags = vec![];
+        let mut artists = vec![];
+        let mut raw_tags = vec![];
+        for (ns, names) in gallery.tags() {
+            let links = names.iter().map(|name| self.tag_link(ns, name)).collect::<Vec<_>>();
+            if ns == "artist" {
+                artists = links.clone();
+            }
+            tags.push(TagGroup { namespace: self.trans.trans_namespace(ns), tags: links });
+            raw_tags.extend(names.iter().map(|name| (ns.clone(), name.clone())));
+        }
+        GalleryItem {
+            id: gallery.id,
+            title: gallery.title(),
+            title_jp: gallery.title_jp(),
+            score: row.score.map(|score| format!("{:.2}", score * 100.)),
+            posted: gallery.posted.map(|posted| posted.format("%Y-%m-%d").to_string()),
+            pages: gallery.pages,
+            preview: row.preview,
+            url: gallery.url().url(),
+            href: format!("g/{}.html", gallery.id),
+            tags,
+            artists,
+            raw_score: row.score.unwrap_or(-1.),
+            posted_at: gallery.posted,
+            raw_tags,
+        }
+    }
+
+    fn tag_link(&self, namespace: &str, name: &str) -> TagLink {
+        let href = match namespace {
+            "artist" => format!("artist/{}.html", slug(name)),
+            _ => format!("tag/{}.{}.html", slug(namespace), slug(name)),
+        };
+        TagLink { name: self.trans.trans_raw(namespace, name), href }
+    }
+
+    fn search_entry<'g>(&self, gallery: &'g GalleryItem) -> SearchEntry<'g> {
+        let mut tags = vec![];
+        for group in &gallery.tags {
+            tags.extend(group.tags.iter().map(|tag| tag.name.clone()));
+        }
+        tags.extend(gallery.raw_tags.iter().map(|(ns, name)| format!("{}:{}", ns, name)));
+        SearchEntry {
+            title: &gallery.title,
+            title_jp: &gallery.title_jp,
+            tags,
+            score: gallery.score.as_deref(),
+            posted: gallery.posted.as_deref(),
+            href: &gallery.href,
+        }
+    }
+
+    /// 生成作者和标签的列表，以及每个作者和标签的画廊列表，画廊按发布时间排列
+    fn render_tags(&mut self, galleries: &[&GalleryItem]) -> Result<()> {
+        let mut tags = BTreeMap::<_, Vec<_>>::new();
+        for gallery in galleries {
+            for (ns, name) in &gallery.raw_tags {
+                tags.entry((ns.as_str(), name.as_str())).or_default().push(*gallery);
+            }
+        }
+
+        let mut groups = BTreeMap::<_, Vec<_>>::new();
+        for ((ns, name), galleries) in &tags {
+            let link = self.tag_link(ns, name);
+            let path = link.href.trim_end_matches(".html");
+            let title = match *ns {
+                "artist" => format!("作者：{}", link.name),
+                _ => format!("{}：{}", self.trans.trans_namespace(ns), link.name),
+            };
+            self.render_list(path, &title, galleries)?;
+            groups.entry(*ns).or_default().push(context! {
+                name => link.name,
+                href => link.href,
+                count => galleries.len(),
+            });
+        }
+
+        let artists = groups.remove("artist").unwrap_or_default();
+        self.render(
+            "artists.html",
+            "tags.html",
+            context! {
+                title => "作者",
+                groups => [context! { tags => artists }],
+            },
+        )?;
+        let groups = groups
+            .into_iter()
+            .map(|(ns, tags)| context! { namespace => self.trans.trans_namespace(ns), tags })
+            .collect::<Vec<_>>();
+        self.render("tags.html", "tags.html", context! { title => "标签", groups })
+    }
+
+    /// 分页生成画廊列表，第一页为 `{name}.html`，之后为 `{name}-2.html`、`{name}-3.html`……
+    fn render_list(&mut self, name: &str, title: &str, galleries: &[&GalleryItem]) -> Result<()> {
+        let total = galleries.len().div_ceil(PAGE_SIZE).max(1);
+        let path = |page: usize| match page {
+            1 => format!("{}.html", name),
+            _ => format!("{}-{}.html", name, page),
+        };
+        for page in 1..=total {
+            let start = (page - 1) * PAGE_SIZE;
+            let chunk = &galleries[start..galleries.len().min(start + PAGE_SIZE)];
+            self.render(
+                &path(page),
+                "list.html",
+                context! {
+                    title,
+                    galleries => chunk,
+                    page,
+                    total,
+                    prev => (page > 1).then(|| path(page - 1)),
+                    next => (page < total).then(|| path(page + 1)),
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 渲染一个页面，path 为相对于网站根目录的路径
+    ///
+    /// 模板中可以通过 `root` 变量获取从当前页面到网站根目录的相对路径
+    fn render(&mut self, path: &str, template: &str, ctx: Value) -> Result<()> {
+        let root = "../".repeat(path.matches('/').count());
+        let html = self.env.get_template(template)?.render(context! { root, ..ctx })?;
+        fs::write(self.output.join(path), html)?;
+        self.written += 1;
+        Ok(())
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// 将标签转换为可以安全地用作文件名的字符串
+///
+/// 小写字母、数字和 `-` 保持不变，空格转换为 `_`，其余字符按 UTF-8 字节转换为 `~xx`，保证不同的标签不会冲突
+fn slug(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        match c {
+            'a'..='z' | '0'..='9' | '-' => result.push(c),
+            ' ' => result.push('_'),
+            _ => {
+                for b in c.to_string().bytes() {
+                    write!(result, "~{:02x}", b).unwrap();
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{MessageEntity, MessageKind, PollEntity, VoteEntity};
+    use crate::ehentai::EhGallery;
+
+    #[test]
+    fn slug_is_file_safe() {
+        assert_eq!(slug("lolicon"), "lolicon");
+        assert_eq!(slug("big breasts"), "big_breasts");
+        assert_eq!(slug("a/b.c"), "a~2fb~2ec");
+        assert_eq!(slug("中"), "~e4~b8~ad");
+    }
+
+    #[tokio::test]
+    async fn generate_site() {
+        let dir = std::env::temp_dir().join(format!("exloli-site-{}", std::process::id()));
+        let trans_file = dir.join("db.text.json");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&trans_file, r#"{"data": []}"#).unwrap();
+        let trans = EhTagTransDB::new(trans_file.to_str().unwrap());
+
+        let db = Database::memory().await.unwrap();
+        for (id, posted) in
+            [(1, "2024-01-01T00:00:00"), (2, "2024-02-01T00:00:00"), (3, "2024-03-01T00:00:00")]
+        {
+            let gallery = EhGallery {
+                title: format!("<gallery {}>", id),
+                tags: [("artist", "foo bar"), ("female", "lolicon")]
+                    .into_iter()
+                    .map(|(ns, tag)| (ns.to_string(), vec![tag.to_string()]))
+                    .collect(),
+                posted: posted.parse().unwrap(),
+                ..EhGallery::mock(id, 0)
+            };
+            GalleryEntity::create(&db, &gallery).await.unwrap();
+        }
+        // 只有已经发布的画廊会被生成
+        for id in [1, 2] {
+            PollEntity::create(&db, id as i64, id).await.unwrap();
+            MessageEntity::create(&db, id, "", id, MessageKind::Text).await.unwrap();
+        }
+        VoteEntity::create(&db, 1, 1, 5).await.unwrap();
+        PollEntity::update_score(&db, 1).await.unwrap();
+
+        let output = dir.join("site");
+        // 旧的画廊页面会被清理
+        fs::create_dir_all(output.join("g")).unwrap();
+        fs::write(output.join("g/3.html"), "").unwrap();
+        fs::write(output.join("index-2.html"), "").unwrap();
+        fs::write(output.join("latest-3.html"), "").unwrap();
+        // index、latest、artists、tags、search、两个画廊、一个作者、一个标签
+        assert_eq!(generate(&db, &trans, &output).await.unwrap(), 9);
+        assert!(!output.join("g/3.html").exists());
+        assert!(!output.join("index-2.html").exists());
+        assert!(!output.join("latest-3.html").exists());
+
+        let index = fs::read_to_string(output.join("index.html")).unwrap();
+        assert!(index.find("g/1.html").unwrap() < index.find("g/2.html").unwrap());
+        assert!(index.contains("&lt;gallery 1&gt;"));
+        let latest = fs::read_to_string(output.join("latest.html")).unwrap();
+        assert!(latest.find("g/2.html").unwrap() < latest.find("g/1.html").unwrap());
+        let artist = fs::read_to_string(output.join("artist/foo_bar.html")).unwrap();
+        assert!(artist.contains(r#"href="../g/1.html""#));
+        assert!(output.join("tag/female.lolicon.html").exists());
+
+        let index: Vec<serde_json::Value> =
+            serde_json::from_reader(File::open(output.join("search.json")).unwrap()).unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[1]["score"], "0.00");
+        assert_eq!(index[0]["tags"][2], "artist:foo bar");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
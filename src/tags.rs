@@ -44,6 +44,13 @@ impl EhTagTransDB {
         Self { file: file.to_string(), db: Arc::new(RwLock::new(Some(db))) }
     }
 
+    /// 不包含任何翻译的数据库，用于测试
+    #[cfg(test)]
+    pub(crate) fn empty() -> Self {
+        let db = EhTagTransDBInner { data: vec![] };
+        Self { file: String::new(), db: Arc::new(RwLock::new(Some(db))) }
+    }
+
     pub async fn start(&self) {
         loop {
             if let Err(err) = self.update().await {
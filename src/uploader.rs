@@ -1,216 +1,380 @@
 use std::backtrace::Backtrace;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
 use std::time::Duration;
 
 use anyhow::{anyhow, bail, Result};
-use chrono::{Datelike, Utc};
-use futures::StreamExt;
-use regex::Regex;
-use reqwest::{Client, StatusCode};
-use telegraph_rs::{html_to_node, Telegraph};
-use teloxide::prelude::*;
-use teloxide::types::MessageId;
-use teloxide::utils::html::{code_inline, link};
-use tokio::task::JoinHandle;
+use chrono::{Datelike, NaiveDateTime, Utc};
+use futures::channel::mpsc;
+use futures::{stream, StreamExt};
+use reqwest::Client;
+use std::sync::{Arc, RwLock};
+use telegraph_rs::html_to_node;
+use teloxide::utils::html::escape;
+use teloxide::{ApiError, RequestError};
+use tokio::sync::{broadcast, Notify};
 use tokio::time;
-use tokio::sync::Mutex;
-use std::sync::Arc;
-use tracing::{debug, error, info, Instrument};
+use tracing::{debug, error, info, warn};
 
 use crate::bot::Bot;
-use crate::config::Config;
+use crate::config::{Channel, Config};
 use crate::database::{
-    GalleryEntity, ImageEntity, MessageEntity, PageEntity, PollEntity, TelegraphEntity,
+    CatboxAlbumEntity, Database, GalleryEntity, ImageEntity, JobEntity, JobStatus, MessageEntity,
+    PageEntity, PageImage, PollEntity, TelegraphEntity,
 };
-use crate::ehentai::{EhClient, EhGallery, EhGalleryUrl, GalleryInfo};
-use crate::catbox::CatboxUploader;
+use crate::ehentai::{EhClient, EhError, EhGallery, EhGalleryUrl, EhPageUrl, GalleryInfo};
+use crate::host::{new_host, CatboxUploader, HostKind, ImageHost};
+use crate::processor::{dhash, ImageProcessor};
 use crate::tags::EhTagTransDB;
-use crate::utils::pad_left;
+use crate::utils::ratelimit::{Backoff, RateLimiter};
+use crate::utils::url_of;
+
+mod dry_run;
+mod filter;
+mod health;
+mod progress;
+mod publish;
+mod post;
+mod registry;
+mod repair;
+mod telegraph;
+mod template;
+
+pub use dry_run::{DryRunReport, PlannedUpdate, PlannedUpload};
+pub use progress::{Progress, UploadProgress};
+pub use registry::{ActiveUpload, Cancelled, UploadRegistry};
+pub use telegraph::TelegraphPool;
+pub use template::{MessageContext, MessageTemplate, TagGroup};
 
 #[derive(Debug, Clone)]
 pub struct ExloliUploader {
     ehentai: EhClient,
-    telegraph: Telegraph,
+    telegraph: TelegraphPool,
     bot: Bot,
     config: Config,
+    db: Database,
     trans: EhTagTransDB,
     catbox_uploader: CatboxUploader,
+    /// 图片上传使用的图床
+    host: Arc<dyn ImageHost>,
+    /// 上传前的图片处理
+    processor: ImageProcessor,
+    /// 用于在有新任务时唤醒空闲的 worker
+    notify: Arc<Notify>,
+    /// 用于在有画廊等待发布时唤醒发布队列
+    publish_notify: Arc<Notify>,
+    /// 上传到 catbox 的限速器
+    catbox_limit: RateLimiter,
+    /// 请求失败时的重试策略
+    backoff: Backoff,
+    /// 上传进度的广播
+    progress: broadcast::Sender<(i32, Progress)>,
+    /// 正在执行的上传任务，用于取消
+    running: UploadRegistry,
+    /// 各个频道的消息模板
+    templates: Arc<RwLock<HashMap<String, MessageTemplate>>>,
+}
+
+/// 一次上传任务的结果
+#[derive(Debug)]
+pub enum UploadOutcome {
+    /// 文章已经准备好，等待发布到频道
+    Ready,
+    /// 已经发布过，不需要再做任何事
+    Done,
+    /// 不满足频道的筛选规则，在 until 之前不会再次检查
+    Skipped { rule: String, until: NaiveDateTime },
 }
 
 impl ExloliUploader {
     pub async fn new(
         config: Config,
+        db: Database,
         ehentai: EhClient,
         bot: Bot,
         trans: EhTagTransDB,
-        userhash: String, 
+        userhash: String,
     ) -> Result<Self> {
-        let telegraph = Telegraph::new(&config.telegraph.author_name)
-            .author_url(&config.telegraph.author_url)
-            .access_token(&config.telegraph.access_token)
-            .create()
-            .await?;
-            let catbox_uploader = CatboxUploader::new(&userhash);
-        Ok(Self { ehentai, config, telegraph, bot, trans, catbox_uploader })
+        let telegraph = TelegraphPool::new(&config).await?;
+        let catbox_uploader = CatboxUploader::new(&userhash);
+        let host = new_host(&config)?;
+        if !config.dry_run {
+            if let Err(err) = host.health_check().await {
+                warn!("图床不可用：{}", err);
+            }
+        }
+        let processor = ImageProcessor::new(config.image.clone());
+        let templates = load_templates(&config)?;
+        let notify = Arc::new(Notify::new());
+        let catbox_limit = RateLimiter::new(&config.limit.catbox);
+        let backoff = Backoff::new(&config.limit);
+        let (progress, _) = broadcast::channel(1024);
+        Ok(Self {
+            ehentai,
+            config,
+            db,
+            telegraph,
+            bot,
+            trans,
+            catbox_uploader,
+            host,
+            processor,
+            notify,
+            publish_notify: Arc::new(Notify::new()),
+            catbox_limit,
+            backoff,
+            progress,
+            running: UploadRegistry::default(),
+            templates: Arc::new(RwLock::new(templates)),
+        })
     }
 }
 
 impl ExloliUploader {
-    // 负责上传图片到 Catbox，并同时创建专辑
-    pub async fn create_album_and_upload_images(
-        &self,
-        gallery: &EhGallery,
-        file_urls: Vec<String>, // 文件 URLs
-    ) -> Result<String> {
-        let album_name = &gallery.title_jp(); // 使用画廊的标题作为专辑名称
-        let description = &self.config.telegraph.author_name; // 使用 config.toml 中的 author_name 作为描述
-
-        // 将图片分成若干批次
-        let chunk_size = 35;
-        let file_chunks = file_urls.chunks(chunk_size);
-
-        let mut all_files = Vec::new();
-
-        // 分批上传文件
-        for chunk in file_chunks {
-            let file_urls_batch = chunk.to_vec();
-            let album_url = self.catbox_uploader.create_album(album_name, description, file_urls_batch).await?;
-            all_files.push(album_url);
+    /// 恢复未完成的任务并启动 worker，之后每隔 interval 分钟检查一次
+    pub async fn start(&self) {
+        match JobEntity::adopt(&self.db, &self.config.default_channel().id()).await {
+            Ok(0) => {}
+            Ok(n) => info!("将 {} 个旧任务分配给频道 {}", n, self.config.default_channel().name),
+            Err(err) => error!("分配旧任务失败：{}", err),
+        }
+        match JobEntity::resume(&self.db).await {
+            Ok(0) => {}
+            Ok(n) => info!("恢复 {} 个未完成的上传任务", n),
+            Err(err) => error!("恢复上传任务失败：{}", err),
+        }
+        for _ in 0..self.config.queue.workers {
+            let uploader = self.clone();
+            tokio::spawn(async move { uploader.work().await });
+        }
+        for channel in &self.config.channels {
+            let uploader = self.clone();
+            let channel = channel.clone();
+            tokio::spawn(async move { uploader.publish_loop(&channel).await });
+        }
+        if self.config.health.enabled {
+            let uploader = self.clone();
+            tokio::spawn(async move { uploader.health_loop().await });
+        }
+        loop {
+            for channel in &self.config.channels {
+                info!("开始扫描 E 站 本子：{}", channel.name);
+                self.check(channel).await;
+            }
+            info!("扫描完毕，等待 {:?} 后继续", self.config.interval);
+            time::sleep(self.config.interval).await;
         }
-
-        // 返回所有生成的专辑链接
-        Ok(all_files.join("\\n"))
     }
 
-    // 更新专辑，将新的图片上传并同步更新专辑
-    pub async fn update_album_with_new_images(
-        &self,
-        short: &str,  // 专辑的短链接
-        gallery: &EhGallery,
-        new_file_urls: Vec<String>, // 新的文件 URLs
-    ) -> Result<()> {
-        let album_name = &gallery.title_jp(); // 使用画廊的标题作为专辑名称
-        let description = &self.config.telegraph.author_name; // 使用 config.toml 中的 author_name 作为描述
-
-        // 将图片分成若干批次
-        let chunk_size = 35;
-        let file_chunks = new_file_urls.chunks(chunk_size);
-
-        // 分批更新专辑中的文件
-        for chunk in file_chunks {
-            let new_file_urls_batch = chunk.to_vec();
-            self.catbox_uploader.edit_album(short, album_name, description, new_file_urls_batch).await?;
+    /// 根据频道的配置，扫描前 N 个本子，进行更新并将未上传的本子加入上传队列
+    #[tracing::instrument(skip_all, fields(channel = channel.name))]
+    async fn check(&self, channel: &Channel) {
+        let stream = self.ehentai.search_iter(&channel.search_params).take(channel.search_count);
+        tokio::pin!(stream);
+        while let Some(next) = stream.next().await {
+            if let Err(err) = self.try_update(&next, true).await {
+                error!("check_and_update: {:?}\n{}", err, Backtrace::force_capture());
+            }
+            if let Err(err) = self.enqueue(&next, channel, true).await {
+                error!("check_and_enqueue: {:?}\n{}", err, Backtrace::force_capture());
+            }
+            time::sleep(Duration::from_secs(1)).await;
         }
-
-        Ok(())
     }
 
-
-    async fn upload_gallery_images(
+    /// 将画廊加入指定频道的上传队列，返回任务 ID
+    ///
+    /// 如果 check 为 true 且画廊已经发布到该频道，或者最近因为筛选规则被跳过，则不会创建任务，返回 None
+    pub async fn enqueue(
         &self,
-        gallery: &EhGallery,
-    ) -> Result<Vec<String>> {
-        let mut uploaded_urls = Vec::new();
-
-        for page in &gallery.pages {
-            let image_url = self.ehentai.get_image_url(page).await?; 
-            let uploaded_url = self.catbox_uploader.upload_file(&image_url.1).await?; 
-            uploaded_urls.push(uploaded_url); 
+        gallery: &EhGalleryUrl,
+        channel: &Channel,
+        check: bool,
+    ) -> Result<Option<i64>> {
+        if check && self.is_uploaded(gallery.id(), channel).await? {
+            return Ok(None);
         }
-
-        Ok(uploaded_urls)
+        if check && JobEntity::is_skipped(&self.db, gallery.id(), &channel.id()).await? {
+            debug!("最近被跳过：{}", gallery);
+            return Ok(None);
+        }
+        let id = JobEntity::create(&self.db, gallery, &channel.id(), !check).await?;
+        debug!("加入上传队列：{} -> {}", gallery, id);
+        self.notify.notify_one();
+        Ok(Some(id))
     }
 
-    // 上传画廊并创建/更新专辑
-    pub async fn upload_gallery_and_create_album(
-        &self,
-        gallery: &EhGallery,
-    ) -> Result<String> {
-
-        let uploaded_urls = self.upload_gallery_images(gallery).await?;
-
-        let album_short_url = self.create_album_and_upload_images(gallery, uploaded_urls).await?;
-
-        Ok(album_short_url)
+    /// 检查画廊是否已经上传并发布到指定频道
+    async fn is_uploaded(&self, id: i32, channel: &Channel) -> Result<bool> {
+        Ok(GalleryEntity::check(&self.db, id).await?
+            && MessageEntity::get_by_gallery(&self.db, id, &channel.id()).await?.is_some())
     }
 
-    /// 每隔 interval 分钟检查一次
-    pub async fn start(&self) {
+    /// 不断从上传队列中领取任务并执行
+    async fn work(&self) {
         loop {
-            info!("开始扫描 E 站 本子");
-            self.check().await;
-            info!("扫描完毕，等待 {:?} 后继续", self.config.interval);
-            time::sleep(self.config.interval).await;
+            match JobEntity::claim(&self.db).await {
+                Ok(Some(job)) => self.run_job(job).await,
+                Ok(None) => {
+                    tokio::select! {
+                        _ = self.notify.notified() => {}
+                        _ = time::sleep(Duration::from_secs(60)) => {}
+                    }
+                }
+                Err(err) => {
+                    error!("领取上传任务失败：{}", err);
+                    time::sleep(Duration::from_secs(60)).await;
+                }
+            }
         }
     }
 
-    /// 根据配置文件，扫描前 N 个本子，并进行上传或者更新
-    #[tracing::instrument(skip(self))]
-    async fn check(&self) {
-        let stream = self
-            .ehentai
-            .search_iter(&self.config.exhentai.search_params)
-            .take(self.config.exhentai.search_count);
-        tokio::pin!(stream);
-        while let Some(next) = stream.next().await {
-            if let Err(err) = self.try_update(&next, true).await {
-                error!("check_and_update: {:?}\n{}", err, Backtrace::force_capture());
+    /// 执行一个上传任务，并根据结果更新任务状态
+    #[tracing::instrument(skip(self, job), fields(job = job.id))]
+    async fn run_job(&self, job: JobEntity) {
+        info!("开始执行任务：{}（第 {} 次）", job.url, job.attempts);
+        let channel = self.config.channel(&job.channel_id);
+        let (id, result) = match (job.url.parse::<EhGalleryUrl>(), channel) {
+            (Ok(url), Some(channel)) => {
+                let _guard = self.running.register(job.id, &url);
+                (Some(url.id()), self.try_upload(&url, channel, !job.force).await)
             }
-            if let Err(err) = self.try_upload(&next, true).await {
-                error!("check_and_upload: {:?}\n{}", err, Backtrace::force_capture());
+            (Ok(url), None) => (Some(url.id()), Err(anyhow!("找不到频道：{}", job.channel_id))),
+            (Err(err), _) => (None, Err(err.into())),
+        };
+        let result = match result {
+            Ok(outcome) => {
+                if let Some(id) = id {
+                    self.report(id, Progress::Finished { error: None, retry: false });
+                }
+                match outcome {
+                    UploadOutcome::Ready => {
+                        // 每个频道有各自的发布队列，唤醒所有等待中的发布队列
+                        self.publish_notify.notify_waiters();
+                        JobEntity::ready(&self.db, job.id).await
+                    }
+                    UploadOutcome::Done => JobEntity::done(&self.db, job.id).await,
+                    UploadOutcome::Skipped { rule, until } => {
+                        JobEntity::skip(&self.db, job.id, &rule, until).await
+                    }
+                }
             }
-            time::sleep(Duration::from_secs(1)).await;
+            Err(err) => {
+                error!("try_upload: {:?}\n{}", err, Backtrace::force_capture());
+                // 画廊被删除、cookie 失效等错误重试也不会成功，直接将任务标记为失败
+                let permanent = err.downcast_ref::<EhError>().is_some_and(EhError::is_permanent)
+                    || err.is::<Cancelled>();
+                let retry = !permanent && job.attempts < self.config.queue.max_attempts;
+                let delay = chrono::Duration::from_std(self.config.queue.retry_delay)
+                    .unwrap_or_default()
+                    * job.attempts;
+                // 取消时 cancel 已经发送了结束事件
+                if let Some(id) = id.filter(|_| !err.is::<Cancelled>()) {
+                    self.report(id, Progress::Finished { error: Some(err.to_string()), retry });
+                }
+                JobEntity::fail(&self.db, job.id, &err.to_string(), retry, delay).await
+            }
+        };
+        if let Err(err) = result {
+            error!("更新任务状态失败：{}", err);
         }
     }
 
-    /// 检查指定画廊是否已经上传，如果没有则进行上传
-    #[tracing::instrument(skip(self))]
-    pub async fn try_upload(&self, gallery: &EhGalleryUrl, check: bool) -> Result<()> {
-        if check
-            && GalleryEntity::check(gallery.id()).await?
-            && MessageEntity::get_by_gallery(gallery.id()).await?.is_some()
-        {
-            return Ok(());
+    /// 检查指定画廊是否已经发布到频道，如果没有则上传图片并发布文章
+    #[tracing::instrument(skip(self, channel), fields(channel = channel.name))]
+    pub async fn try_upload(
+        &self,
+        gallery: &EhGalleryUrl,
+        channel: &Channel,
+        check: bool,
+    ) -> Result<UploadOutcome> {
+        if check && self.is_uploaded(gallery.id(), channel).await? {
+            return Ok(UploadOutcome::Done);
         }
 
         let gallery = self.ehentai.get_gallery(gallery).await?;
-        self.upload_gallery_image(&gallery).await?;
-        let article = self.publish_telegraph_article(&gallery).await?;
-        let text = self.create_message_text(&gallery, &article.url).await?;
-        
-        let msg = if let Some(parent) = &gallery.parent {
-            if let Some(pmsg) = MessageEntity::get_by_gallery(parent.id()).await? {
-                self.bot
-                    .send_message(self.config.telegram.channel_id.clone(), text)
-                    .reply_to_message_id(MessageId(pmsg.id))
-                    .await?
-            } else {
-                self.bot.send_message(self.config.telegram.channel_id.clone(), text).await?
+        // 管理员手动上传的画廊不受筛选规则限制
+        if check {
+            if let Some(rule) = channel.filter.matched_rule(&gallery) {
+                info!("跳过画廊 {}：{}", gallery.url, rule);
+                let until = channel.filter.recheck_at(&gallery);
+                return Ok(UploadOutcome::Skipped { rule, until });
             }
-        } else {
-            self.bot.send_message(self.config.telegram.channel_id.clone(), text).await?
-        };
-        
-        MessageEntity::create(msg.id.0, gallery.url.id()).await?;
-        TelegraphEntity::create(gallery.url.id(), &article.url).await?;
-        GalleryEntity::create(&gallery).await?;
+            // 已经有文章并且所有页面都已上传的画廊（例如发布到了其他频道）直接使用已有的图片和文章
+            if TelegraphEntity::get(&self.db, gallery.url.id()).await?.is_some()
+                && PageEntity::count(&self.db, gallery.url.id()).await? as usize
+                    == gallery.pages.len()
+            {
+                info!("使用已有的文章：{}", gallery.url);
+                return Ok(UploadOutcome::Ready);
+            }
+        }
+        self.upload_or_cancel(&gallery).await?;
+        self.flag_ads(gallery.url.id()).await?;
+        JobEntity::update_status(&self.db, gallery.url.id(), JobStatus::Publishing).await?;
+        let (account, article) = self.publish_telegraph_article(&gallery).await?;
+        self.report(gallery.url.id(), Progress::Published(article[0].clone()));
+        // 专辑只是额外的入口，创建失败不影响发布
+        if let Err(err) = self.sync_albums(&gallery).await {
+            error!("同步 catbox 专辑失败：{:?}", err);
+        }
+        TelegraphEntity::create(&self.db, gallery.url.id(), &account, &article).await?;
 
+        Ok(UploadOutcome::Ready)
+    }
+
+    /// 下载并上传画廊的图片，期间被管理员取消时删除本次新增的页面记录
+    ///
+    /// 只有下载和上传图片的阶段可以取消，开始发布后再取消会留下不完整的消息
+    async fn upload_or_cancel(&self, gallery: &EhGallery) -> Result<()> {
+        let token = self.running.token(gallery.url.id());
+        let existing = PageEntity::list_pages(&self.db, gallery.url.id()).await?;
+        tokio::select! {
+            result = self.upload_gallery_image(gallery) => result?,
+            _ = token.cancelled() => {
+                info!("上传已取消，回滚新增的页面");
+                PageEntity::delete_except(&self.db, gallery.url.id(), &existing).await?;
+                return Err(Cancelled.into());
+            }
+        }
+        self.running.remove(gallery.url.id());
+        Ok(())
+    }
+
+    /// 沿着父画廊向上更新频道中旧版本的消息，使其指向最新的版本
+    async fn update_old_versions(&self, channel: &Channel, mut parent: Option<i32>) -> Result<()> {
+        // 限制深度，避免父画廊形成环时无限循环
+        for _ in 0..10 {
+            let Some(id) = parent else { break };
+            let (Some(entity), Some(msg), Some(telegraph)) = (
+                GalleryEntity::get(&self.db, id).await?,
+                MessageEntity::get_by_gallery(&self.db, id, &channel.id()).await?,
+                TelegraphEntity::get(&self.db, id).await?,
+            ) else {
+                break;
+            };
+            let text = self.create_message_text(channel, &entity, &telegraph.url).await?;
+            self.edit_post(channel, &msg, text).await?;
+            debug!("已更新旧版本消息：{}", id);
+            parent = entity.parent;
+        }
         Ok(())
     }
 
-    /// 检查指定画廊是否有更新，比如标题、标签
+    /// 检查指定画廊是否有更新，比如标题、标签，并更新所有频道中的消息
     #[tracing::instrument(skip(self))]
     pub async fn try_update(&self, gallery: &EhGalleryUrl, check: bool) -> Result<()> {
-        let entity = match GalleryEntity::get(gallery.id()).await? {
+        let entity = match GalleryEntity::get(&self.db, gallery.id()).await? {
             Some(v) => v,
             _ => return Ok(()),
         };
-        let message = match MessageEntity::get_by_gallery(gallery.id()).await? {
-            Some(v) => v,
-            _ => return Ok(()),
+        let messages = self.messages_of(gallery.id()).await?;
+        let Some(published) = messages.iter().map(|(_, msg)| msg.publish_date).min() else {
+            return Ok(());
         };
 
         let now = Utc::now().date_naive();
-        let seed = match now - message.publish_date {
+        let seed = match now - published {
             d if d < chrono::Duration::days(2) => 1,
             d if d < chrono::Duration::days(7) => 3,
             d if d < chrono::Duration::days(14) => 7,
@@ -222,192 +386,814 @@ impl ExloliUploader {
 
         let gallery = self.ehentai.get_gallery(gallery).await?;
 
-        if gallery.tags != entity.tags.0 || gallery.title != entity.title {
-            let telegraph = TelegraphEntity::get(gallery.url.id()).await?.unwrap();
-            let text = self.create_message_text(&gallery, &telegraph.url).await?;
-            self.bot
-                .edit_message_text(
-                    self.config.telegram.channel_id.clone(),
-                    MessageId(message.id),
-                    text,
-                )
-                .await?;
+        // 页数变化时，上传新的图片，并重新发布文章、同步专辑
+        let new_pages = gallery.pages.len() as i32 != entity.pages;
+        if new_pages {
+            info!("页数变化：{} -> {}", entity.pages, gallery.pages.len());
+            self.upload_gallery_image(&gallery).await?;
+            self.flag_ads(gallery.url.id()).await?;
+            let (account, article) = self.publish_telegraph_article(&gallery).await?;
+            TelegraphEntity::update(&self.db, gallery.url.id(), &account, &article).await?;
+            if let Err(err) = self.sync_albums(&gallery).await {
+                error!("同步 catbox 专辑失败：{:?}", err);
+            }
         }
 
-        GalleryEntity::create(&gallery).await?;
+        if new_pages || gallery.tags != entity.tags.0 || gallery.title != entity.title {
+            let telegraph = TelegraphEntity::get(&self.db, gallery.url.id()).await?.unwrap();
+            for (channel, message) in &messages {
+                let text = self.create_message_text(channel, &gallery, &telegraph.url).await?;
+                self.edit_post(channel, message, text).await?;
+            }
+        }
+
+        GalleryEntity::create(&self.db, &gallery).await?;
 
         Ok(())
     }
 
-    /// 重新发布指定画廊的文章，并更新消息
-    pub async fn republish(&self, gallery: &GalleryEntity, msg: &MessageEntity) -> Result<()> {
-        info!("重新发布：{}", msg.id);
-        let article = self.publish_telegraph_article(gallery).await?;
-        let text = self.create_message_text(gallery, &article.url).await?;
-        self.bot
-            .edit_message_text(self.config.telegram.channel_id.clone(), MessageId(msg.id), text)
-            .await?;
-        TelegraphEntity::update(gallery.id, &article.url).await?;
+    /// 重新发布指定画廊的文章，并更新所有频道中的消息
+    pub async fn republish(&self, gallery: &GalleryEntity) -> Result<()> {
+        info!("重新发布：{}", gallery.id);
+        let (account, article) = self.publish_telegraph_article(gallery).await?;
+        TelegraphEntity::update(&self.db, gallery.id, &account, &article).await?;
+        for (channel, msg) in self.messages_of(gallery.id).await? {
+            let text = self.create_message_text(channel, gallery, &article[0]).await?;
+            self.edit_post(channel, &msg, text).await?;
+        }
         Ok(())
     }
 
-    /// 检查 telegraph 文章是否正常
-    pub async fn check_telegraph(&self, url: &str) -> Result<bool> {
-        Ok(Client::new().head(url).send().await?.status() != StatusCode::NOT_FOUND)
+    /// 画廊在各个频道中的消息
+    async fn messages_of(&self, gallery_id: i32) -> Result<Vec<(&Channel, MessageEntity)>> {
+        let mut messages = vec![];
+        for channel in &self.config.channels {
+            let msg = MessageEntity::get_by_gallery(&self.db, gallery_id, &channel.id()).await?;
+            if let Some(msg) = msg {
+                messages.push((channel, msg));
+            }
+        }
+        Ok(messages)
     }
 }
 
 impl ExloliUploader {
-   /// 获取某个画廊里的所有图片，并且上传到 telegraph，如果已经上传过的，会跳过上传
+    /// 获取某个画廊里的所有图片，并且上传到 telegraph，如果已经上传过的，会跳过上传
     async fn upload_gallery_image(&self, gallery: &EhGallery) -> Result<()> {
+        // 新版本的画廊先与父画廊逐页对比，未变化的页面直接使用父画廊的图片
+        let parent_images = match &gallery.parent {
+            Some(parent) => ImageEntity::get_by_gallery_id(&self.db, parent.id())
+                .await?
+                .into_iter()
+                .map(|img| (img.hash, img.id))
+                .collect(),
+            None => HashMap::new(),
+        };
         let mut pages = vec![];
         for page in &gallery.pages {
-            match ImageEntity::get_by_hash(page.hash()).await? {
-                Some(img) => {
-                    PageEntity::create(page.gallery_id(), page.page(), img.id).await?;
+            let image_id = match parent_images.get(page.hash()) {
+                Some(id) => Some(*id),
+                None => ImageEntity::get_by_hash(&self.db, page.hash()).await?.map(|img| img.id),
+            };
+            match image_id {
+                Some(id) => {
+                    PageEntity::create(&self.db, page.gallery_id(), page.page(), id).await?;
                 }
                 None => pages.push(page.clone()),
             }
         }
         info!("需要下载&上传 {} 张图片", pages.len());
+        let (total, pending) = (gallery.pages.len(), pages.len());
+        self.report(gallery.url.id(), Progress::Started { total, pending });
 
-        let concurrent = self.config.threads_num;
-        let (tx, mut rx) = tokio::sync::mpsc::channel(concurrent * 2);
-        let client = self.ehentai.clone();
-
-        let getter = tokio::spawn(
-            async move {
-                for page in pages {
-                    let rst = client.get_image_url(&page).await?;
-                    info!("已解析：{}", page.page());
-                    tx.send((page, rst)).await?;
-                }
-                Result::<()>::Ok(())
-            }
-            .in_current_span(),
-        );
-
-        let catbox_uploader = CatboxUploader::new(&self.config.catbox.userhash);
-        let client = Arc::new(Mutex::new(Client::builder()
+        // 下载和上传分别由 threads_num 和 upload_threads_num 个 worker 并发执行，中间通过 channel 连接
+        let client = Client::builder()
             .timeout(Duration::from_secs(30))
             .connect_timeout(Duration::from_secs(30))
-            .build()?));
-        let uploader = tokio::spawn(
-            async move {
-                while let Some((page, (fileindex, url))) = rx.recv().await {
-                    let filename = format!("{}.{}", page.hash(), url.split('.').last().unwrap_or("jpg"));
-                    let client = client.clone();  
-                    let bytes = client.lock().await.get(url).send().await?.bytes().await?;
-                    debug!("已下载: {}", page.page());
-                    let uploaded_url = catbox_uploader.upload_file(&filename).await?;
-                    debug!("已上传: {}", page.page());
-                    ImageEntity::create(fileindex, page.hash(), &uploaded_url).await?;
-                    PageEntity::create(page.gallery_id(), page.page(), fileindex).await?;
+            .build()?;
+        let client = &client;
+        let (tx, rx) = mpsc::channel(self.config.upload_threads_num);
+
+        let downloader = stream::iter(pages)
+            .map(|page| async move {
+                let result = self.download_page(client, &page).await;
+                (page, result)
+            })
+            .buffer_unordered(self.config.threads_num.max(1))
+            .map(Ok)
+            .forward(tx);
+
+        let uploader = rx
+            .map(|(page, result)| async move {
+                let result = match result {
+                    Ok(image) => self.upload_page(&page, image).await,
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(()) => {
+                        self.report(page.gallery_id(), Progress::Uploaded(page.page()));
+                        Ok(())
+                    }
+                    Err(err) => {
+                        error!("第 {} 页上传失败：{:?}", page.page(), err);
+                        let progress = Progress::Failed(page.page(), err.to_string());
+                        self.report(page.gallery_id(), progress);
+                        Err(page.page())
+                    }
                 }
-                Result::<()>::Ok(())
+            })
+            .buffer_unordered(self.config.upload_threads_num.max(1))
+            .filter_map(|result| async move { result.err() })
+            .collect::<Vec<_>>();
+
+        let (_, mut failed) = tokio::join!(downloader, uploader);
+        if !failed.is_empty() {
+            failed.sort();
+            bail!("{} 张图片上传失败：{:?}", failed.len(), failed);
+        }
+
+        Ok(())
+    }
+
+    /// 解析并下载一页的图片，失败时按照退避策略重试
+    ///
+    /// E 站请求本身的暂时性错误已经在 EhClient 中重试过了，这里只重试 H@H 节点失效和图片下载失败，
+    /// 重新解析页面可以换到另一个节点上
+    async fn download_page(&self, client: &Client, page: &EhPageUrl) -> Result<DownloadedImage> {
+        let download = || async move {
+            let (fileindex, url) = self.ehentai.get_image_url(page).await?;
+            info!("已解析：{}", page.page());
+            self.report(page.gallery_id(), Progress::Resolved(page.page()));
+            let (data, phash) = download_image(client, &url).await?;
+            self.report(page.gallery_id(), Progress::Downloaded(page.page()));
+            Ok(DownloadedImage { fileindex, url, data, phash })
+        };
+        let should_retry = |err: &anyhow::Error| match err.downcast_ref::<EhError>() {
+            Some(err) => matches!(err, EhError::HaHUrlBroken(_)),
+            None => true,
+        };
+        self.backoff.retry_if(download, should_retry).await
+    }
+
+    /// 将下载好的图片上传到图床，并记录到数据库中
+    async fn upload_page(&self, page: &EhPageUrl, image: DownloadedImage) -> Result<()> {
+        let DownloadedImage { fileindex, url, data, phash } = image;
+        // 内容相同但编码不同的图片（例如广告页）直接复用已经上传的版本
+        let similar = match phash {
+            Some(phash) => {
+                ImageEntity::find_similar(&self.db, phash, self.config.dedup.reuse_distance).await?
             }
-            .in_current_span(),
-        );
+            None => vec![],
+        };
+        let (uploaded_url, kind) = match similar.into_iter().next() {
+            Some((img, distance)) => {
+                debug!("复用相似图片: {} -> {} ({})", page.page(), img.id, distance);
+                (img.raw_url().to_string(), img.host)
+            }
+            None => {
+                let url = self.upload_to_host(page, &url, data).await?;
+                debug!("已上传: {}", page.page());
+                (url, self.host.kind())
+            }
+        };
+        ImageEntity::create(&self.db, fileindex, page.hash(), &uploaded_url, kind, phash).await?;
+        PageEntity::create(&self.db, page.gallery_id(), page.page(), fileindex).await?;
+        Ok(())
+    }
 
-        tokio::try_join!(flatten(getter), flatten(uploader))?;
+    /// 处理图片并上传到当前的图床，返回图床上的 URL，url 为图片的原始地址，用于确定文件扩展名
+    async fn upload_to_host(&self, page: &EhPageUrl, url: &str, data: Vec<u8>) -> Result<String> {
+        let filename = image_filename(page.hash(), url);
+        let (data, filename) = process_image(&self.processor, data, &filename).await?;
+        self.backoff
+            .retry(|| async {
+                if self.host.kind() == HostKind::Catbox {
+                    self.catbox_limit.acquire().await;
+                }
+                self.host.upload(&filename, data.clone()).await
+            })
+            .await
+    }
 
+    /// 检查画廊中的图片是否在其他画廊中也出现过，将出现次数过多的图片标记为广告
+    async fn flag_ads(&self, gallery_id: i32) -> Result<()> {
+        let config = &self.config.dedup;
+        if config.ad_threshold == 0 {
+            return Ok(());
+        }
+        for img in ImageEntity::get_by_gallery_id(&self.db, gallery_id).await? {
+            let Some(phash) = img.phash.filter(|_| !img.ad) else { continue };
+            let ids = ImageEntity::find_similar(&self.db, phash, config.ad_distance)
+                .await?
+                .into_iter()
+                .map(|(img, _)| img.id)
+                .collect::<Vec<_>>();
+            let count = PageEntity::count_galleries(&self.db, &ids).await?;
+            if count >= config.ad_threshold {
+                info!("标记广告图片：{:?}（出现在 {} 个画廊中）", ids, count);
+                ImageEntity::mark_ad(&self.db, &ids).await?;
+            }
+        }
         Ok(())
     }
 
-    /// 从数据库中读取某个画廊的所有图片，生成一篇 telegraph 文章
+    /// 从数据库中读取某个画廊的所有图片，生成 telegraph 文章，返回发布所用的账号和按顺序排列的文章 URL
+    ///
+    /// 内容超出 telegraph 的大小限制时，会被拆分为多篇文章，文章之间通过上一篇/下一篇的链接相连，
+    /// 同一个画廊的所有文章都由同一个账号发布，以便之后编辑
     async fn publish_telegraph_article<T: GalleryInfo>(
         &self,
         gallery: &T,
-    ) -> Result<telegraph_rs::Page> {
-        let images = ImageEntity::get_by_gallery_id(gallery.url().id()).await?;
+    ) -> Result<(String, Vec<String>)> {
+        let content = self.render_article(gallery).await?;
+        if content.chunks.len() == 1 {
+            let html = content.html(0, &[]);
+            let (account, page) = self.create_telegraph_page(None, &content.title(0), &html).await?;
+            return Ok((account, vec![page.url]));
+        }
 
-        let mut html = String::new();
-        if gallery.cover() != 0 && gallery.cover() < images.len() {
-            html.push_str(&format!(r#"<img src="{}">"#, images[gallery.cover()].url()))
+        // 第一篇文章决定使用的账号，之后的文章即使被限流也只能等待
+        let mut account = None;
+        let mut pages = vec![];
+        for (i, chunk) in content.chunks.iter().enumerate() {
+            let html = format!("{}{}", content.header, chunk);
+            let (name, page) =
+                self.create_telegraph_page(account.as_deref(), &content.title(i), &html).await?;
+            account = Some(name);
+            pages.push(page);
         }
-        for img in images {
-            html.push_str(&format!(r#"<img src="{}">"#, img.url()));
+        let account = account.unwrap();
+        info!("文章过长，已拆分为 {} 篇", pages.len());
+
+        // 所有文章都发布之后才能知道彼此的 URL，再补上导航链接
+        let urls = pages.iter().map(|page| page.url.clone()).collect::<Vec<_>>();
+        for (i, page) in pages.iter().enumerate() {
+            self.edit_telegraph_page(&account, &page.path, &page.title, &content.html(i, &urls))
+                .await?;
         }
-        html.push_str(&format!("<p>图片总数：{}</p>", gallery.pages()));
 
-        let node = html_to_node(&html);
-        let title = gallery.title_jp();
-        Ok(self.telegraph.create_page(&title, &node, false).await?)
+        Ok((account, urls))
     }
 
-    /// 为画廊生成一条可供发送的 telegram 消息正文
+    /// 从数据库中读取某个画廊的所有图片，按照 telegraph 的大小限制生成文章内容
+    async fn render_article<T: GalleryInfo>(&self, gallery: &T) -> Result<ArticleContent> {
+        let pages = PageImage::get_by_gallery_id(&self.db, gallery.url().id()).await?;
+
+        let captions = self.config.telegraph.page_captions;
+        let mut imgs = vec![];
+        // cover 是从 0 开始的序号，需要在去掉广告之前按页码找到封面
+        let cover = pages
+            .iter()
+            .find(|p| gallery.cover() != 0 && p.page == gallery.cover() as i32 + 1);
+        if let Some(cover) = cover.filter(|p| !p.image.ad) {
+            imgs.push(figure(&cover.image.url(), captions.then_some("封面")));
+        }
+        for p in pages.iter().filter(|p| !p.image.ad) {
+            let caption = captions.then(|| format!("第 {} 页", p.page));
+            imgs.push(figure(&p.image.url(), caption.as_deref()));
+        }
+        let footer = format!("<p>图片总数：{}</p>", gallery.pages());
+        let header = match self.config.telegraph.header {
+            true => self.telegraph_header(gallery),
+            false => String::new(),
+        };
+
+        // 按照转换为 node 之后的大小对图片进行分组，每篇文章都会带上开头的画廊信息
+        let limit = TELEGRAPH_CONTENT_LIMIT.saturating_sub(html_to_node(&header).len());
+        let mut chunks = vec![String::new()];
+        let mut size = 0;
+        for img in imgs {
+            let len = html_to_node(&img).len();
+            if size + len > limit && size != 0 {
+                chunks.push(String::new());
+                size = 0;
+            }
+            size += len;
+            chunks.last_mut().unwrap().push_str(&img);
+        }
+
+        Ok(ArticleContent { title: gallery.title_jp(), header, chunks, footer })
+    }
+
+    /// 文章开头的画廊信息：标题、日文标题、翻译后的标签、来源和发布时间
+    fn telegraph_header<T: GalleryInfo>(&self, gallery: &T) -> String {
+        let mut html = format!("<h4>{}</h4>", escape(&gallery.title()));
+        if gallery.title_jp() != gallery.title() {
+            html.push_str(&format!("<p>{}</p>", escape(&gallery.title_jp())));
+        }
+        for (ns, tags) in self.trans.trans_tags(gallery.tags()) {
+            html.push_str(&format!("<p><b>{}</b>：{}</p>", escape(&ns), escape(&tags.join("、"))));
+        }
+        let url = gallery.url().url();
+        html.push_str(&format!(r#"<p>来源：<a href="{}">{}</a></p>"#, url, url));
+        if let Some(posted) = gallery.posted() {
+            html.push_str(&format!("<p>发布时间：{}</p>", posted.format("%Y-%m-%d %H:%M")));
+        }
+        html.push_str("<hr>");
+        html
+    }
+
+    /// 使用发布文章的账号编辑一篇 telegraph 文章
+    async fn edit_telegraph_page(
+        &self,
+        account: &str,
+        path: &str,
+        title: &str,
+        html: &str,
+    ) -> Result<()> {
+        let node = html_to_node(html);
+        self.backoff.retry(|| self.telegraph.edit_page(account, path, title, &node)).await?;
+        Ok(())
+    }
+
+    /// 发布一篇 telegraph 文章，不指定账号时由账号池选择，返回发布所用的账号和文章
+    async fn create_telegraph_page(
+        &self,
+        account: Option<&str>,
+        title: &str,
+        html: &str,
+    ) -> Result<(String, telegraph_rs::Page)> {
+        let node = html_to_node(html);
+        self.backoff.retry(|| self.telegraph.create_page(account, title, &node)).await
+    }
+
+    /// 将画廊中存放在 catbox 上的图片同步到 catbox 专辑中，专辑不存在时创建
+    ///
+    /// 单个专辑最多 500 个文件，超出时按顺序拆分为多个专辑
+    async fn sync_albums<T: GalleryInfo>(&self, gallery: &T) -> Result<()> {
+        let id = gallery.url().id();
+        let files = ImageEntity::get_by_gallery_id(&self.db, id)
+            .await?
+            .into_iter()
+            .filter(|img| img.host == HostKind::Catbox && !img.ad)
+            .filter_map(|img| img.raw_url().rsplit('/').next().map(String::from))
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        let albums = CatboxAlbumEntity::list(&self.db, id).await?;
+        let chunks = files.chunks(CATBOX_ALBUM_LIMIT).collect::<Vec<_>>();
+        let description = &self.config.telegraph.author_name;
+        for (part, chunk) in chunks.iter().enumerate() {
+            let title = match chunks.len() {
+                1 => gallery.title_jp(),
+                n => format!("{} ({}/{})", gallery.title_jp(), part + 1, n),
+            };
+            match albums.iter().find(|album| album.part == part as i32) {
+                // 比较文件列表而不是数量，被替换的页面也需要同步到专辑中
+                Some(album) if album.file_list.as_slice() == *chunk => {}
+                Some(album) => {
+                    self.catbox_limit.acquire().await;
+                    self.catbox_uploader
+                        .edit_album(&album.short, &title, description, chunk)
+                        .await?;
+                    CatboxAlbumEntity::update_files(&self.db, &album.short, chunk).await?;
+                    debug!("更新专辑：{} {} -> {}", album.short, album.file_list.len(), chunk.len());
+                }
+                None => {
+                    self.catbox_limit.acquire().await;
+                    let short =
+                        self.catbox_uploader.create_album(&title, description, chunk).await?;
+                    CatboxAlbumEntity::create(&self.db, &short, id, part as i32, chunk).await?;
+                    debug!("创建专辑：{}", short);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 为画廊生成一条可供发送到指定频道的 telegram 消息正文
     async fn create_message_text<T: GalleryInfo>(
         &self,
+        channel: &Channel,
         gallery: &T,
         article: &str,
     ) -> Result<String> {
-        let re = Regex::new("[-/· ]").unwrap();
-        let tags = self.trans.trans_tags(gallery.tags());
-        let mut text = String::new();
-        for (ns, tag) in tags {
-            let tag = tag
-                .iter()
-                .map(|s| format!("#{}", re.replace_all(s, "_")))
-                .collect::<Vec<_>>()
-                .join(" ");
-            text.push_str(&format!("{}: {}\n", code_inline(&pad_left(&ns, 6)), tag))
-        }
-
-        text.push_str(
-            &format!("{}: {}\n", code_inline("  预览"), link(article, &gallery.title()),),
-        );
-        text.push_str(&format!("{}: {}", code_inline("原始地址"), gallery.url().url()));
+        let tags = gallery
+            .tags()
+            .iter()
+            .map(|(ns, tags)| TagGroup {
+                namespace: self.trans.trans_namespace(ns),
+                raw: ns.clone(),
+                tags: tags.iter().flat_map(|t| self.trans.trans(ns, t)).collect(),
+            })
+            .collect();
+        let albums = CatboxAlbumEntity::list(&self.db, gallery.url().id())
+            .await?
+            .iter()
+            .map(|album| album.url())
+            .collect::<Vec<_>>();
+        let diff = match gallery.parent() {
+            Some(parent) => PageDiff::between(&self.db, parent, gallery.url().id()).await?,
+            None => None,
+        };
+        let newer = MessageEntity::get_newest_version(&self.db, gallery.url().id(), &channel.id())
+            .await?
+            .map(|msg| url_of(channel.channel_id.clone(), msg.id).to_string());
+        let context = MessageContext {
+            id: gallery.url().id(),
+            title: gallery.title(),
+            title_jp: gallery.title_jp(),
+            tags,
+            pages: gallery.pages(),
+            favorite: gallery.favorite(),
+            posted: gallery.posted().map(|t| t.format("%Y-%m-%d %H:%M").to_string()),
+            preview: article.to_string(),
+            album: albums.first().cloned(),
+            albums,
+            diff: diff.map(|diff| diff.to_string()),
+            newer,
+            url: gallery.url().url(),
+        };
+        let templates = self.templates.read().unwrap();
+        templates.get(&channel.id()).expect("频道没有加载模板").render(&context)
+    }
+
+    /// 重新读取所有频道的消息模板
+    pub fn reload_template(&self) -> Result<()> {
+        *self.templates.write().unwrap() = load_templates(&self.config)?;
+        Ok(())
+    }
+
+    /// 重新读取消息模板，并使用新模板重新渲染所有频道中画廊的消息，返回更新的消息数量
+    ///
+    /// galleries 为空时，重新渲染 80 分以上或最近两个月的画廊；单条消息失败时只记录日志，不会中断
+    pub async fn rerender(&self, mut galleries: Vec<GalleryEntity>) -> Result<usize> {
+        self.reload_template()?;
+        if galleries.is_empty() {
+            galleries = GalleryEntity::list_scans(&self.db).await?;
+        }
+        let mut count = 0;
+        for gallery in galleries.iter().rev() {
+            let Some(telegraph) = TelegraphEntity::get(&self.db, gallery.id).await? else {
+                continue;
+            };
+            for (channel, msg) in self.messages_of(gallery.id).await? {
+                let text = match self.create_message_text(channel, gallery, &telegraph.url).await {
+                    Ok(text) => text,
+                    Err(err) => {
+                        warn!("渲染消息失败：{} {:?}", gallery.id, err);
+                        continue;
+                    }
+                };
+                if self.rerender_post(channel, &msg, text).await {
+                    count += 1;
+                }
+                // 避免短时间内编辑大量消息触发 telegram 的限制
+                time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+        Ok(count)
+    }
+
+    /// 编辑一条消息，被限流时等待后重试，返回消息是否被更新
+    async fn rerender_post(&self, channel: &Channel, msg: &MessageEntity, text: String) -> bool {
+        loop {
+            let err = match self.edit_post(channel, msg, text.clone()).await {
+                Ok(()) => return true,
+                Err(err) => err,
+            };
+            match err.downcast_ref::<RequestError>() {
+                // 内容没有变化
+                Some(RequestError::Api(ApiError::MessageNotModified)) => return false,
+                Some(RequestError::RetryAfter(duration)) => time::sleep(*duration).await,
+                _ => {
+                    warn!("重新渲染消息失败：{} {}", msg.gallery_id, err);
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// 读取所有频道的消息模板
+fn load_templates(config: &Config) -> Result<HashMap<String, MessageTemplate>> {
+    config
+        .channels
+        .iter()
+        .map(|c| Ok((c.id(), MessageTemplate::load(c.message_template.as_deref())?)))
+        .collect()
+}
+
+/// 新版本画廊与父画廊之间的页面差异
+#[derive(Debug, Default, PartialEq)]
+struct PageDiff {
+    /// 新增的页面数量
+    added: usize,
+    /// 删除的页面数量
+    removed: usize,
+    /// 被替换的页面数量
+    replaced: usize,
+}
+
+impl PageDiff {
+    /// 根据两个版本的页面哈希计算差异，新版本中的新图片优先视为替换了旧版本中被删除的图片
+    fn new(parent: &[&str], current: &[&str]) -> Self {
+        let parent_set = parent.iter().collect::<HashSet<_>>();
+        let current_set = current.iter().collect::<HashSet<_>>();
+        let new = current_set.difference(&parent_set).count();
+        let gone = parent_set.difference(&current_set).count();
+        let replaced = new.min(gone);
+        Self { added: new - replaced, removed: gone - replaced, replaced }
+    }
+
+    /// 从数据库中读取两个画廊的图片并计算差异，父画廊没有图片记录时返回 None
+    async fn between(db: &Database, parent: i32, current: i32) -> Result<Option<Self>> {
+        let parent = ImageEntity::get_by_gallery_id(db, parent).await?;
+        if parent.is_empty() {
+            return Ok(None);
+        }
+        let current = ImageEntity::get_by_gallery_id(db, current).await?;
+        let parent = parent.iter().map(|img| img.hash.as_str()).collect::<Vec<_>>();
+        let current = current.iter().map(|img| img.hash.as_str()).collect::<Vec<_>>();
+        Ok(Some(Self::new(&parent, &current)))
+    }
+}
 
-        Ok(text)
+impl Display for PageDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if self.added != 0 {
+            parts.push(format!("新增 {} 页", self.added));
+        }
+        if self.removed != 0 {
+            parts.push(format!("删除 {} 页", self.removed));
+        }
+        if self.replaced != 0 {
+            parts.push(format!("替换 {} 页", self.replaced));
+        }
+        match parts.is_empty() {
+            true => f.write_str("图片无变化"),
+            false => f.write_str(&parts.join("，")),
+        }
     }
 }
 
-async fn flatten<T>(handle: JoinHandle<Result<T>>) -> Result<T> {
-    match handle.await {
-        Ok(Ok(result)) => Ok(result),
-        Ok(Err(err)) => Err(err),
-        Err(err) => bail!(err),
+/// 单个 catbox 专辑的文件数量上限
+const CATBOX_ALBUM_LIMIT: usize = 500;
+
+/// 单篇 telegraph 文章内容的大小上限，telegraph 的限制是 64KB，留出一些余量给导航链接
+const TELEGRAPH_CONTENT_LIMIT: usize = 60 * 1024;
+
+/// 画廊文章的内容，过长时被拆分为多篇
+struct ArticleContent {
+    title: String,
+    /// 每篇文章开头的画廊信息
+    header: String,
+    /// 每篇文章中的图片
+    chunks: Vec<String>,
+    /// 最后一篇文章末尾的图片总数
+    footer: String,
+}
+
+impl ArticleContent {
+    /// 第 index 篇文章的标题
+    fn title(&self, index: usize) -> String {
+        match self.chunks.len() {
+            1 => self.title.clone(),
+            n => format!("{} ({}/{})", self.title, index + 1, n),
+        }
+    }
+
+    /// 第 index 篇文章的完整内容，urls 为所有文章的 URL，用于生成导航链接
+    fn html(&self, index: usize, urls: &[String]) -> String {
+        if self.chunks.len() == 1 {
+            return format!("{}{}{}", self.header, self.chunks[0], self.footer);
+        }
+        let nav = part_navigation(urls, index);
+        let mut html = format!("{}{}{}{}", self.header, nav, self.chunks[index], nav);
+        if index + 1 == self.chunks.len() {
+            html.push_str(&self.footer);
+        }
+        html
+    }
+}
+
+/// 生成拆分后的第 index 篇文章的导航链接
+fn part_navigation(urls: &[String], index: usize) -> String {
+    let mut links = vec![format!("第 {}/{} 部分", index + 1, urls.len())];
+    if index > 0 {
+        links.push(format!(r#"<a href="{}">上一部分</a>"#, urls[index - 1]));
+    }
+    if let Some(next) = urls.get(index + 1) {
+        links.push(format!(r#"<a href="{}">下一部分</a>"#, next));
     }
+    format!("<p>{}</p>", links.join(" | "))
+}
+
+/// 文章中的一张图片，caption 不为空时在图片下方显示说明
+fn figure(url: &str, caption: Option<&str>) -> String {
+    match caption {
+        Some(caption) => {
+            format!(r#"<figure><img src="{}"><figcaption>{}</figcaption></figure>"#, url, caption)
+        }
+        None => format!(r#"<img src="{}">"#, url),
+    }
+}
+
+/// 根据页面哈希和图片原始地址生成上传时使用的文件名
+fn image_filename(hash: &str, url: &str) -> String {
+    format!("{}.{}", hash, url.rsplit('.').next().unwrap_or("jpg"))
+}
+
+/// 下载一张图片，同时计算它的感知哈希，无法识别的图片感知哈希为空
+async fn download_image(client: &Client, url: &str) -> Result<(Vec<u8>, Option<i64>)> {
+    let bytes = client.get(url).send().await?.error_for_status()?.bytes().await?.to_vec();
+    debug!("已下载: {}", url);
+    let (bytes, phash) = tokio::task::spawn_blocking(move || {
+        let phash = dhash(&bytes).map_err(|err| warn!("无法计算感知哈希：{}", err)).ok();
+        (bytes, phash)
+    })
+    .await?;
+    // 数据库中没有无符号 64 位整数，按位原样转换
+    Ok((bytes, phash.map(|h| h as i64)))
+}
+
+/// 在阻塞线程中处理图片，返回处理后的内容和文件名
+async fn process_image(
+    processor: &ImageProcessor,
+    data: Vec<u8>,
+    filename: &str,
+) -> Result<(Vec<u8>, String)> {
+    let processor = processor.clone();
+    let filename = filename.to_string();
+    tokio::task::spawn_blocking(move || processor.process(data, &filename)).await?
+}
+
+/// 已经下载但还未上传的图片
+struct DownloadedImage {
+    fileindex: u32,
+    /// 图片的原始地址
+    url: String,
+    data: Vec<u8>,
+    phash: Option<i64>,
 }
 
 impl ExloliUploader {
     pub async fn reupload(&self, mut galleries: Vec<GalleryEntity>) -> Result<()> {
         if galleries.is_empty() {
-            galleries = GalleryEntity::list_scans().await?;
+            galleries = GalleryEntity::list_scans(&self.db).await?;
         }
         for gallery in galleries.iter().rev() {
-            if let Some(score) = PollEntity::get_by_gallery(gallery.id).await? {
+            if let Some(score) = PollEntity::get_by_gallery(&self.db, gallery.id).await? {
                 if score.score > 0.8 {
-                    info!("尝试上传画廊：{}", gallery.url());
-                    if let Err(err) = self.try_upload(&gallery.url(), true).await {
-                        error!("上传失败：{}", err);
+                    info!("加入上传队列：{}", gallery.url());
+                    for channel in &self.config.channels {
+                        self.enqueue(&gallery.url(), channel, true).await?;
                     }
-                    time::sleep(Duration::from_secs(60)).await;
                 }
             }
         }
         Ok(())
     }
+}
 
-    pub async fn recheck(&self, mut galleries: Vec<GalleryEntity>) -> Result<()> {
-        if galleries.is_empty() {
-            galleries = GalleryEntity::list_scans().await?;
+#[cfg(test)]
+impl ExloliUploader {
+    /// 创建一个使用内存数据库和示例配置的上传器，E 站、telegram 和图床的请求都会发送到 endpoint
+    async fn mock(endpoint: &str) -> Self {
+        use teloxide::prelude::RequesterExt;
+        use teloxide::types::ParseMode;
+
+        let mut config = Config::parse(include_str!("../config.toml.example")).unwrap();
+        config.limit.retries = 0;
+        let bot = teloxide::Bot::new("token")
+            .set_api_url(endpoint.parse().unwrap())
+            .throttle(Default::default())
+            .parse_mode(ParseMode::Html)
+            .cache_me();
+        let catbox_uploader = CatboxUploader::with_endpoint("userhash", endpoint);
+        Self {
+            ehentai: EhClient::with_endpoint(endpoint).with_backoff(Backoff::new(&config.limit)),
+            telegraph: TelegraphPool::mock(Default::default()),
+            bot,
+            db: Database::memory().await.unwrap(),
+            trans: EhTagTransDB::empty(),
+            host: Arc::new(catbox_uploader.clone()),
+            catbox_uploader,
+            processor: ImageProcessor::new(config.image.clone()),
+            notify: Arc::new(Notify::new()),
+            publish_notify: Arc::new(Notify::new()),
+            catbox_limit: RateLimiter::unlimited(),
+            backoff: Backoff::new(&config.limit),
+            progress: broadcast::channel(1024).0,
+            running: UploadRegistry::default(),
+            templates: Arc::new(RwLock::new(load_templates(&config).unwrap())),
+            config,
         }
-        for gallery in galleries.iter().rev() {
-            let telegraph =
-                TelegraphEntity::get(gallery.id).await?.ok_or(anyhow!("找不到 telegraph"))?;
-            if let Some(msg) = MessageEntity::get_by_gallery(gallery.id).await? {
-                info!("检测画廊：{}", gallery.url());
-                if !self.check_telegraph(&telegraph.url).await? {
-                    info!("重新上传预览：{}", gallery.url());
-                    if let Err(err) = self.republish(gallery, &msg).await {
-                        error!("上传失败：{}", err);
-                    }
-                    time::sleep(Duration::from_secs(60)).await;
-                }
-            }
-            time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::mock::MockServer;
+
+    #[test]
+    fn page_diff() {
+        let diff = PageDiff::new(&["a", "b", "c"], &["a", "x", "c", "y", "z"]);
+        assert_eq!(diff, PageDiff { added: 2, removed: 0, replaced: 1 });
+        assert_eq!(diff.to_string(), "新增 2 页，替换 1 页");
+        assert_eq!(PageDiff::new(&["a", "b"], &["a", "b"]).to_string(), "图片无变化");
+    }
+
+    #[test]
+    fn article_content() {
+        let content = ArticleContent {
+            title: "t".to_string(),
+            header: "<h4>t</h4>".to_string(),
+            chunks: vec!["<img a>".to_string(), "<img b>".to_string()],
+            footer: "<p>2</p>".to_string(),
+        };
+        let urls = ["https://telegra.ph/a".to_string(), "https://telegra.ph/b".to_string()];
+        assert_eq!(content.title(1), "t (2/2)");
+        assert!(content.html(0, &urls).contains(r#"<a href="https://telegra.ph/b">下一部分</a>"#));
+        assert!(!content.html(0, &urls).ends_with("<p>2</p>"));
+        assert!(content.html(1, &urls).ends_with("<p>2</p>"));
+    }
+
+    #[test]
+    fn figure_html() {
+        assert_eq!(figure("https://a/1.jpg", None), r#"<img src="https://a/1.jpg">"#);
+        assert_eq!(
+            figure("https://a/1.jpg", Some("第 1 页")),
+            r#"<figure><img src="https://a/1.jpg"><figcaption>第 1 页</figcaption></figure>"#
+        );
+    }
+
+    fn gallery() -> EhGallery {
+        EhGallery {
+            title: "<title>".to_string(),
+            title_jp: Some("タイトル".to_string()),
+            tags: [("female".to_string(), vec!["glasses".to_string()])].into_iter().collect(),
+            posted: "2024-01-01T12:30:00".parse().unwrap(),
+            cover: 2,
+            ..EhGallery::mock(1, 3)
         }
-        Ok(())
+    }
+
+    #[tokio::test]
+    async fn telegraph_header() {
+        let server = MockServer::start(vec![]).await;
+        let uploader = ExloliUploader::mock(&server.url()).await;
+        assert_eq!(
+            uploader.telegraph_header(&gallery()),
+            "<h4>&lt;title&gt;</h4><p>タイトル</p><p><b>female</b>：glasses</p>\
+             <p>来源：<a href=\"https://exhentai.org/g/1/aaaaaaaaaa/\">\
+             https://exhentai.org/g/1/aaaaaaaaaa/</a></p>\
+             <p>发布时间：2024-01-01 12:30</p><hr>"
+        );
+    }
+
+    #[tokio::test]
+    async fn render_article() {
+        let server = MockServer::start(vec![]).await;
+        let mut uploader = ExloliUploader::mock(&server.url()).await;
+        uploader.config.telegraph.header = false;
+        uploader.config.telegraph.page_captions = true;
+        let gallery = gallery();
+        let urls = (1..=3).map(|page| format!("https://files.catbox.moe/{page}.jpg"));
+        PageEntity::seed(&uploader.db, 1, urls).await;
+        // 第 2 页是广告，不影响按页码找到的封面（第 3 页）
+        ImageEntity::mark_ad(&uploader.db, &[2]).await.unwrap();
+
+        let content = uploader.render_article(&gallery).await.unwrap();
+        assert_eq!(
+            content.chunks,
+            [[
+                figure("https://files.catbox.moe/3.jpg", Some("封面")),
+                figure("https://files.catbox.moe/1.jpg", Some("第 1 页")),
+                figure("https://files.catbox.moe/3.jpg", Some("第 3 页")),
+            ]
+            .concat()]
+        );
+        assert_eq!(content.footer, "<p>图片总数：3</p>");
+    }
+
+    #[tokio::test]
+    async fn download_and_upload() {
+        let mut jpeg = std::io::Cursor::new(vec![]);
+        image::RgbImage::new(16, 16).write_to(&mut jpeg, image::ImageFormat::Jpeg).unwrap();
+        let jpeg = jpeg.into_inner();
+        let image = MockServer::start(jpeg.clone()).await;
+        let catbox = MockServer::start(b"https://files.catbox.moe/abcdef.jpg".to_vec()).await;
+        let host = CatboxUploader::with_endpoint("userhash", &catbox.url());
+
+        let url = format!("{}h/0123456789abcdef/keystamp=1;fileindex=42/01.jpg", image.url());
+        let filename = image_filename("0123456789", &url);
+        assert_eq!(filename, "0123456789.jpg");
+
+        let processor = ImageProcessor::new(Default::default());
+        let (data, phash) = download_image(&Client::new(), &url).await.unwrap();
+        assert!(phash.is_some());
+        let (data, filename) = process_image(&processor, data, &filename).await.unwrap();
+        let uploaded = host.upload(&filename, data).await.unwrap();
+        assert_eq!(uploaded, "https://files.catbox.moe/abcdef.jpg");
+
+        let requests = catbox.requests();
+        assert_eq!(requests.len(), 1);
+        let needle = [b"Content-Type: image/jpeg\r\n\r\n", jpeg.as_slice()].concat();
+        assert!(requests[0].windows(needle.len()).any(|w| w == needle));
     }
 }
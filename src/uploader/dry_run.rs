@@ -0,0 +1,258 @@
+use std::fmt::{self, Display};
+
+use anyhow::Result;
+use futures::StreamExt;
+use tracing::{error, info};
+
+use super::ExloliUploader;
+use crate::config::Channel;
+use crate::database::{GalleryEntity, ImageEntity, MessageEntity};
+use crate::ehentai::{EhGallery, EhGalleryUrl};
+
+/// 预览时用来代替 telegraph 文章的地址
+const PLACEHOLDER_ARTICLE: &str = "https://telegra.ph/dry-run";
+
+/// 一次模拟扫描中一个频道的结果
+#[derive(Debug, Default)]
+pub struct DryRunReport {
+    /// 频道名称
+    pub channel: String,
+    /// 将要上传的新画廊
+    pub upload: Vec<PlannedUpload>,
+    /// 将要更新的已上传画廊
+    pub update: Vec<PlannedUpdate>,
+    /// 被筛选规则跳过的画廊，以及匹配的规则
+    pub filtered: Vec<(EhGalleryUrl, String)>,
+    /// 已上传且没有变化的画廊数量
+    pub unchanged: usize,
+    /// 获取失败的画廊
+    pub failed: Vec<(EhGalleryUrl, String)>,
+}
+
+#[derive(Debug)]
+pub struct PlannedUpload {
+    pub url: EhGalleryUrl,
+    pub title: String,
+    /// 画廊的总页数
+    pub pages: usize,
+    /// 需要下载并上传的图片数量，其余的图片已经存在
+    pub new_images: usize,
+    /// 父画廊在频道中的消息，发布后会被回复并编辑
+    pub parent_message: Option<i32>,
+    /// 将要发送的消息正文
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct PlannedUpdate {
+    pub url: EhGalleryUrl,
+    pub title: String,
+    /// 需要编辑的频道消息
+    pub message: i32,
+    /// 发生变化的内容
+    pub changes: Vec<String>,
+    /// 编辑后的消息正文
+    pub text: String,
+}
+
+impl ExloliUploader {
+    /// 模拟一次扫描，报告每个频道将会上传、更新和编辑的内容
+    ///
+    /// 只会请求 E 站和读取数据库，不会写入数据库，也不会调用 Telegram、Telegraph 和 Catbox
+    pub async fn dry_run(&self) -> Result<Vec<DryRunReport>> {
+        let mut reports = vec![];
+        for channel in &self.config.channels {
+            reports.push(self.dry_run_channel(channel).await?);
+        }
+        Ok(reports)
+    }
+
+    async fn dry_run_channel(&self, channel: &Channel) -> Result<DryRunReport> {
+        info!("开始模拟扫描：{}", channel.name);
+        let mut report = DryRunReport { channel: channel.name.clone(), ..Default::default() };
+        let stream = self.ehentai.search_iter(&channel.search_params).take(channel.search_count);
+        tokio::pin!(stream);
+        while let Some(next) = stream.next().await {
+            let result = match self.is_uploaded(next.id(), channel).await {
+                Ok(uploaded) => match self.ehentai.get_gallery(&next).await {
+                    Ok(gallery) if uploaded => {
+                        self.plan_update(&gallery, channel, &mut report).await
+                    }
+                    Ok(gallery) => self.plan_upload(&gallery, channel, &mut report).await,
+                    Err(err) => Err(err.into()),
+                },
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                error!("dry_run: {} {:?}", next, err);
+                report.failed.push((next, err.to_string()));
+            }
+        }
+        Ok(report)
+    }
+
+    /// 对应 try_upload
+    async fn plan_upload(
+        &self,
+        gallery: &EhGallery,
+        channel: &Channel,
+        report: &mut DryRunReport,
+    ) -> Result<()> {
+        if let Some(rule) = channel.filter.matched_rule(gallery) {
+            report.filtered.push((gallery.url.clone(), rule));
+            return Ok(());
+        }
+        let mut new_images = 0;
+        for page in &gallery.pages {
+            if ImageEntity::get_by_hash(&self.db, page.hash()).await?.is_none() {
+                new_images += 1;
+            }
+        }
+        let parent_message = match &gallery.parent {
+            Some(parent) => MessageEntity::get_by_gallery(&self.db, parent.id(), &channel.id())
+                .await?
+                .map(|msg| msg.id),
+            None => None,
+        };
+        let text = self.create_message_text(channel, gallery, PLACEHOLDER_ARTICLE).await?;
+        report.upload.push(PlannedUpload {
+            url: gallery.url.clone(),
+            title: gallery.title.clone(),
+            pages: gallery.pages.len(),
+            new_images,
+            parent_message,
+            text,
+        });
+        Ok(())
+    }
+
+    /// 对应 try_update，但不考虑更新频率的限制
+    async fn plan_update(
+        &self,
+        gallery: &EhGallery,
+        channel: &Channel,
+        report: &mut DryRunReport,
+    ) -> Result<()> {
+        let (Some(entity), Some(message)) = (
+            GalleryEntity::get(&self.db, gallery.url.id()).await?,
+            MessageEntity::get_by_gallery(&self.db, gallery.url.id(), &channel.id()).await?,
+        ) else {
+            report.unchanged += 1;
+            return Ok(());
+        };
+        let changes = changes_of(&entity, gallery);
+        if changes.is_empty() {
+            report.unchanged += 1;
+            return Ok(());
+        }
+        let text = self.create_message_text(channel, gallery, PLACEHOLDER_ARTICLE).await?;
+        report.update.push(PlannedUpdate {
+            url: gallery.url.clone(),
+            title: gallery.title.clone(),
+            message: message.id,
+            changes,
+            text,
+        });
+        Ok(())
+    }
+}
+
+/// 比较数据库中的记录和最新的画廊信息
+fn changes_of(entity: &GalleryEntity, gallery: &EhGallery) -> Vec<String> {
+    let mut changes = vec![];
+    if gallery.title != entity.title {
+        changes.push(format!("标题：{} -> {}", entity.title, gallery.title));
+    }
+    if gallery.tags != entity.tags.0 {
+        changes.push("标签".to_string());
+    }
+    if gallery.pages.len() as i32 != entity.pages {
+        changes.push(format!("页数：{} -> {}", entity.pages, gallery.pages.len()));
+    }
+    changes
+}
+
+impl Display for DryRunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "频道 {}", self.channel)?;
+        writeln!(f, "将要上传 {} 个画廊：", self.upload.len())?;
+        for item in &self.upload {
+            writeln!(f, "- {} {}", item.url, item.title)?;
+            writeln!(f, "  页数：{}，需要上传：{}", item.pages, item.new_images)?;
+            if let Some(id) = item.parent_message {
+                writeln!(f, "  回复并编辑父画廊的消息：{}", id)?;
+            }
+            writeln!(f, "{}", indent(&item.text))?;
+        }
+        writeln!(f, "将要更新 {} 个画廊：", self.update.len())?;
+        for item in &self.update {
+            writeln!(f, "- {} {}", item.url, item.title)?;
+            writeln!(f, "  编辑消息：{}，变化：{}", item.message, item.changes.join("；"))?;
+            writeln!(f, "{}", indent(&item.text))?;
+        }
+        if !self.filtered.is_empty() {
+            writeln!(f, "跳过 {} 个画廊：", self.filtered.len())?;
+            for (url, rule) in &self.filtered {
+                writeln!(f, "- {} {}", url, rule)?;
+            }
+        }
+        writeln!(f, "没有变化：{}", self.unchanged)?;
+        if !self.failed.is_empty() {
+            writeln!(f, "获取失败 {} 个画廊：", self.failed.len())?;
+            for (url, err) in &self.failed {
+                writeln!(f, "- {} {}", url, err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn indent(text: &str) -> String {
+    text.lines().map(|line| format!("    {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MessageKind;
+    use crate::host::mock::MockServer;
+
+    fn gallery(id: i32, title: &str, pages: i32) -> EhGallery {
+        EhGallery { title: title.to_string(), ..EhGallery::mock(id, pages) }
+    }
+
+    #[tokio::test]
+    async fn plan() {
+        // 模拟扫描不应该请求任何外部服务，所有请求都会得到 500
+        let server = MockServer::route(|_| (500, vec![])).await;
+        let uploader = ExloliUploader::mock(&server.url()).await;
+        let channel = &uploader.config.channels[0];
+        let mut report = DryRunReport::default();
+
+        let new = gallery(1, "new", 3);
+        uploader.plan_upload(&new, channel, &mut report).await.unwrap();
+        let mut filtered = gallery(2, "filtered", 3);
+        filtered.tags.insert("other".to_string(), vec!["ai generated".to_string()]);
+        uploader.plan_upload(&filtered, channel, &mut report).await.unwrap();
+
+        let old = gallery(3, "old", 2);
+        GalleryEntity::create(&uploader.db, &old).await.unwrap();
+        MessageEntity::create(&uploader.db, 10, &channel.id(), 3, MessageKind::Text).await.unwrap();
+        uploader.plan_update(&old, channel, &mut report).await.unwrap();
+        uploader.plan_update(&gallery(3, "renamed", 4), channel, &mut report).await.unwrap();
+        // 没有发布记录的画廊视为没有变化
+        uploader.plan_update(&gallery(4, "unknown", 1), channel, &mut report).await.unwrap();
+
+        assert_eq!(report.upload.len(), 1);
+        assert_eq!(report.upload[0].new_images, 3);
+        assert!(report.upload[0].text.contains(PLACEHOLDER_ARTICLE));
+        assert_eq!(report.filtered.len(), 1);
+        assert_eq!(report.update.len(), 1);
+        assert_eq!(report.update[0].message, 10);
+        assert_eq!(report.update[0].changes, vec!["标题：old -> renamed", "页数：2 -> 4"]);
+        assert_eq!(report.unchanged, 2);
+        // 不会写入数据库
+        assert!(GalleryEntity::get(&uploader.db, 1).await.unwrap().is_none());
+        assert!(server.requests().is_empty());
+    }
+}
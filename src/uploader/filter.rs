@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use indexmap::IndexMap;
+
+use crate::config::Filter;
+use crate::ehentai::EhGallery;
+
+impl Filter {
+    /// 检查画廊是否满足所有筛选规则，不满足时返回第一条不满足的规则
+    pub fn matched_rule(&self, gallery: &EhGallery) -> Option<String> {
+        if !self.include_tags.is_empty()
+            && !self.include_tags.iter().any(|tag| has_tag(&gallery.tags, tag))
+        {
+            return Some(format!("include_tags = {:?}", self.include_tags));
+        }
+        if let Some(tag) = self.exclude_tags.iter().find(|tag| has_tag(&gallery.tags, tag)) {
+            return Some(format!("exclude_tags: {}", tag));
+        }
+        let pages = gallery.pages.len();
+        if let Some(min) = self.min_pages.filter(|&min| pages < min) {
+            return Some(format!("min_pages = {} ({})", min, pages));
+        }
+        if let Some(max) = self.max_pages.filter(|&max| pages > max) {
+            return Some(format!("max_pages = {} ({})", max, pages));
+        }
+        if let Some(min) = self.min_favorites.filter(|&min| gallery.favorite < min) {
+            return Some(format!("min_favorites = {} ({})", min, gallery.favorite));
+        }
+        let age = (Utc::now().naive_utc() - gallery.posted).to_std().unwrap_or_default();
+        if let Some(min) = self.min_age.filter(|&min| age < min) {
+            return Some(format!("min_age = {:?} (posted {})", min, gallery.posted));
+        }
+        if let Some(max) = self.max_age.filter(|&max| age > max) {
+            return Some(format!("max_age = {:?} (posted {})", max, gallery.posted));
+        }
+        if let Some(uploader) = gallery
+            .uploader
+            .as_ref()
+            .filter(|uploader| self.uploader_blacklist.iter().any(|u| u == *uploader))
+        {
+            return Some(format!("uploader_blacklist: {}", uploader));
+        }
+        None
+    }
+
+    /// 被跳过的画廊下次可以重新检查的时间
+    pub fn recheck_at(&self, gallery: &EhGallery) -> NaiveDateTime {
+        let now = Utc::now().naive_utc();
+        if let Some(ready) = self.min_age.map(|min| gallery.posted + to_chrono(min)) {
+            if ready > now {
+                return ready;
+            }
+        }
+        now + to_chrono(self.recheck_after.unwrap_or(Duration::from_secs(86400)))
+    }
+}
+
+fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_default()
+}
+
+/// 判断画廊是否包含指定标签，标签的格式为 `namespace:tag` 或者 `tag`
+fn has_tag(tags: &IndexMap<String, Vec<String>>, tag: &str) -> bool {
+    match tag.split_once(':') {
+        Some((ns, tag)) => tags.get(ns).is_some_and(|v| v.iter().any(|t| t == tag)),
+        None => tags.values().flatten().any(|t| t == tag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gallery() -> EhGallery {
+        let mut gallery = EhGallery::mock(2552897, 0);
+        gallery.tags.insert("language".to_string(), vec!["chinese".to_string()]);
+        gallery.tags.insert("female".to_string(), vec!["glasses".to_string()]);
+        gallery.favorite = 100;
+        gallery.uploader = Some("someone".to_string());
+        gallery.posted = Utc::now().naive_utc() - chrono::Duration::days(3);
+        gallery
+    }
+
+    #[test]
+    fn matched_rule() {
+        let gallery = gallery();
+        assert_eq!(Filter::default().matched_rule(&gallery), None);
+
+        let filter = Filter {
+            include_tags: vec!["language:chinese".to_string()],
+            exclude_tags: vec!["male:glasses".to_string()],
+            min_favorites: Some(50),
+            max_age: Some(Duration::from_secs(7 * 86400)),
+            ..Default::default()
+        };
+        assert_eq!(filter.matched_rule(&gallery), None);
+
+        let filter = Filter { exclude_tags: vec!["glasses".to_string()], ..Default::default() };
+        assert_eq!(filter.matched_rule(&gallery).unwrap(), "exclude_tags: glasses");
+
+        let filter = Filter { min_age: Some(Duration::from_secs(7 * 86400)), ..Default::default() };
+        assert!(filter.matched_rule(&gallery).unwrap().starts_with("min_age"));
+
+        let filter =
+            Filter { uploader_blacklist: vec!["someone".to_string()], ..Default::default() };
+        assert_eq!(filter.matched_rule(&gallery).unwrap(), "uploader_blacklist: someone");
+    }
+
+    #[test]
+    fn recheck_at() {
+        let gallery = gallery();
+        let now = Utc::now().naive_utc();
+        let filter = Filter { min_age: Some(Duration::from_secs(7 * 86400)), ..Default::default() };
+        assert_eq!(filter.recheck_at(&gallery), gallery.posted + chrono::Duration::days(7));
+
+        let filter = Filter { min_favorites: Some(500), ..Default::default() };
+        let at = filter.recheck_at(&gallery) - now;
+        assert!(
+            at >= chrono::Duration::days(1)
+                && at < chrono::Duration::days(1) + chrono::Duration::minutes(1)
+        );
+    }
+}
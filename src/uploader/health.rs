@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use reqwest::{Client, StatusCode};
+use tokio::time;
+use tracing::{error, info, warn};
+
+use super::ExloliUploader;
+use crate::database::{
+    GalleryEntity, HealthSummary, ImageEntity, LinkStatus, PageEntity, TelegraphEntity,
+};
+use crate::ehentai::GalleryInfo;
+
+impl ExloliUploader {
+    /// 按照滚动的计划在后台检查文章和图片的链接，每一轮检查一批最久没有检查过的链接
+    pub(super) async fn health_loop(&self) {
+        loop {
+            if let Err(err) = self.check_health().await {
+                error!("检查链接失败：{:?}", err);
+            }
+            time::sleep(self.config.health.interval).await;
+        }
+    }
+
+    /// 检查一批文章和图片，失效的文章会被重新发布，失效的图片会被重新上传
+    async fn check_health(&self) -> Result<()> {
+        let config = &self.config.health;
+        let before = Utc::now().naive_utc() - chrono::Duration::from_std(config.recheck_after)?;
+        let client = health_client()?;
+        let client = &client;
+
+        let articles = TelegraphEntity::list_unchecked(&self.db, before, config.batch).await?;
+        let results = stream::iter(articles)
+            .map(|article| async move {
+                let status = check_article(client, &article).await;
+                (article, status)
+            })
+            .buffer_unordered(config.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        let (mut alive, mut dead) = (0, 0);
+        for (article, status) in results {
+            TelegraphEntity::update_status(&self.db, article.gallery_id, status).await?;
+            match status {
+                LinkStatus::Alive => alive += 1,
+                LinkStatus::Dead => {
+                    dead += 1;
+                    self.repair_article(article.gallery_id).await;
+                }
+                _ => {}
+            }
+        }
+
+        let images = ImageEntity::list_unchecked(&self.db, before, config.batch).await?;
+        let results = stream::iter(images)
+            .map(|image| async move {
+                let status = check_link(client, &image.url()).await;
+                (image, status)
+            })
+            .buffer_unordered(config.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        let mut dead_images = vec![];
+        for (image, status) in results {
+            ImageEntity::update_status(&self.db, image.id, status).await?;
+            match status {
+                LinkStatus::Alive => alive += 1,
+                LinkStatus::Dead => {
+                    dead += 1;
+                    warn!("图片已失效：{} {}", image.id, image.url());
+                    dead_images.push(image.id);
+                }
+                _ => {}
+            }
+        }
+        if !dead_images.is_empty() {
+            for gallery_id in PageEntity::list_galleries(&self.db, &dead_images).await? {
+                if let Err(err) = self.repair_images(gallery_id).await {
+                    error!("修复图片失败：{} {:?}", gallery_id, err);
+                }
+            }
+        }
+
+        if alive + dead > 0 {
+            info!("链接检查完毕：{} 个正常，{} 个失效", alive, dead);
+        }
+        Ok(())
+    }
+
+    /// 重新发布失效的文章，并更新所有频道中的消息
+    async fn repair_article(&self, gallery_id: i32) {
+        let result = async {
+            let gallery = GalleryEntity::get(&self.db, gallery_id).await?.context("找不到画廊")?;
+            info!("重新发布失效的文章：{}", gallery.url());
+            self.republish(&gallery).await?;
+            TelegraphEntity::update_status(&self.db, gallery_id, LinkStatus::Alive).await?;
+            anyhow::Ok(())
+        };
+        if let Err(err) = result.await {
+            error!("修复文章失败：{} {:?}", gallery_id, err);
+        }
+    }
+
+    /// 立即检查指定画廊的文章和其中的每一张图片，重新上传失效的图片，文章失效时重新发布
+    ///
+    /// 返回文章的检查结果
+    pub async fn check_gallery(&self, gallery: &GalleryEntity) -> Result<LinkStatus> {
+        let article =
+            TelegraphEntity::get(&self.db, gallery.id).await?.context("找不到 telegraph")?;
+        let status = check_article(&health_client()?, &article).await;
+        TelegraphEntity::update_status(&self.db, gallery.id, status).await?;
+        if status == LinkStatus::Dead {
+            info!("重新发布失效的文章：{}", gallery.url());
+            self.republish(gallery).await?;
+            TelegraphEntity::update_status(&self.db, gallery.id, LinkStatus::Alive).await?;
+        }
+        // 重新上传图片之后会编辑文章，因此要在失效的文章重新发布之后进行
+        self.repair_images(gallery.id).await?;
+        Ok(status)
+    }
+
+    /// 清空所有链接的检查时间，让后台在接下来的几轮中重新检查所有链接
+    pub async fn recheck(&self) -> Result<()> {
+        HealthSummary::reset(&self.db).await?;
+        Ok(())
+    }
+}
+
+pub(super) fn health_client() -> Result<Client> {
+    Ok(Client::builder()
+        .timeout(Duration::from_secs(30))
+        .connect_timeout(Duration::from_secs(30))
+        .build()?)
+}
+
+/// 检查文章的所有部分，任意一篇失效时整篇文章视为失效
+async fn check_article(client: &Client, article: &TelegraphEntity) -> LinkStatus {
+    let mut result = LinkStatus::Alive;
+    for url in article.parts.iter() {
+        match check_link(client, url).await {
+            LinkStatus::Dead => return LinkStatus::Dead,
+            LinkStatus::Alive => {}
+            status => result = status,
+        }
+    }
+    result
+}
+
+/// 通过 HEAD 请求检查链接，只有明确返回 404 或 410 时才视为失效
+pub(super) async fn check_link(client: &Client, url: &str) -> LinkStatus {
+    match client.head(url).send().await {
+        Ok(resp) => match resp.status() {
+            StatusCode::NOT_FOUND | StatusCode::GONE => LinkStatus::Dead,
+            status if status.is_success() || status.is_redirection() => LinkStatus::Alive,
+            status => {
+                warn!("检查链接出错：{} {}", url, status);
+                LinkStatus::Error
+            }
+        },
+        Err(err) => {
+            warn!("检查链接出错：{} {}", url, err);
+            LinkStatus::Error
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use reqwest::Url;
+use teloxide::prelude::*;
+use teloxide::requests::HasPayload;
+use teloxide::types::{InputFile, InputMedia, InputMediaPhoto, MessageId, ParseMode};
+use tracing::warn;
+
+use super::ExloliUploader;
+use crate::config::{Channel, PostMode};
+use crate::database::{MessageEntity, MessageKind, PageImage};
+use crate::ehentai::GalleryInfo;
+
+/// 图片说明的最大长度，按照解析 HTML 之后的 UTF-16 字符计算
+const CAPTION_LIMIT: usize = 1024;
+
+impl ExloliUploader {
+    /// 按照配置的发送方式将画廊发送到频道，返回消息 ID 和消息类型
+    ///
+    /// 消息正文超出图片说明的长度限制、没有可用的图片或者发送图片失败时，退回为纯文本消息
+    pub(super) async fn send_post<T: GalleryInfo>(
+        &self,
+        channel: &Channel,
+        gallery: &T,
+        text: String,
+        reply_to: Option<MessageId>,
+    ) -> Result<(MessageId, MessageKind)> {
+        let mode = match self.config.telegram.post_mode {
+            PostMode::Text => PostMode::Text,
+            _ if caption_len(&text) > CAPTION_LIMIT => {
+                warn!("消息正文超出图片说明的长度限制，以纯文本发送");
+                PostMode::Text
+            }
+            mode => mode,
+        };
+        if mode != PostMode::Text {
+            let images = self.preview_images(gallery).await?;
+            // 图片由 telegram 自行下载，图床无法被 telegram 访问时会发送失败
+            match self.send_photos(channel, mode, images, &text, reply_to).await {
+                Ok(Some(sent)) => return Ok(sent),
+                Ok(None) => {}
+                Err(err) => warn!("发送图片失败，以纯文本发送：{}", err),
+            }
+        }
+
+        let mut req = self.bot.send_message(channel.channel_id.clone(), text);
+        req.payload_mut().reply_to_message_id = reply_to;
+        let msg = req.await?;
+        Ok((msg.id, MessageKind::Text))
+    }
+
+    /// 以封面或者图片组的形式发送画廊，图片数量不足时返回 None
+    async fn send_photos(
+        &self,
+        channel: &Channel,
+        mode: PostMode,
+        images: Vec<Url>,
+        text: &str,
+        reply_to: Option<MessageId>,
+    ) -> Result<Option<(MessageId, MessageKind)>> {
+        let config = &self.config.telegram;
+        let chat = channel.channel_id.clone();
+
+        if mode == PostMode::Cover && !images.is_empty() {
+            let mut req = self
+                .bot
+                .send_photo(chat, InputFile::url(images[0].clone()))
+                .caption(text)
+                .has_spoiler(config.spoiler);
+            req.payload_mut().reply_to_message_id = reply_to;
+            let msg = req.await?;
+            return Ok(Some((msg.id, MessageKind::Photo)));
+        }
+
+        if mode == PostMode::MediaGroup && images.len() > 1 {
+            let media = images
+                .into_iter()
+                .take(config.media_group_size.clamp(2, 10))
+                .enumerate()
+                .map(|(i, url)| {
+                    let mut photo = InputMediaPhoto::new(InputFile::url(url));
+                    if i == 0 {
+                        photo = photo.caption(text).parse_mode(ParseMode::Html);
+                    }
+                    if config.spoiler {
+                        photo = photo.spoiler();
+                    }
+                    InputMedia::Photo(photo)
+                })
+                .collect::<Vec<_>>();
+            let mut req = self.bot.send_media_group(chat, media);
+            req.payload_mut().reply_to_message_id = reply_to;
+            let msgs = req.await?;
+            let msg = msgs.first().context("发送图片组失败")?;
+            return Ok(Some((msg.id, MessageKind::MediaGroup)));
+        }
+
+        Ok(None)
+    }
+
+    /// 根据消息类型编辑频道中的画廊消息，图片说明超出长度限制时从开头逐行删除
+    pub(super) async fn edit_post(
+        &self,
+        channel: &Channel,
+        msg: &MessageEntity,
+        text: String,
+    ) -> Result<()> {
+        let chat = channel.channel_id.clone();
+        match msg.kind {
+            MessageKind::Text => {
+                self.bot.edit_message_text(chat, MessageId(msg.id), text).await?;
+            }
+            MessageKind::Photo | MessageKind::MediaGroup => {
+                self.bot
+                    .edit_message_caption(chat, MessageId(msg.id))
+                    .caption(fit_caption(&text))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 用于预览的图片：封面在前，其余按页码排列，跳过广告
+    async fn preview_images<T: GalleryInfo>(&self, gallery: &T) -> Result<Vec<Url>> {
+        let mut pages = PageImage::get_by_gallery_id(&self.db, gallery.url().id()).await?;
+        // cover 是从 0 开始的序号，需要在去掉广告之前按页码找到封面
+        if let Some(index) = pages.iter().position(|p| p.page == gallery.cover() as i32 + 1) {
+            let cover = pages.remove(index);
+            pages.insert(0, cover);
+        }
+        Ok(pages
+            .iter()
+            .filter(|p| !p.image.ad)
+            .filter_map(|p| p.image.url().parse().ok())
+            .collect())
+    }
+}
+
+/// 计算 HTML 文本在 telegram 解析之后的长度
+fn caption_len(html: &str) -> usize {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"");
+    text.replace("&amp;", "&").encode_utf16().count()
+}
+
+/// 从开头逐行删除，直到文本符合图片说明的长度限制
+fn fit_caption(html: &str) -> String {
+    let mut lines = html.lines().collect::<Vec<_>>();
+    while lines.len() > 1 && caption_len(&lines.join("\n")) > CAPTION_LIMIT {
+        lines.remove(0);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{ImageEntity, PageEntity};
+    use crate::ehentai::EhGallery;
+    use crate::host::mock::MockServer;
+
+    #[test]
+    fn caption_length() {
+        assert_eq!(caption_len("<b>标题</b>"), 2);
+        assert_eq!(caption_len(r#"<a href="https://e.org/?a=1&amp;b=2">x</a>"#), 1);
+        assert_eq!(caption_len("&lt;a&gt; &amp;lt; &quot;"), 10);
+        // emoji 在 UTF-16 中占两个字符
+        assert_eq!(caption_len("😀a"), 3);
+    }
+
+    #[test]
+    fn fit_caption_drops_leading_lines() {
+        let short = "<b>a</b>\nb";
+        assert_eq!(fit_caption(short), short);
+
+        let tags = "标".repeat(CAPTION_LIMIT - 10);
+        let html = format!("title\n<b>{}</b>\n<a href=\"x\">link</a>", tags);
+        assert_eq!(fit_caption(&html), format!("<b>{}</b>\n<a href=\"x\">link</a>", tags));
+        // 只剩一行时不再删除
+        let long = "a".repeat(CAPTION_LIMIT + 1);
+        assert_eq!(fit_caption(&format!("b\n{}", long)), long);
+    }
+
+    #[tokio::test]
+    async fn preview_images() {
+        let server = MockServer::start(vec![]).await;
+        let uploader = ExloliUploader::mock(&server.url()).await;
+        let urls = (1..=4).map(|page| format!("https://files.catbox.moe/{page}.jpg"));
+        PageEntity::seed(&uploader.db, 1, urls).await;
+        ImageEntity::mark_ad(&uploader.db, &[1]).await.unwrap();
+        // 封面是第 3 页，第 1 页的广告不影响封面的位置
+        let gallery = EhGallery { cover: 2, ..EhGallery::mock(1, 4) };
+        let images = uploader.preview_images(&gallery).await.unwrap();
+        let names = images.iter().map(|url| url.path()).collect::<Vec<_>>();
+        assert_eq!(names, ["/3.jpg", "/2.jpg", "/4.jpg"]);
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt::{self, Display};
+
+use tokio::sync::broadcast;
+
+use super::ExloliUploader;
+
+/// 上传过程中产生的进度事件，和画廊 ID 一起广播给订阅者
+#[derive(Debug, Clone)]
+pub enum Progress {
+    /// 开始上传图片，total 为总页数，pending 为需要下载并上传的页数
+    Started { total: usize, pending: usize },
+    /// 已解析出某一页的图片地址
+    Resolved(i32),
+    /// 已下载某一页的图片
+    Downloaded(i32),
+    /// 已上传某一页的图片
+    Uploaded(i32),
+    /// 某一页上传失败
+    Failed(i32, String),
+    /// 已发布 telegraph 文章
+    Published(String),
+    /// 任务结束，error 为失败原因，retry 表示之后是否会重试
+    Finished { error: Option<String>, retry: bool },
+}
+
+impl ExloliUploader {
+    /// 订阅所有画廊的上传进度
+    pub fn subscribe(&self) -> broadcast::Receiver<(i32, Progress)> {
+        self.progress.subscribe()
+    }
+
+    /// 发送进度事件，没有订阅者时直接丢弃
+    pub(super) fn report(&self, gallery_id: i32, progress: Progress) {
+        let _ = self.progress.send((gallery_id, progress));
+    }
+}
+
+/// 根据进度事件汇总出的上传状态
+#[derive(Debug, Clone, Default)]
+pub struct UploadProgress {
+    pub total: usize,
+    pub pending: usize,
+    pub resolved: usize,
+    pub downloaded: usize,
+    pub uploaded: usize,
+    pub failed: Vec<(i32, String)>,
+    pub article: Option<String>,
+    /// 任务结束时的结果，None 表示仍在进行中
+    pub finished: Option<Result<(), String>>,
+    pub retry: bool,
+}
+
+impl UploadProgress {
+    pub fn apply(&mut self, progress: Progress) {
+        match progress {
+            Progress::Started { total, pending } => {
+                // 重试时会重新开始，之前的计数已经没有意义
+                *self = Self { total, pending, ..Default::default() };
+            }
+            Progress::Resolved(_) => self.resolved += 1,
+            Progress::Downloaded(_) => self.downloaded += 1,
+            Progress::Uploaded(_) => self.uploaded += 1,
+            Progress::Failed(page, err) => self.failed.push((page, err)),
+            Progress::Published(url) => self.article = Some(url),
+            Progress::Finished { error, retry } => {
+                self.finished = Some(error.map_or(Ok(()), Err));
+                self.retry = retry;
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_some()
+    }
+}
+
+impl Display for UploadProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.finished {
+            None => writeln!(f, "上传中……")?,
+            Some(Ok(())) => writeln!(f, "上传完成")?,
+            Some(Err(err)) if self.retry => writeln!(f, "上传失败，稍后重试：{}", err)?,
+            Some(Err(err)) => writeln!(f, "上传失败：{}", err)?,
+        }
+        writeln!(f, "页数：{}，需要上传：{}", self.total, self.pending)?;
+        writeln!(f, "解析：{}/{}", self.resolved, self.pending)?;
+        writeln!(f, "下载：{}/{}", self.downloaded, self.pending)?;
+        writeln!(f, "上传：{}/{}", self.uploaded, self.pending)?;
+        if let Some(article) = &self.article {
+            writeln!(f, "文章：{}", article)?;
+        }
+        if !self.failed.is_empty() {
+            writeln!(f, "失败 {} 页：", self.failed.len())?;
+            // 只列出前几页，避免消息过长
+            for (page, err) in self.failed.iter().take(10) {
+                writeln!(f, "第 {} 页：{}", page, err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let mut progress = UploadProgress::default();
+        progress.apply(Progress::Started { total: 10, pending: 3 });
+        progress.apply(Progress::Resolved(1));
+        progress.apply(Progress::Downloaded(1));
+        progress.apply(Progress::Failed(2, "404".to_string()));
+        assert!(!progress.is_finished());
+        assert_eq!(
+            progress.to_string(),
+            "上传中……\n页数：10，需要上传：3\n解析：1/3\n下载：1/3\n上传：0/3\n失败 1 页：\n第 2 页：404\n"
+        );
+
+        // 重试时计数会被重置
+        progress.apply(Progress::Started { total: 10, pending: 2 });
+        progress.apply(Progress::Uploaded(1));
+        progress.apply(Progress::Published("https://telegra.ph/foo".to_string()));
+        progress.apply(Progress::Finished { error: None, retry: false });
+        assert!(progress.is_finished());
+        assert!(progress.failed.is_empty());
+        assert_eq!(
+            progress.to_string(),
+            "上传完成\n页数：10，需要上传：2\n解析：0/2\n下载：0/2\n上传：1/2\n文章：https://telegra.ph/foo\n"
+        );
+
+        progress.apply(Progress::Finished { error: Some("timeout".to_string()), retry: true });
+        assert!(progress.to_string().starts_with("上传失败，稍后重试：timeout\n"));
+    }
+}
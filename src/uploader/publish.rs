@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{Local, Utc};
+use teloxide::types::MessageId;
+use tokio::time::{self, Instant};
+use tracing::{error, info};
+
+use super::ExloliUploader;
+use crate::config::Channel;
+use crate::database::{GalleryEntity, JobEntity, MessageEntity, TelegraphEntity};
+use crate::ehentai::{EhError, EhGalleryUrl, GalleryInfo};
+
+impl ExloliUploader {
+    /// 按照配置的节奏和时间段，依次将频道发布队列中的画廊发送到频道
+    #[tracing::instrument(skip_all, fields(channel = channel.name))]
+    pub(super) async fn publish_loop(&self, channel: &Channel) {
+        let config = &self.config.publish;
+        let mut last: Option<Instant> = None;
+        loop {
+            if !config.in_window(Local::now().time()) {
+                time::sleep(Duration::from_secs(60)).await;
+                continue;
+            }
+            if let Some(last) = last {
+                let next = last + config.interval;
+                if next > Instant::now() {
+                    time::sleep_until(next).await;
+                    continue;
+                }
+            }
+            let now = Utc::now().naive_utc();
+            let job = match JobEntity::list_ready(&self.db, &channel.id()).await {
+                // 跳过发布失败后还在等待重试的任务
+                Ok(jobs) => jobs.into_iter().find(|job| job.run_after <= now),
+                Err(err) => {
+                    error!("读取发布队列失败：{}", err);
+                    None
+                }
+            };
+            let Some(job) = job else {
+                tokio::select! {
+                    _ = self.publish_notify.notified() => {}
+                    _ = time::sleep(Duration::from_secs(60)) => {}
+                }
+                continue;
+            };
+            last = Some(Instant::now());
+            self.publish_job(&job, channel).await;
+        }
+    }
+
+    /// 发布一个任务，并根据结果更新任务状态
+    async fn publish_job(&self, job: &JobEntity, channel: &Channel) {
+        let result = match self.publish(job, channel).await {
+            Ok(()) => JobEntity::done(&self.db, job.id).await,
+            Err(err) => {
+                error!("发布失败：{} {:?}", job.url, err);
+                if is_permanent(&err) || job.attempts >= self.config.queue.max_attempts {
+                    JobEntity::fail(&self.db, job.id, &err.to_string(), false, Default::default())
+                        .await
+                } else {
+                    // 图片和文章已经存在，留在发布队列中稍后重新发布，不需要重新上传
+                    let delay = chrono::Duration::from_std(self.config.queue.retry_delay)
+                        .unwrap_or_default()
+                        * job.attempts.max(1);
+                    JobEntity::retry_publish(&self.db, job.id, &err.to_string(), delay).await
+                }
+            }
+        };
+        if let Err(err) = result {
+            error!("更新任务状态失败：{}", err);
+        }
+    }
+
+    /// 将已经上传完毕的画廊发送到频道
+    #[tracing::instrument(skip(self, job, channel), fields(job = job.id))]
+    async fn publish(&self, job: &JobEntity, channel: &Channel) -> Result<()> {
+        info!("发布：{}", job.url);
+        let url = job.url.parse::<EhGalleryUrl>()?;
+        // 重新获取画廊信息，排队期间标签和标题可能已经更新
+        let gallery = self.ehentai.get_gallery(&url).await?;
+        let telegraph =
+            TelegraphEntity::get(&self.db, url.id()).await?.context("找不到 telegraph 文章")?;
+        let text = self.create_message_text(channel, &gallery, &telegraph.url).await?;
+
+        let reply_to = match &gallery.parent {
+            Some(parent) => MessageEntity::get_by_gallery(&self.db, parent.id(), &channel.id())
+                .await?
+                .map(|m| MessageId(m.id)),
+            None => None,
+        };
+        let (id, kind) = self.send_post(channel, &gallery, text, reply_to).await?;
+
+        MessageEntity::create(&self.db, id.0, &channel.id(), gallery.url.id(), kind).await?;
+        GalleryEntity::create(&self.db, &gallery).await?;
+
+        if let Err(err) = self.update_old_versions(channel, gallery.parent()).await {
+            error!("更新旧版本消息失败：{:?}", err);
+        }
+
+        Ok(())
+    }
+
+    /// 按发布顺序列出频道中等待发布的任务
+    pub async fn publish_queue(&self, channel: &Channel) -> Result<Vec<JobEntity>> {
+        Ok(JobEntity::list_ready(&self.db, &channel.id()).await?)
+    }
+
+    /// 将指定画廊移动到频道发布队列的第 position 位（从 1 开始），返回调整后的队列
+    pub async fn move_in_queue(
+        &self,
+        channel: &Channel,
+        gallery_id: i32,
+        position: usize,
+    ) -> Result<Vec<JobEntity>> {
+        let mut jobs = JobEntity::list_ready(&self.db, &channel.id()).await?;
+        let index = jobs
+            .iter()
+            .position(|job| job.gallery_id == gallery_id)
+            .context("该画廊不在发布队列中")?;
+        let job = jobs.remove(index);
+        jobs.insert(position.saturating_sub(1).min(jobs.len()), job);
+        JobEntity::reorder(&self.db, &jobs.iter().map(|job| job.id).collect::<Vec<_>>()).await?;
+        Ok(jobs)
+    }
+}
+
+/// 发布失败的原因是否无法通过重试解决
+///
+/// 除了画廊被删除等 E 站错误外，没有底层错误的 anyhow 错误（如找不到 telegraph 文章）也不会自行恢复
+fn is_permanent(err: &anyhow::Error) -> bool {
+    if let Some(err) = err.downcast_ref::<EhError>() {
+        return err.is_permanent();
+    }
+    err.chain().count() == 1 && (err.is::<&str>() || err.is::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::JobStatus;
+    use crate::host::mock::MockServer;
+
+    #[tokio::test]
+    async fn publish_failure_stays_ready() {
+        // E 站返回错误，重新获取画廊信息失败
+        let server = MockServer::route(|_| (500, vec![])).await;
+        let uploader = ExloliUploader::mock(&server.url()).await;
+        let channel = &uploader.config.channels[0];
+        let url = "https://exhentai.org/g/1/aaaaaaaaaa/".parse().unwrap();
+        JobEntity::create(&uploader.db, &url, &channel.id(), false).await.unwrap();
+        let job = JobEntity::claim(&uploader.db).await.unwrap().unwrap();
+        JobEntity::ready(&uploader.db, job.id).await.unwrap();
+
+        uploader.publish_job(&job, channel).await;
+
+        let jobs = JobEntity::list_ready(&uploader.db, &channel.id()).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].status, JobStatus::Ready);
+        assert_eq!(jobs[0].attempts, 2);
+        assert!(jobs[0].last_error.is_some());
+        assert!(jobs[0].run_after > Utc::now().naive_utc());
+        // 不会重新进入上传队列
+        assert!(JobEntity::claim(&uploader.db).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn publish_gives_up_after_max_attempts() {
+        let server = MockServer::route(|_| (500, vec![])).await;
+        let uploader = ExloliUploader::mock(&server.url()).await;
+        let channel = &uploader.config.channels[0];
+        let url = "https://exhentai.org/g/1/aaaaaaaaaa/".parse().unwrap();
+        JobEntity::create(&uploader.db, &url, &channel.id(), false).await.unwrap();
+        let job = JobEntity::claim(&uploader.db).await.unwrap().unwrap();
+        JobEntity::ready(&uploader.db, job.id).await.unwrap();
+
+        for _ in 0..uploader.config.queue.max_attempts {
+            let jobs = JobEntity::list_ready(&uploader.db, &channel.id()).await.unwrap();
+            uploader.publish_job(&jobs[0], channel).await;
+        }
+
+        // 达到最大尝试次数后标记为失败，不再留在发布队列中
+        assert!(JobEntity::list_ready(&uploader.db, &channel.id()).await.unwrap().is_empty());
+        let job = JobEntity::get(&uploader.db, job.id).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+    }
+
+    #[test]
+    fn permanent_errors() {
+        assert!(is_permanent(&EhError::GalleryRemoved.into()));
+        assert!(!is_permanent(&EhError::RateLimited(String::new()).into()));
+        assert!(is_permanent(&None::<()>.context("找不到 telegraph 文章").unwrap_err()));
+        assert!(is_permanent(&anyhow::anyhow!("找不到频道：{}", 1)));
+        let io = std::io::Error::new(std::io::ErrorKind::TimedOut, "timeout");
+        assert!(!is_permanent(&Err::<(), _>(io).context("发送消息失败").unwrap_err()));
+    }
+}
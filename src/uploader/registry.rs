@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{NaiveDateTime, Utc};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+use super::{ExloliUploader, Progress};
+use crate::database::JobEntity;
+use crate::ehentai::EhGalleryUrl;
+
+/// 上传被管理员取消
+#[derive(Debug, Error)]
+#[error("上传已取消")]
+pub struct Cancelled;
+
+impl ExloliUploader {
+    /// 列出正在下载和上传图片的任务
+    pub fn running(&self) -> Vec<ActiveUpload> {
+        self.running.list()
+    }
+
+    /// 取消指定画廊的上传，正在执行的任务会停止并回滚，排队中和等待发布的任务直接移出队列
+    ///
+    /// 返回是否找到了可以取消的任务，找到时会发送任务结束的进度事件
+    pub async fn cancel(&self, gallery_id: i32) -> anyhow::Result<bool> {
+        let cancelled = self.running.cancel(gallery_id)
+            || JobEntity::cancel_pending(&self.db, gallery_id).await?;
+        if cancelled {
+            let error = Some("已取消".to_string());
+            self.report(gallery_id, Progress::Finished { error, retry: false });
+        }
+        Ok(cancelled)
+    }
+}
+
+/// 一个正在执行的上传任务
+#[derive(Debug, Clone)]
+pub struct ActiveUpload {
+    /// 任务 ID
+    pub job: i64,
+    /// 画廊 URL
+    pub url: EhGalleryUrl,
+    /// 开始执行的时间
+    pub started_at: NaiveDateTime,
+    token: CancellationToken,
+}
+
+impl ActiveUpload {
+    /// 是否已经被取消，正在等待上传停止
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+/// 正在执行的上传任务的登记表，按画廊 ID 索引，克隆后共享同一份数据
+#[derive(Debug, Clone, Default)]
+pub struct UploadRegistry(Arc<Mutex<HashMap<i32, ActiveUpload>>>);
+
+impl UploadRegistry {
+    /// 登记一个任务，返回的 guard 被 drop 时自动注销
+    pub fn register(&self, job: i64, url: &EhGalleryUrl) -> RegistryGuard {
+        let upload = ActiveUpload {
+            job,
+            url: url.clone(),
+            started_at: Utc::now().naive_utc(),
+            token: CancellationToken::new(),
+        };
+        self.0.lock().unwrap().insert(url.id(), upload);
+        RegistryGuard { registry: self.clone(), gallery_id: url.id(), job }
+    }
+
+    /// 获取指定画廊的取消令牌，没有登记时返回一个永远不会被取消的令牌
+    pub fn token(&self, gallery_id: i32) -> CancellationToken {
+        match self.0.lock().unwrap().get(&gallery_id) {
+            Some(upload) => upload.token.clone(),
+            None => CancellationToken::new(),
+        }
+    }
+
+    /// 取消指定画廊的上传，返回是否找到了该任务
+    pub fn cancel(&self, gallery_id: i32) -> bool {
+        match self.0.lock().unwrap().get(&gallery_id) {
+            Some(upload) => {
+                upload.token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 注销指定画廊，之后该任务不能再被取消
+    pub fn remove(&self, gallery_id: i32) {
+        self.0.lock().unwrap().remove(&gallery_id);
+    }
+
+    /// 列出所有正在执行的任务
+    pub fn list(&self) -> Vec<ActiveUpload> {
+        let mut uploads = self.0.lock().unwrap().values().cloned().collect::<Vec<_>>();
+        uploads.sort_by_key(|upload| upload.job);
+        uploads
+    }
+}
+
+pub struct RegistryGuard {
+    registry: UploadRegistry,
+    gallery_id: i32,
+    job: i64,
+}
+
+impl Drop for RegistryGuard {
+    fn drop(&mut self) {
+        let mut map = self.registry.0.lock().unwrap();
+        // 只注销自己登记的任务
+        if map.get(&self.gallery_id).is_some_and(|upload| upload.job == self.job) {
+            map.remove(&self.gallery_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::database::{ImageEntity, PageEntity};
+    use crate::ehentai::EhGallery;
+    use crate::host::HostKind;
+
+    #[tokio::test]
+    async fn cancel_rolls_back_new_pages() {
+        // 只建立连接不响应，使第 3 页的下载一直挂起
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uploader =
+            ExloliUploader::mock(&format!("http://{}/", listener.local_addr().unwrap())).await;
+        let db = &uploader.db;
+        let gallery = EhGallery::mock(1, 3);
+        // 第 1 页是上次上传留下的，第 2 页的图片已经存在，会在本次上传中直接记录
+        PageEntity::seed(db, 1, ["https://files.catbox.moe/1.jpg".to_string()]).await;
+        let url = "https://files.catbox.moe/2.jpg";
+        ImageEntity::create(db, 2, "hash2", url, HostKind::Catbox, None).await.unwrap();
+
+        let _guard = uploader.running.register(1, &gallery.url);
+        let mut rx = uploader.subscribe();
+        let upload = uploader.upload_or_cancel(&gallery);
+        let cancel = async {
+            while !matches!(rx.recv().await, Ok((1, Progress::Started { .. }))) {}
+            assert_eq!(PageEntity::count(db, 1).await.unwrap(), 2);
+            assert!(uploader.cancel(1).await.unwrap());
+        };
+        let (result, _) = tokio::join!(upload, cancel);
+
+        assert!(result.unwrap_err().is::<Cancelled>());
+        assert_eq!(PageEntity::list_pages(db, 1).await.unwrap(), vec![1]);
+        assert!(matches!(rx.try_recv(), Ok((1, Progress::Finished { error: Some(_), .. }))));
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn cancel_pending_reports_finished() {
+        let uploader = ExloliUploader::mock("http://127.0.0.1:1/").await;
+        let url = "https://exhentai.org/g/1/aaaaaaaaaa/".parse().unwrap();
+        let channel = &uploader.config.channels[0];
+        JobEntity::create(&uploader.db, &url, &channel.id(), false).await.unwrap();
+
+        let mut rx = uploader.subscribe();
+        assert!(uploader.cancel(1).await.unwrap());
+        let (id, progress) = rx.try_recv().unwrap();
+        assert_eq!(id, 1);
+        assert!(
+            matches!(progress, Progress::Finished { error: Some(e), retry: false } if e == "已取消")
+        );
+
+        // 没有可以取消的任务时不发送事件
+        assert!(!uploader.cancel(1).await.unwrap());
+        assert!(rx.try_recv().is_err());
+    }
+}
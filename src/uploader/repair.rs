@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use futures::{stream, StreamExt};
+use reqwest::Client;
+use tracing::{error, info};
+
+use super::health::{check_link, health_client};
+use super::ExloliUploader;
+use crate::database::{
+    GalleryEntity, ImageEntity, LinkStatus, PageEntity, PageImage, TelegraphEntity,
+};
+use crate::ehentai::{EhPageUrl, GalleryInfo};
+
+impl ExloliUploader {
+    /// 逐张检查画廊中的图片，只从 E 站重新下载并上传失效的图片，然后重建引用了这些图片的文章
+    ///
+    /// 返回重新上传的图片数量
+    pub async fn repair_images(&self, gallery_id: i32) -> Result<usize> {
+        let client = health_client()?;
+        let client = &client;
+        let pages = PageImage::get_by_gallery_id(&self.db, gallery_id)
+            .await?
+            .into_iter()
+            .filter(|p| !p.image.ad);
+        let results = stream::iter(pages)
+            .map(|page| async move {
+                let status = check_link(client, &page.image.url()).await;
+                (page, status)
+            })
+            .buffer_unordered(self.config.health.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut broken = vec![];
+        for (page, status) in results {
+            ImageEntity::update_status(&self.db, page.image.id, status).await?;
+            if status == LinkStatus::Dead {
+                broken.push(page);
+            }
+        }
+        if broken.is_empty() {
+            return Ok(0);
+        }
+
+        info!("画廊 {} 中有 {} 张图片失效，重新上传", gallery_id, broken.len());
+        let mut rehosted = vec![];
+        for page in broken {
+            match self.rehost_image(client, gallery_id, &page).await {
+                Ok(()) => rehosted.push(page.image.id),
+                Err(err) => error!("重新上传第 {} 页失败：{:?}", page.page, err),
+            }
+        }
+        if rehosted.is_empty() {
+            return Ok(0);
+        }
+
+        // 同一张图片可能被多个画廊引用，这些画廊的文章都需要重建，单个画廊失败不影响其他画廊
+        for id in PageEntity::list_galleries(&self.db, &rehosted).await? {
+            let result = match GalleryEntity::get(&self.db, id).await {
+                Ok(Some(gallery)) => self.rebuild_article(&gallery).await,
+                Ok(None) => Ok(()),
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = result {
+                error!("重建文章失败：{} {:?}", id, err);
+            }
+        }
+        Ok(rehosted.len())
+    }
+
+    /// 根据页面重新下载图片并上传到当前的图床，更新数据库中图片的 URL
+    async fn rehost_image(&self, client: &Client, gallery_id: i32, page: &PageImage) -> Result<()> {
+        let img = &page.image;
+        let url = EhPageUrl::new(&img.hash, gallery_id, page.page);
+        let image = self.download_page(client, &url).await?;
+        let uploaded = self.upload_to_host(&url, &image.url, image.data).await?;
+        ImageEntity::update_url(&self.db, img.id, &uploaded, self.host.kind()).await?;
+        info!("已重新上传：{} -> {}", img.url(), uploaded);
+        Ok(())
+    }
+
+    /// 使用数据库中最新的图片重建画廊的文章
+    ///
+    /// 文章篇数不变时直接编辑原有的文章，URL 保持不变，频道消息也不需要修改；否则重新发布文章
+    pub async fn rebuild_article(&self, gallery: &GalleryEntity) -> Result<()> {
+        let Some(telegraph) = TelegraphEntity::get(&self.db, gallery.id).await? else {
+            return Ok(());
+        };
+        let content = self.render_article(gallery).await?;
+        if content.chunks.len() != telegraph.parts.len() {
+            info!("文章篇数变化，重新发布：{}", gallery.url());
+            return self.republish(gallery).await;
+        }
+        info!("重建文章：{}", gallery.url());
+        for (i, url) in telegraph.parts.iter().enumerate() {
+            let path = url.rsplit('/').next().context("无效的文章 URL")?;
+            let html = content.html(i, &telegraph.parts);
+            self.edit_telegraph_page(&telegraph.account, path, &content.title(i), &html).await?;
+        }
+        TelegraphEntity::update_status(&self.db, gallery.id, LinkStatus::Alive).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ehentai::EhGallery;
+    use crate::host::mock::MockServer;
+    use crate::uploader::telegraph::MockPages;
+    use crate::uploader::TelegraphPool;
+
+    fn jpeg() -> Vec<u8> {
+        let mut jpeg = std::io::Cursor::new(vec![]);
+        image::RgbImage::new(16, 16).write_to(&mut jpeg, image::ImageFormat::Jpeg).unwrap();
+        jpeg.into_inner()
+    }
+
+    /// 模拟图床、E 站和 catbox：dead 开头的图片已经失效，其余图片正常
+    async fn server() -> MockServer {
+        let url = Arc::new(std::sync::OnceLock::<String>::new());
+        let server_url = url.clone();
+        let jpeg = jpeg();
+        let server = MockServer::route(move |line| {
+            let base = server_url.get().unwrap();
+            match line.split(' ').take(2).collect::<Vec<_>>()[..] {
+                ["HEAD", path] if path.starts_with("/dead") => (404, vec![]),
+                ["GET", path] if path.starts_with("/s/") => {
+                    let src = format!("{}h/0123456789/keystamp=1;fileindex=2/02.jpg", base);
+                    (200, format!(r#"<img id="img" src="{}">"#, src).into_bytes())
+                }
+                ["GET", path] if path.starts_with("/h/") => (200, jpeg.clone()),
+                ["POST", _] => (200, b"https://files.catbox.moe/new.jpg".to_vec()),
+                _ => (200, vec![]),
+            }
+        })
+        .await;
+        url.set(server.url()).unwrap();
+        server
+    }
+
+    async fn setup(uploader: &ExloliUploader, server: &MockServer, parts: &[&str]) {
+        let db = &uploader.db;
+        GalleryEntity::create(db, &EhGallery::mock(1, 3)).await.unwrap();
+        let names = ["alive1", "dead2", "alive3"];
+        let urls = names.map(|name| format!("{}{}.jpg", server.url(), name));
+        PageEntity::seed(db, 1, urls).await;
+        let parts = parts.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        TelegraphEntity::create(db, 1, "mock", &parts).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn repair_only_dead_images() {
+        let server = server().await;
+        let pages = Arc::new(MockPages::default());
+        let mut uploader = ExloliUploader::mock(&server.url()).await;
+        uploader.telegraph = TelegraphPool::mock(pages.clone());
+        setup(&uploader, &server, &["https://telegra.ph/old"]).await;
+
+        assert_eq!(uploader.repair_images(1).await.unwrap(), 1);
+
+        let images = ImageEntity::get_by_gallery_id(&uploader.db, 1).await.unwrap();
+        let urls = images.iter().map(|img| img.url()).collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                format!("{}alive1.jpg", server.url()),
+                "https://files.catbox.moe/new.jpg".to_string(),
+                format!("{}alive3.jpg", server.url()),
+            ]
+        );
+        // 只从 E 站下载了失效的第 2 页
+        let requests = server.requests();
+        let lines = requests.iter().map(|r| String::from_utf8_lossy(r).to_string());
+        assert_eq!(lines.filter(|r| r.starts_with("GET /s/")).count(), 1);
+        // 文章篇数没有变化，直接编辑原有的文章
+        assert_eq!(pages.calls(), ["edit old"]);
+        let telegraph = TelegraphEntity::get(&uploader.db, 1).await.unwrap().unwrap();
+        assert_eq!(telegraph.parts.0, ["https://telegra.ph/old"]);
+    }
+
+    #[tokio::test]
+    async fn rebuild_republishes_when_parts_change() {
+        let server = server().await;
+        let pages = Arc::new(MockPages::default());
+        let mut uploader = ExloliUploader::mock(&server.url()).await;
+        uploader.telegraph = TelegraphPool::mock(pages.clone());
+        setup(&uploader, &server, &["https://telegra.ph/a", "https://telegra.ph/b"]).await;
+
+        let gallery = GalleryEntity::get(&uploader.db, 1).await.unwrap().unwrap();
+        uploader.rebuild_article(&gallery).await.unwrap();
+
+        assert_eq!(pages.calls(), ["create title"]);
+        let telegraph = TelegraphEntity::get(&uploader.db, 1).await.unwrap().unwrap();
+        assert_eq!(telegraph.parts.0, ["https://telegra.ph/page-1"]);
+    }
+}
@@ -0,0 +1,329 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use telegraph_rs::{Error, Page, Telegraph};
+use tokio::time::{self, Instant};
+use tracing::warn;
+
+use crate::config::Config;
+use crate::utils::ratelimit::RateLimiter;
+
+/// Telegraph 账号池
+///
+/// 新文章默认使用当前账号发布，当前账号被限流时轮换到下一个没有被限流的账号，
+/// 所有账号都被限流时等待最早解除限流的账号。已经发布的文章只能由发布它的账号编辑。
+#[derive(Debug, Clone)]
+pub struct TelegraphPool {
+    accounts: Arc<Vec<Account>>,
+    /// 当前用于发布新文章的账号
+    current: Arc<AtomicUsize>,
+}
+
+/// 发布和编辑页面的接口，测试时可以替换为不访问网络的实现
+trait PageApi: Debug + Send + Sync {
+    fn create_page<'a>(
+        &'a self,
+        title: &'a str,
+        node: &'a str,
+    ) -> BoxFuture<'a, Result<Page, Error>>;
+
+    fn edit_page<'a>(
+        &'a self,
+        path: &'a str,
+        title: &'a str,
+        node: &'a str,
+    ) -> BoxFuture<'a, Result<Page, Error>>;
+}
+
+impl PageApi for Telegraph {
+    fn create_page<'a>(
+        &'a self,
+        title: &'a str,
+        node: &'a str,
+    ) -> BoxFuture<'a, Result<Page, Error>> {
+        Box::pin(Telegraph::create_page(self, title, node, false))
+    }
+
+    fn edit_page<'a>(
+        &'a self,
+        path: &'a str,
+        title: &'a str,
+        node: &'a str,
+    ) -> BoxFuture<'a, Result<Page, Error>> {
+        Box::pin(Telegraph::edit_page(self, path, title, node, false))
+    }
+}
+
+#[derive(Debug)]
+struct Account {
+    name: String,
+    client: Box<dyn PageApi>,
+    /// 每个账号单独限速
+    limit: RateLimiter,
+    /// 被限流时，限流解除的时间
+    flood_until: Mutex<Option<Instant>>,
+}
+
+impl TelegraphPool {
+    pub async fn new(config: &Config) -> Result<Self> {
+        let mut accounts = vec![];
+        for account in &config.telegraph.accounts {
+            let client = Telegraph::new(&config.telegraph.author_name)
+                .author_url(&config.telegraph.author_url)
+                .access_token(&account.access_token)
+                .create()
+                .await?;
+            accounts.push(Account {
+                name: account.name.clone(),
+                client: Box::new(client),
+                limit: RateLimiter::new(&config.limit.telegraph),
+                flood_until: Mutex::new(None),
+            });
+        }
+        Ok(Self { accounts: Arc::new(accounts), current: Arc::new(AtomicUsize::new(0)) })
+    }
+
+    /// 发布一篇新页面，返回发布所用的账号名称和页面
+    ///
+    /// 不指定账号时自动选择，被限流时轮换到其他账号；指定账号时，被限流会等待限流解除后重试
+    pub async fn create_page(
+        &self,
+        account: Option<&str>,
+        title: &str,
+        node: &str,
+    ) -> Result<(String, Page)> {
+        loop {
+            let account = match account {
+                Some(name) => self.get(name)?,
+                None => self.available().await,
+            };
+            account.wait().await;
+            match account.client.create_page(title, node).await {
+                Err(err) => account.check_flood(err)?,
+                Ok(page) => return Ok((account.name.clone(), page)),
+            }
+        }
+    }
+
+    /// 使用发布页面的账号编辑页面，被限流时等待限流解除后重试
+    pub async fn edit_page(
+        &self,
+        account: &str,
+        path: &str,
+        title: &str,
+        node: &str,
+    ) -> Result<Page> {
+        let account = self.get(account)?;
+        loop {
+            account.wait().await;
+            match account.client.edit_page(path, title, node).await {
+                Err(err) => account.check_flood(err)?,
+                Ok(page) => return Ok(page),
+            }
+        }
+    }
+
+    /// 根据名称查找账号
+    ///
+    /// 名称为空时是添加账号池之前发布的文章，使用旧的 access_token 对应的账号，没有时使用第一个账号
+    fn get(&self, name: &str) -> Result<&Account> {
+        match self.accounts.iter().find(|account| account.name == name) {
+            Some(account) => Ok(account),
+            None if name.is_empty() => Ok(&self.accounts[0]),
+            None => Err(anyhow!("找不到 telegraph 账号：{}", name)),
+        }
+    }
+
+    /// 从当前账号开始，找到第一个没有被限流的账号，所有账号都被限流时等待
+    async fn available(&self) -> &Account {
+        loop {
+            let now = Instant::now();
+            let start = self.current.load(Ordering::Relaxed);
+            let mut earliest = None;
+            for i in 0..self.accounts.len() {
+                let idx = (start + i) % self.accounts.len();
+                match self.accounts[idx].flood_until() {
+                    Some(until) if until > now => {
+                        earliest = Some(earliest.map_or(until, |t: Instant| t.min(until)));
+                    }
+                    _ => {
+                        if idx != start {
+                            warn!("切换到 telegraph 账号：{}", self.accounts[idx].name);
+                        }
+                        self.current.store(idx, Ordering::Relaxed);
+                        return &self.accounts[idx];
+                    }
+                }
+            }
+            let until = earliest.unwrap();
+            warn!("所有 telegraph 账号都被限流，等待 {:?}", until - now);
+            time::sleep_until(until).await;
+        }
+    }
+}
+
+impl Account {
+    fn flood_until(&self) -> Option<Instant> {
+        *self.flood_until.lock().unwrap()
+    }
+
+    /// 等待限流解除，并取走一个令牌
+    async fn wait(&self) {
+        if let Some(until) = self.flood_until() {
+            time::sleep_until(until).await;
+        }
+        self.limit.acquire().await;
+    }
+
+    /// 限流错误会记录限流解除的时间，以便调用者重试，其他错误直接返回
+    fn check_flood(&self, err: Error) -> Result<()> {
+        let wait = flood_wait(&err).ok_or(err)?;
+        warn!("telegraph 账号 {} 被限流，{:?} 后解除", self.name, wait);
+        *self.flood_until.lock().unwrap() = Some(Instant::now() + wait);
+        Ok(())
+    }
+}
+
+/// 解析 telegraph 的限流错误，例如 FLOOD_WAIT_7，返回需要等待的时间
+fn flood_wait(err: &Error) -> Option<Duration> {
+    match err {
+        Error::ApiError(msg) => {
+            msg.strip_prefix("FLOOD_WAIT_")?.parse().ok().map(Duration::from_secs)
+        }
+        _ => None,
+    }
+}
+
+/// 测试用的 telegraph，不访问网络，只记录发布和编辑过的页面
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MockPages(Mutex<Vec<String>>);
+
+#[cfg(test)]
+impl MockPages {
+    /// 按顺序返回所有调用，格式为 `create <标题>` 或 `edit <路径>`
+    pub(crate) fn calls(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn page(&self, call: String) -> Result<Page, Error> {
+        let mut calls = self.0.lock().unwrap();
+        calls.push(call);
+        let path = format!("page-{}", calls.len());
+        Ok(serde_json::from_value(serde_json::json!({
+            "path": path,
+            "url": format!("https://telegra.ph/{}", path),
+            "title": "",
+            "description": "",
+            "views": 0,
+        }))
+        .unwrap())
+    }
+}
+
+#[cfg(test)]
+impl PageApi for Arc<MockPages> {
+    fn create_page<'a>(
+        &'a self,
+        title: &'a str,
+        _node: &'a str,
+    ) -> BoxFuture<'a, Result<Page, Error>> {
+        Box::pin(async move { self.page(format!("create {}", title)) })
+    }
+
+    fn edit_page<'a>(
+        &'a self,
+        path: &'a str,
+        _title: &'a str,
+        _node: &'a str,
+    ) -> BoxFuture<'a, Result<Page, Error>> {
+        Box::pin(async move { self.page(format!("edit {}", path)) })
+    }
+}
+
+#[cfg(test)]
+impl TelegraphPool {
+    /// 只有一个名为 mock 的账号的账号池
+    pub(crate) fn mock(pages: Arc<MockPages>) -> Self {
+        Self::mock_accounts(vec![("mock", pages)])
+    }
+
+    /// 由多个测试账号组成的账号池
+    fn mock_accounts(accounts: Vec<(&str, Arc<MockPages>)>) -> Self {
+        let accounts = accounts
+            .into_iter()
+            .map(|(name, pages)| Account {
+                name: name.to_string(),
+                client: Box::new(pages),
+                limit: RateLimiter::unlimited(),
+                flood_until: Mutex::new(None),
+            })
+            .collect();
+        Self { accounts: Arc::new(accounts), current: Arc::new(AtomicUsize::new(0)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn account(name: &str) -> Account {
+        Account {
+            name: name.to_string(),
+            client: Box::new(Telegraph::new(name).access_token("token").create().await.unwrap()),
+            limit: RateLimiter::unlimited(),
+            flood_until: Mutex::new(None),
+        }
+    }
+
+    #[test]
+    fn parse_flood_wait() {
+        let err = Error::ApiError("FLOOD_WAIT_7".to_string());
+        assert_eq!(flood_wait(&err), Some(Duration::from_secs(7)));
+        assert_eq!(flood_wait(&Error::ApiError("PAGE_NOT_FOUND".to_string())), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rotate_on_flood() {
+        let pool = TelegraphPool {
+            accounts: Arc::new(vec![account("a").await, account("b").await]),
+            current: Arc::new(AtomicUsize::new(0)),
+        };
+        assert_eq!(pool.available().await.name, "a");
+
+        // 当前账号被限流时轮换到下一个账号，并且之后继续使用该账号
+        let err = Error::ApiError("FLOOD_WAIT_10".to_string());
+        pool.accounts[0].check_flood(err).unwrap();
+        assert_eq!(pool.available().await.name, "b");
+        assert_eq!(pool.get("").unwrap().name, "a");
+
+        // 所有账号都被限流时，等待最早解除限流的账号
+        let start = Instant::now();
+        let err = Error::ApiError("FLOOD_WAIT_20".to_string());
+        pool.accounts[1].check_flood(err).unwrap();
+        assert_eq!(pool.available().await.name, "a");
+        assert_eq!(start.elapsed().as_secs(), 10);
+    }
+
+    #[tokio::test]
+    async fn edit_legacy_page() {
+        let main = Arc::new(MockPages::default());
+        let legacy = Arc::new(MockPages::default());
+        let pool = TelegraphPool::mock_accounts(vec![("main", main.clone()), ("", legacy.clone())]);
+
+        // 没有记录账号名称的旧文章使用旧的 token 编辑
+        pool.edit_page("", "old-page", "title", "[]").await.unwrap();
+        assert_eq!(legacy.calls(), ["edit old-page"]);
+        assert!(main.calls().is_empty());
+
+        // 没有配置旧的 token 时使用第一个账号
+        let pool = TelegraphPool::mock_accounts(vec![("main", main.clone())]);
+        pool.edit_page("", "old-page", "title", "[]").await.unwrap();
+        assert_eq!(main.calls(), ["edit old-page"]);
+        assert!(pool.edit_page("other", "old-page", "title", "[]").await.is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use minijinja::{escape_formatter, Environment, Value};
+use regex::Regex;
+use serde::Serialize;
+use teloxide::utils::html::{code_inline, escape, link};
+
+use crate::utils::pad_left;
+
+/// 内置的默认模板
+const DEFAULT_TEMPLATE: &str = include_str!("../../templates/message.j2");
+
+/// 频道消息的模板，使用 jinja2 语法
+///
+/// 模板的输出使用 telegram 的 HTML 格式，变量中的字符串会被自动转义，可以使用以下过滤器和函数：
+///
+/// - `code`：将文本显示为等宽字体
+/// - `pad_left(n)`：在左侧填充空格，使文本的显示宽度至少为 n
+/// - `hashtag`：将标签转换为 telegram 的 hashtag
+/// - `link(url, text)`：生成一个链接
+#[derive(Debug)]
+pub struct MessageTemplate {
+    env: Environment<'static>,
+}
+
+/// 渲染消息时可以使用的变量
+#[derive(Debug, Serialize)]
+pub struct MessageContext {
+    /// 画廊 ID
+    pub id: i32,
+    /// 画廊标题
+    pub title: String,
+    /// 画廊日文标题，没有时与标题相同
+    pub title_jp: String,
+    /// 翻译后的标签，按 namespace 分组
+    pub tags: Vec<TagGroup>,
+    /// 页数
+    pub pages: usize,
+    /// 收藏数量，旧画廊可能为空
+    pub favorite: Option<i32>,
+    /// 发布时间，格式为 YYYY-MM-DD HH:MM，旧画廊可能为空
+    pub posted: Option<String>,
+    /// telegraph 预览的地址
+    pub preview: String,
+    /// catbox 专辑的地址
+    pub albums: Vec<String>,
+    /// 第一个 catbox 专辑的地址
+    pub album: Option<String>,
+    /// 与父画廊相比的页面变化
+    pub diff: Option<String>,
+    /// 最新版本的消息地址
+    pub newer: Option<String>,
+    /// E 站的原始地址
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagGroup {
+    /// 翻译后的 namespace
+    pub namespace: String,
+    /// 原始的 namespace，例如 artist
+    pub raw: String,
+    /// 翻译后的标签
+    pub tags: Vec<String>,
+}
+
+impl MessageTemplate {
+    /// 从文件中读取模板，path 为空时使用内置的默认模板
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let source = match path {
+            Some(path) => {
+                std::fs::read_to_string(path).with_context(|| format!("读取模板失败：{}", path))?
+            }
+            None => DEFAULT_TEMPLATE.to_string(),
+        };
+        Self::new(source)
+    }
+
+    pub fn new(source: String) -> Result<Self> {
+        let mut env = Environment::new();
+        // 未被标记为安全的字符串按照 telegram 的规则转义
+        env.set_formatter(|out, state, value| match value.as_str() {
+            Some(s) if !value.is_safe() => Ok(out.write_str(&escape(s))?),
+            _ => escape_formatter(out, state, value),
+        });
+        env.add_filter("code", |s: String| Value::from_safe_string(code_inline(&s)));
+        env.add_filter("pad_left", |s: String, len: usize| pad_left(&s, len).into_owned());
+        let re = Regex::new("[-/· ]").unwrap();
+        env.add_filter("hashtag", move |s: String| format!("#{}", re.replace_all(&s, "_")));
+        env.add_function("link", |url: String, text: Value| {
+            Value::from_safe_string(link(&url, &text.to_string()))
+        });
+        env.add_template_owned("message", source)?;
+        Ok(Self { env })
+    }
+
+    pub fn render(&self, context: &MessageContext) -> Result<String> {
+        Ok(self.env.get_template("message")?.render(context)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_default() {
+        let template = MessageTemplate::load(None).unwrap();
+        let context = MessageContext {
+            id: 1,
+            title: "<title>".to_string(),
+            title_jp: "<title>".to_string(),
+            tags: vec![TagGroup {
+                namespace: "作者".to_string(),
+                raw: "artist".to_string(),
+                tags: vec!["a b".to_string(), "c".to_string()],
+            }],
+            pages: 10,
+            favorite: None,
+            posted: None,
+            preview: "https://telegra.ph/a".to_string(),
+            albums: vec![
+                "https://catbox.moe/c/a".to_string(),
+                "https://catbox.moe/c/b".to_string(),
+            ],
+            album: Some("https://catbox.moe/c/a".to_string()),
+            diff: None,
+            newer: None,
+            url: "https://exhentai.org/g/1/a/".to_string(),
+        };
+        assert_eq!(
+            template.render(&context).unwrap(),
+            [
+                "<code>  作者</code>: #a_b #c",
+                "<code>  预览</code>: <a href=\"https://telegra.ph/a\">&lt;title&gt;</a>",
+                "<code>  专辑</code>: <a href=\"https://catbox.moe/c/a\">1</a> <a href=\"https://catbox.moe/c/b\">2</a>",
+                "<code>原始地址</code>: https://exhentai.org/g/1/a/",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -1,6 +1,10 @@
 use std::borrow::Cow;
 
+use reqwest::Url;
+use teloxide::types::{ChatId, Message, MessageId, Recipient};
+
 pub mod html;
+pub mod ratelimit;
 
 /// 左填充空格
 pub fn pad_left(s: &str, len: usize) -> Cow<str> {
@@ -11,3 +15,13 @@ pub fn pad_left(s: &str, len: usize) -> Cow<str> {
         Cow::Owned(" ".repeat(len - width) + s)
     }
 }
+
+/// 频道中某条消息的链接
+pub fn url_of(channel: Recipient, id: i32) -> Url {
+    match channel {
+        Recipient::Id(chat_id) => Message::url_of(chat_id, None, MessageId(id)).unwrap(),
+        Recipient::ChannelUsername(username) => {
+            Message::url_of(ChatId(-1000000000000), Some(&username[1..]), MessageId(id)).unwrap()
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::{self, Instant};
+use tracing::warn;
+
+use crate::config::{Limit, RateLimit};
+
+/// 令牌桶限速器，克隆后共享同一个桶
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    /// 每秒补充的令牌数量，为 0 时不限速
+    rate: f64,
+    /// 桶的容量，即允许的最大突发请求数
+    burst: f64,
+    /// 当前令牌数量和上次补充的时间
+    state: Arc<Mutex<(f64, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimit) -> Self {
+        let burst = config.burst.max(1) as f64;
+        Self { rate: config.rate, burst, state: Arc::new(Mutex::new((burst, Instant::now()))) }
+    }
+
+    /// 不限速
+    pub fn unlimited() -> Self {
+        Self::new(&RateLimit { rate: 0.0, burst: 1 })
+    }
+
+    /// 取走一个令牌，令牌不足时等待
+    pub async fn acquire(&self) {
+        if self.rate <= 0.0 {
+            return;
+        }
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let (tokens, last) = &mut *state;
+                let now = Instant::now();
+                *tokens =
+                    (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.burst);
+                *last = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - *tokens) / self.rate)
+            };
+            time::sleep(wait).await;
+        }
+    }
+}
+
+/// 指数退避的重试策略
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// 最大重试次数，不包括第一次尝试
+    retries: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    base: Duration,
+    /// 单次等待时间的上限
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(config: &Limit) -> Self {
+        Self { retries: config.retries, base: config.backoff, max: config.max_backoff }
+    }
+
+    /// 第 n 次重试前需要等待的时间
+    fn delay(&self, n: u32) -> Duration {
+        self.base.saturating_mul(1 << n.min(16)).min(self.max)
+    }
+
+    /// 执行 f，失败时按照退避策略重试，返回最后一次的结果
+    pub async fn retry<T, E, F, Fut>(&self, f: F) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.retry_if(f, |_| true).await
+    }
+
+    /// 执行 f，仅在 predicate 返回 true 时按照退避策略重试，其他错误直接返回
+    pub async fn retry_if<T, E, F, Fut, P>(&self, mut f: F, predicate: P) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        P: Fn(&E) -> bool,
+    {
+        let mut n = 0;
+        loop {
+            match f().await {
+                Err(err) if n < self.retries && predicate(&err) => {
+                    let delay = self.delay(n);
+                    warn!("第 {} 次重试，等待 {:?}：{}", n + 1, delay, err);
+                    time::sleep(delay).await;
+                    n += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limiter() {
+        let limiter = RateLimiter::new(&RateLimit { rate: 2.0, burst: 3 });
+        let start = Instant::now();
+        // 前 3 个请求使用桶中已有的令牌，之后每 0.5 秒一个
+        for _ in 0..7 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed().as_millis(), 2000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff() {
+        let backoff =
+            Backoff { retries: 3, base: Duration::from_secs(1), max: Duration::from_secs(3) };
+        let start = Instant::now();
+        let count = AtomicU32::new(0);
+        let result: Result<(), &str> = backoff
+            .retry(|| async {
+                count.fetch_add(1, Ordering::SeqCst);
+                Err("error")
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+        // 1 + 2 + 3（上限）
+        assert_eq!(start.elapsed().as_secs(), 6);
+    }
+}